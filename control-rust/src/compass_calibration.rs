@@ -0,0 +1,324 @@
+/// Online hard/soft-iron magnetometer calibration, fit from raw samples collected while the
+/// vehicle spins through a full set of headings. Mirrors the approach ArduPilot/INAV's online
+/// compass calibration uses: least-squares fit a general ellipsoid to the samples, then recover
+/// the hard-iron offset (the ellipsoid's center) and a soft-iron correction matrix (from the
+/// eigen-decomposition of the quadratic form) that maps the ellipsoid onto a unit sphere.
+const MIN_SAMPLES: usize = 50;
+
+/// Accumulates raw magnetometer samples while the car spins, to later fit with `fit`.
+pub struct CompassCalibrator {
+    samples: Vec<(f32, f32, f32)>,
+}
+
+impl CompassCalibrator {
+    pub fn new() -> CompassCalibrator {
+        CompassCalibrator { samples: Vec::new() }
+    }
+
+    pub fn add_sample(&mut self, x: f32, y: f32, z: f32) {
+        self.samples.push((x, y, z));
+    }
+
+    /// Fits a `CompassCalibration` to the collected samples, or `None` if too few were
+    /// collected, or if they don't constrain a non-degenerate ellipsoid.
+    pub fn fit(&self) -> Option<CompassCalibration> {
+        if self.samples.len() < MIN_SAMPLES {
+            return None;
+        }
+
+        // Least-squares fit of a*x^2+b*y^2+c*z^2+2(d*xy+e*xz+f*yz)+2(g*x+h*y+i*z) = 1 via the
+        // normal equations (A' * A) * p = A' * ones, where each sample contributes one row
+        // [x^2, y^2, z^2, 2xy, 2xz, 2yz, 2x, 2y, 2z] to A.
+        let mut ata = [[0.0f64; 9]; 9];
+        let mut atb = [0.0f64; 9];
+        for &(x, y, z) in &self.samples {
+            let (x, y, z) = (x as f64, y as f64, z as f64);
+            let row = [
+                x * x, y * y, z * z,
+                2.0 * x * y, 2.0 * x * z, 2.0 * y * z,
+                2.0 * x, 2.0 * y, 2.0 * z,
+            ];
+            for r in 0..9 {
+                atb[r] += row[r];
+                for c in 0..9 {
+                    ata[r][c] += row[r] * row[c];
+                }
+            }
+        }
+
+        let p = match solve9(&ata, &atb) {
+            Some(p) => p,
+            None => return None,
+        };
+        let (a, b, c, d, e, f, g, h, i) = (p[0], p[1], p[2], p[3], p[4], p[5], p[6], p[7], p[8]);
+
+        // Q is the quadratic form's matrix; the ellipsoid's center satisfies Q * center = -[g,h,i].
+        let q = [[a, d, e], [d, b, f], [e, f, c]];
+        let center = match solve3(&q, &[-g, -h, -i]) {
+            Some(center) => center,
+            None => return None,
+        };
+
+        // Every sample satisfies p' * Q * p + 2 * [g,h,i] . p = 1 and [g,h,i] = -Q * center, so
+        // (p - center)' * Q * (p - center) = 1 + center' * Q * center = k.
+        let k = 1.0 - (g * center[0] + h * center[1] + i * center[2]);
+        if k == 0.0 {
+            return None;
+        }
+
+        // A soft-iron matrix that maps the ellipsoid onto a unit sphere: M = V * diag(sqrt(
+        // eigenvalue / k)) * V', built from Q's eigen-decomposition Q = V * diag(eigenvalue) * V'.
+        let (eigenvalues, eigenvectors) = jacobi_eigen_symmetric_3x3(&q);
+        if eigenvalues.iter().any(|&value| value / k <= 0.0) {
+            return None;
+        }
+        let mut matrix = [[0.0f32; 3]; 3];
+        for row in 0..3 {
+            for column in 0..3 {
+                let mut sum = 0.0f64;
+                for axis in 0..3 {
+                    let scale = (eigenvalues[axis] / k).sqrt();
+                    sum += eigenvectors[row][axis] * scale * eigenvectors[column][axis];
+                }
+                matrix[row][column] = sum as f32;
+            }
+        }
+
+        Some(CompassCalibration {
+            offset: (center[0] as f32, center[1] as f32, center[2] as f32),
+            matrix: matrix,
+        })
+    }
+}
+
+/// The hard-iron offset and soft-iron correction matrix fit by `CompassCalibrator::fit`.
+#[derive(Clone, Copy)]
+pub struct CompassCalibration {
+    offset: (f32, f32, f32),
+    matrix: [[f32; 3]; 3],
+}
+
+impl CompassCalibration {
+    /// Applies the hard/soft-iron correction to a raw magnetometer reading.
+    pub fn apply(&self, x: f32, y: f32, z: f32) -> (f32, f32, f32) {
+        let centered = (x - self.offset.0, y - self.offset.1, z - self.offset.2);
+        (
+            self.matrix[0][0] * centered.0 + self.matrix[0][1] * centered.1 + self.matrix[0][2] * centered.2,
+            self.matrix[1][0] * centered.0 + self.matrix[1][1] * centered.1 + self.matrix[1][2] * centered.2,
+            self.matrix[2][0] * centered.0 + self.matrix[2][1] * centered.1 + self.matrix[2][2] * centered.2,
+        )
+    }
+}
+
+/// Solves the 9x9 system `a * x = b` via Gauss-Jordan elimination with partial pivoting,
+/// returning `None` if `a` is singular.
+fn solve9(a: &[[f64; 9]; 9], b: &[f64; 9]) -> Option<[f64; 9]> {
+    let mut work = *a;
+    let mut result = *b;
+    for pivot in 0..9 {
+        let mut pivot_row = pivot;
+        let mut pivot_value = work[pivot][pivot].abs();
+        for row in (pivot + 1)..9 {
+            if work[row][pivot].abs() > pivot_value {
+                pivot_row = row;
+                pivot_value = work[row][pivot].abs();
+            }
+        }
+        if pivot_value < 1e-9 {
+            return None;
+        }
+        if pivot_row != pivot {
+            work.swap(pivot, pivot_row);
+            result.swap(pivot, pivot_row);
+        }
+        let scale = work[pivot][pivot];
+        for column in 0..9 {
+            work[pivot][column] /= scale;
+        }
+        result[pivot] /= scale;
+        for row in 0..9 {
+            if row == pivot {
+                continue;
+            }
+            let factor = work[row][pivot];
+            if factor == 0.0 {
+                continue;
+            }
+            for column in 0..9 {
+                work[row][column] -= factor * work[pivot][column];
+            }
+            result[row] -= factor * result[pivot];
+        }
+    }
+    Some(result)
+}
+
+/// Solves the 3x3 system `a * x = b` via Gauss-Jordan elimination with partial pivoting,
+/// returning `None` if `a` is singular.
+fn solve3(a: &[[f64; 3]; 3], b: &[f64; 3]) -> Option<[f64; 3]> {
+    let mut work = *a;
+    let mut result = *b;
+    for pivot in 0..3 {
+        let mut pivot_row = pivot;
+        let mut pivot_value = work[pivot][pivot].abs();
+        for row in (pivot + 1)..3 {
+            if work[row][pivot].abs() > pivot_value {
+                pivot_row = row;
+                pivot_value = work[row][pivot].abs();
+            }
+        }
+        if pivot_value < 1e-9 {
+            return None;
+        }
+        if pivot_row != pivot {
+            work.swap(pivot, pivot_row);
+            result.swap(pivot, pivot_row);
+        }
+        let scale = work[pivot][pivot];
+        for column in 0..3 {
+            work[pivot][column] /= scale;
+        }
+        result[pivot] /= scale;
+        for row in 0..3 {
+            if row == pivot {
+                continue;
+            }
+            let factor = work[row][pivot];
+            if factor == 0.0 {
+                continue;
+            }
+            for column in 0..3 {
+                work[row][column] -= factor * work[pivot][column];
+            }
+            result[row] -= factor * result[pivot];
+        }
+    }
+    Some(result)
+}
+
+/// Returns the eigenvalues and eigenvectors (as columns of the second result) of a symmetric
+/// 3x3 matrix via the classical Jacobi eigenvalue algorithm: repeatedly rotate away the largest
+/// off-diagonal element until the matrix is (numerically) diagonal.
+fn jacobi_eigen_symmetric_3x3(a: &[[f64; 3]; 3]) -> ([f64; 3], [[f64; 3]; 3]) {
+    let mut matrix = *a;
+    let mut eigenvectors = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+    let off_diagonal_pairs = [(0usize, 1usize), (0usize, 2usize), (1usize, 2usize)];
+
+    for _sweep in 0..100 {
+        let (mut p, mut q) = off_diagonal_pairs[0];
+        let mut largest = matrix[p][q].abs();
+        for &(row, column) in &off_diagonal_pairs[1..] {
+            if matrix[row][column].abs() > largest {
+                largest = matrix[row][column].abs();
+                p = row;
+                q = column;
+            }
+        }
+        if largest < 1e-12 {
+            break;
+        }
+
+        let theta = (matrix[q][q] - matrix[p][p]) / (2.0 * matrix[p][q]);
+        let t = if theta == 0.0 {
+            1.0
+        } else {
+            theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt())
+        };
+        let c = 1.0 / (t * t + 1.0).sqrt();
+        let s = t * c;
+
+        let apq = matrix[p][q];
+        matrix[p][p] -= t * apq;
+        matrix[q][q] += t * apq;
+        matrix[p][q] = 0.0;
+        matrix[q][p] = 0.0;
+        let r = 3 - p - q; // the one index that isn't p or q
+        let arp = matrix[r][p];
+        let arq = matrix[r][q];
+        matrix[r][p] = c * arp - s * arq;
+        matrix[p][r] = matrix[r][p];
+        matrix[r][q] = s * arp + c * arq;
+        matrix[q][r] = matrix[r][q];
+
+        for row in 0..3 {
+            let vip = eigenvectors[row][p];
+            let viq = eigenvectors[row][q];
+            eigenvectors[row][p] = c * vip - s * viq;
+            eigenvectors[row][q] = s * vip + c * viq;
+        }
+    }
+
+    ([matrix[0][0], matrix[1][1], matrix[2][2]], eigenvectors)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+
+    use super::CompassCalibrator;
+
+    /// Generates samples on a unit sphere, distorted by a known hard-iron offset and a diagonal
+    /// soft-iron scale, the way a real magnetometer would report a spin through a full set of
+    /// headings and tilts near a source of local interference.
+    fn distorted_sphere_samples(offset: (f32, f32, f32), scale: (f32, f32, f32)) -> Vec<(f32, f32, f32)> {
+        let steps = 20;
+        let mut samples = Vec::new();
+        for i in 0..steps {
+            for j in 0..steps {
+                let theta = (i as f32) / (steps as f32) * 2.0 * PI;
+                let phi = (j as f32) / (steps as f32) * PI;
+                let x = phi.sin() * theta.cos();
+                let y = phi.sin() * theta.sin();
+                let z = phi.cos();
+                samples.push((
+                    x * scale.0 + offset.0,
+                    y * scale.1 + offset.1,
+                    z * scale.2 + offset.2,
+                ));
+            }
+        }
+        samples
+    }
+
+    #[test]
+    fn test_recovers_hard_iron_offset() {
+        let offset = (10.0f32, -5.0f32, 3.0f32);
+        let scale = (2.0f32, 1.0f32, 0.5f32);
+        let mut calibrator = CompassCalibrator::new();
+        for &(x, y, z) in &distorted_sphere_samples(offset, scale) {
+            calibrator.add_sample(x, y, z);
+        }
+
+        let calibration = calibrator.fit().expect("Expected a calibration fit");
+        assert!((calibration.offset.0 - offset.0).abs() < 0.1);
+        assert!((calibration.offset.1 - offset.1).abs() < 0.1);
+        assert!((calibration.offset.2 - offset.2).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_correction_maps_samples_onto_unit_sphere() {
+        let offset = (10.0f32, -5.0f32, 3.0f32);
+        let scale = (2.0f32, 1.0f32, 0.5f32);
+        let samples = distorted_sphere_samples(offset, scale);
+        let mut calibrator = CompassCalibrator::new();
+        for &(x, y, z) in &samples {
+            calibrator.add_sample(x, y, z);
+        }
+
+        let calibration = calibrator.fit().expect("Expected a calibration fit");
+        for &(x, y, z) in &samples {
+            let (cx, cy, cz) = calibration.apply(x, y, z);
+            let magnitude = (cx * cx + cy * cy + cz * cz).sqrt();
+            assert!((magnitude - 1.0).abs() < 0.05);
+        }
+    }
+
+    #[test]
+    fn test_too_few_samples_fails_to_fit() {
+        let mut calibrator = CompassCalibrator::new();
+        calibrator.add_sample(1.0, 0.0, 0.0);
+        calibrator.add_sample(0.0, 1.0, 0.0);
+        assert!(calibrator.fit().is_none());
+    }
+}
@@ -0,0 +1,117 @@
+//! Venue-specific settings loaded from a JSON file passed via `--config`: the vehicle's
+//! starting pose, GPS/compass noise priors, the driver/command socket paths, the MQTT broker,
+//! and the maximum throttle. Retuning for a new venue is then a matter of editing the config
+//! file rather than recompiling.
+
+extern crate serde_json;
+
+use std::fs::File;
+use std::io::Read;
+
+use mixer::{Control, MixerChannel};
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub start_latitude: f64,
+    pub start_longitude: f64,
+    pub start_heading_d: f32,
+    pub gps_std_dev_m: f32,
+    pub compass_std_dev_d: f32,
+    pub driver_socket_path: String,
+    pub command_socket_path: String,
+    pub mqtt_broker: Option<String>,
+    pub max_throttle: f32,
+    /// How many binary accelerometer/magnetometer frames the SUP800F reports for every GPS fix.
+    pub binary_frames_per_gps_fix: i32,
+    /// If true, a `Start` command issued after the course has already been completed restarts
+    /// from the first waypoint; if false, a completed course stays done until the car is
+    /// restarted. Venues that cycle the same course repeatedly want this on.
+    pub restart_on_start: bool,
+    /// Maximum rate, in throttle units per second, that `Control` may change the commanded
+    /// throttle away from zero. Keeps state transitions (e.g. into `CollisionRecovery`) from
+    /// snapping the drivetrain to full scale.
+    pub max_throttle_delta_per_s: f32,
+    /// Maximum rate, in steering units per second, that `Control` may change the commanded
+    /// steering.
+    pub max_steering_delta_per_s: f32,
+    /// Milliseconds over which a throttle-to-zero ("stop") command ramps down instead of
+    /// snapping to 0.0. Zero preserves the old instantaneous stop.
+    pub stop_duration_ms: u64,
+    /// Half-width, in meters, of the corridor around the current waypoint-to-waypoint segment
+    /// within which normal pure-pursuit steering applies. Beyond it, steering is overridden to
+    /// correct the drift; see `Control`'s cross-track guard.
+    pub cross_track_corridor_m: f32,
+    /// Cross-track distance, in meters, beyond which the car is considered off course entirely
+    /// and recovers as though it had collided.
+    pub max_cross_track_m: f32,
+    /// If true, the car heads for whichever unvisited waypoint is geographically nearest instead
+    /// of following the routed order. Courses that are a scattered set of targets rather than a
+    /// single loop want this on.
+    pub nearest_first_waypoints: bool,
+    /// The actuator channels a `Mixer`-based driver (e.g. `PiBlasterDriver`) maps throttle and
+    /// steering onto. Retuning a servo or adding a channel is then a config edit, not a rebuild.
+    pub mixer_channels: Vec<MixerChannel>,
+}
+
+impl Default for Config {
+    /**
+     * Matches the hardcoded values this module replaced: the Boulder Solid State Depot
+     * reference point, a 315 degree starting heading, and the original socket paths.
+     */
+    fn default() -> Config {
+        Config {
+            start_latitude: 40.0941804,
+            start_longitude: -105.1872092,
+            start_heading_d: 315.0,
+            gps_std_dev_m: 2.0,
+            compass_std_dev_d: 0.0,
+            driver_socket_path: "/tmp/driver-socket".to_string(),
+            command_socket_path: "/tmp/command-socket".to_string(),
+            mqtt_broker: None,
+            max_throttle: 1.0,
+            binary_frames_per_gps_fix: 3,
+            restart_on_start: true,
+            max_throttle_delta_per_s: 4.0,
+            max_steering_delta_per_s: 8.0,
+            stop_duration_ms: 400,
+            cross_track_corridor_m: 2.0,
+            max_cross_track_m: 6.0,
+            nearest_first_waypoints: false,
+            mixer_channels: vec![
+                MixerChannel {
+                    source: Control::Throttle,
+                    pin: 18,
+                    neutral_us: 1500.0,
+                    min_us: 1000.0,
+                    max_us: 2000.0,
+                    deadband: 0.0,
+                    scale: 1.0,
+                    reverse: false,
+                },
+                MixerChannel {
+                    source: Control::Steering,
+                    pin: 4,
+                    neutral_us: 1650.0,
+                    min_us: 1350.0,
+                    max_us: 1950.0,
+                    deadband: 0.0,
+                    scale: 1.0,
+                    reverse: false,
+                },
+            ],
+        }
+    }
+}
+
+/**
+ * Loads a `Config` from a JSON file at `file_name`.
+ */
+pub fn load(file_name: &str) -> Result<Config, String> {
+    let mut contents = String::new();
+    File::open(file_name)
+        .map_err(|e| format!("Unable to open config file \"{}\": {}", file_name, e))?
+        .read_to_string(&mut contents)
+        .map_err(|e| format!("Unable to read config file \"{}\": {}", file_name, e))?;
+    serde_json::from_str(&contents)
+        .map_err(|e| format!("Unable to parse config file \"{}\": {}", file_name, e))
+}
@@ -5,18 +5,53 @@ use std::time::Duration;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use driver::{Driver, Percentage};
-use telemetry::{difference_d, distance, is_turn_left, relative_degrees, Degrees, TelemetryState};
-use telemetry_message::CommandMessage;
+use telemetry::{
+    cross_track_distance, difference_d, distance, is_turn_left, relative_degrees, Degrees, Meter,
+    MetersPerSecond, Point, TelemetryState,
+};
+use telemetry_message::{CommandMessage, TelemetryMessage};
 use waypoint_generator::WaypointGenerator;
 
 type MilliSeconds = u64;
 
+// The main loop's tick period; command rates passed in units/second are converted to a
+// per-tick delta against this.
+const LOOP_MS: MilliSeconds = 50;
+
+// Pure-pursuit steering parameters; see `pure_pursuit_steering`.
+const MIN_LOOKAHEAD_M: Meter = 1.0;
+const MAX_LOOKAHEAD_M: Meter = 15.0;
+const WHEELBASE_M: Meter = 0.33; // Estimated wheelbase of the RC chassis
+const MAX_STEERING_ANGLE_D: Degrees = 30.0;
+
+// Collision recovery phase durations; see `collision_recovery`.
+const STOP_MS: MilliSeconds = 500;
+const ARM_PULSE_MS: MilliSeconds = 200;
+const ARM_NEUTRAL_MS: MilliSeconds = 200;
+const BACK_UP_MS: MilliSeconds = 1000;
+const PAUSE_MS: MilliSeconds = 500;
+const RECOVERY_THROTTLE: Percentage = -0.5;
+const RECOVERY_STEERING_MAGNITUDE: Percentage = 0.5;
+
+// Stuck detection: a car wedged, high-centered, or spinning its wheels never trips
+// `state.stopped`, so we also watch for many consecutive ticks of near-zero ground speed.
+const STUCK_SPEED: MetersPerSecond = 0.1;
+const MAX_SLOW_SPEED_TICKS: u32 = 40; // 40 ticks * 50ms/tick == 2 seconds
+
+// Cross-track guard: each degree of corrective steering applied per meter of drift from the
+// line between the previous and current waypoints, on top of the usual heading-error term. See
+// `cross_track_correction_steering`.
+const CROSS_TRACK_STEERING_D_PER_M: Degrees = 10.0;
+
 #[derive(PartialEq)]
 enum ControlState {
     CalibrateCompass,
     WaitingForStart,
     Running,
     CollisionRecovery,
+    // Holds position (neutral throttle, zero steering) without advancing the current waypoint,
+    // so `Resume` can continue the course from where it left off.
+    Paused,
 }
 
 pub struct Control {
@@ -24,26 +59,80 @@ pub struct Control {
     run: bool,
     collision_time_ms: MilliSeconds,
     calibrate_time_ms: MilliSeconds,
+    // The steering to apply while reversing out of the current collision, chosen once when the
+    // collision is first detected so the whole recovery sequence steers toward the same side.
+    collision_recovery_steering: Percentage,
+    // Consecutive ticks the car has reported a ground speed below STUCK_SPEED.
+    slow_speed_ticks: u32,
+    // If true, a `Start` after the course is done restarts from the first waypoint instead of
+    // leaving the car idle.
+    restart_on_start: bool,
+    // The throttle/steering most recently sent to the driver, so `drive` can clamp the next
+    // command to a maximum change per cycle instead of jumping straight to the new value.
+    last_throttle: Percentage,
+    last_steering: Percentage,
+    max_throttle_delta_per_cycle: Percentage,
+    max_steering_delta_per_cycle: Percentage,
+    // Maximum per-cycle throttle change allowed specifically when ramping toward zero, derived
+    // from the configured stop duration so braking can be tuned independently of acceleration.
+    stop_delta_per_cycle: Percentage,
+    // The waypoint the car most recently departed, i.e. the start of the segment it's currently
+    // following. `None` until the first waypoint is reached, since there's no prior segment to
+    // measure drift against on the leg from the starting position to the first waypoint.
+    previous_waypoint: Option<Point>,
+    // Half-width of the corridor around the current segment within which normal pure-pursuit
+    // steering applies; beyond it, steering is overridden to correct the drift.
+    cross_track_corridor_m: Meter,
+    // Cross-track distance beyond which the car is considered off course entirely, triggering
+    // the same recovery as a collision.
+    max_cross_track_m: Meter,
     request_telemetry_tx: Sender<()>,
     telemetry_rx: Receiver<TelemetryState>,
+    telemetry_message_tx: Sender<TelemetryMessage>,
     waypoint_generator: Box<WaypointGenerator>,
     driver: Box<Driver>,
 }
 
 impl Control {
     pub fn new(
+        restart_on_start: bool,
+        max_throttle_delta_per_s: Percentage,
+        max_steering_delta_per_s: Percentage,
+        stop_duration_ms: MilliSeconds,
+        cross_track_corridor_m: Meter,
+        max_cross_track_m: Meter,
         request_telemetry_tx: Sender<()>,
         telemetry_rx: Receiver<TelemetryState>,
+        telemetry_message_tx: Sender<TelemetryMessage>,
         waypoint_generator: Box<WaypointGenerator>,
         driver: Box<Driver>,
     ) -> Control {
+        let cycles_per_second = 1000.0 / LOOP_MS as f32;
         Control {
             state: ControlState::WaitingForStart,
             run: false,
             collision_time_ms: 0,
             calibrate_time_ms: 0,
+            collision_recovery_steering: 0.0,
+            slow_speed_ticks: 0,
+            restart_on_start: restart_on_start,
+            last_throttle: 0.0,
+            last_steering: 0.0,
+            max_throttle_delta_per_cycle: max_throttle_delta_per_s / cycles_per_second,
+            max_steering_delta_per_cycle: max_steering_delta_per_s / cycles_per_second,
+            stop_delta_per_cycle: if stop_duration_ms == 0 {
+                // A zero duration means "instantaneous": a delta larger than the full
+                // [-1.0, 1.0] range always reaches the target in a single cycle.
+                2.0
+            } else {
+                1.0 / (stop_duration_ms as f32 / LOOP_MS as f32)
+            },
+            previous_waypoint: None,
+            cross_track_corridor_m: cross_track_corridor_m,
+            max_cross_track_m: max_cross_track_m,
             telemetry_rx: telemetry_rx,
             request_telemetry_tx: request_telemetry_tx,
+            telemetry_message_tx: telemetry_message_tx,
             waypoint_generator: waypoint_generator,
             driver: driver,
         }
@@ -72,17 +161,47 @@ impl Control {
                             self.calibrate_time_ms = SystemTime::now().to_milliseconds();
                             self.state = ControlState::CalibrateCompass;
                             self.run = true;
+                            self.slow_speed_ticks = 0;
+                            match self.telemetry_message_tx.send(TelemetryMessage::StartCompassCalibration) {
+                                Ok(_) => (),
+                                Err(e) => warn!("Unable to start compass calibration: {}", e),
+                            }
+                        }
+                    }
+                    CommandMessage::Start => {
+                        if self.waypoint_generator.done() && self.restart_on_start {
+                            self.waypoint_generator.reset();
+                        }
+                        self.run = true;
+                    }
+                    CommandMessage::Stop => {
+                        self.run = false;
+                        self.slow_speed_ticks = 0;
+                    }
+                    CommandMessage::Pause => {
+                        if self.state != ControlState::Running
+                            && self.state != ControlState::CollisionRecovery
+                        {
+                            warn!("Tried to pause while not running, ignoring");
+                        } else {
+                            self.state = ControlState::Paused;
+                        }
+                    }
+                    CommandMessage::Resume => {
+                        if self.state != ControlState::Paused {
+                            warn!("Tried to resume while not paused, ignoring");
+                        } else {
+                            self.state = ControlState::Running;
+                            self.slow_speed_ticks = 0;
                         }
                     }
-                    CommandMessage::Start => self.run = true,
-                    CommandMessage::Stop => self.run = false,
                 }
             }
 
             if !self.run_incremental() {
                 return;
             }
-            thread::sleep(Duration::from_millis(50));
+            thread::sleep(Duration::from_millis(LOOP_MS));
         }
     }
 
@@ -107,13 +226,40 @@ impl Control {
             }
         }
 
+        if state.speed < STUCK_SPEED {
+            self.slow_speed_ticks += 1;
+        } else {
+            self.slow_speed_ticks = 0;
+        }
+
+        // How far the car has drifted from the segment between the last waypoint it passed and
+        // the one it's currently chasing; `None` before the first waypoint has been reached, or
+        // once the course is done, since there's no segment to measure against.
+        let cross_track_m = if self.state == ControlState::Running && !self.waypoint_generator.done() {
+            self.previous_waypoint.map(|previous_waypoint| {
+                let waypoint = self
+                    .waypoint_generator
+                    .get_current_raw_waypoint(&state.location);
+                cross_track_distance(&previous_waypoint, &waypoint, &state.location)
+            })
+        } else {
+            None
+        };
+
         // Halting the car supercedes all other states
         if !self.run {
             self.state = ControlState::WaitingForStart;
-        } else if state.stopped {
+            self.slow_speed_ticks = 0;
+        } else if self.state != ControlState::Paused
+            && (state.stopped
+                || self.slow_speed_ticks > MAX_SLOW_SPEED_TICKS
+                || cross_track_m.map_or(false, |m| m.abs() > self.max_cross_track_m))
+        {
             // We want to drive for at least one second between collisions
             self.collision_time_ms = SystemTime::now().to_milliseconds();
             self.state = ControlState::CollisionRecovery;
+            self.slow_speed_ticks = 0;
+            self.begin_collision_recovery(&state);
         }
 
         if self.waypoint_generator.done() {
@@ -122,7 +268,7 @@ impl Control {
 
         match self.state {
             ControlState::WaitingForStart => self.waiting_for_start(),
-            ControlState::Running => self.running(&state),
+            ControlState::Running => self.running(&state, cross_track_m),
             ControlState::CollisionRecovery => {
                 let now_ms = SystemTime::now().to_milliseconds();
                 self.collision_recovery(now_ms);
@@ -131,6 +277,7 @@ impl Control {
                 let now_ms = SystemTime::now().to_milliseconds();
                 self.calibrate_compass(now_ms);
             }
+            ControlState::Paused => self.paused(),
         }
 
         // Everything's perfectly all right now. We're fine. We're all fine here now, thank you.
@@ -145,8 +292,12 @@ impl Control {
         self.state = ControlState::Running;
     }
 
-    fn running(&mut self, state: &TelemetryState) {
+    fn running(&mut self, state: &TelemetryState, cross_track_m: Option<Meter>) {
         while self.waypoint_generator.reached(&state.location) {
+            self.previous_waypoint = Some(
+                self.waypoint_generator
+                    .get_current_raw_waypoint(&state.location),
+            );
             self.waypoint_generator.next();
             if self.waypoint_generator.done() {
                 return;
@@ -164,55 +315,60 @@ impl Control {
             0.5
         };
 
-        let goal_heading: Degrees = relative_degrees(&state.location, &waypoint);
-
-        // We want to stay in the heading range of the waypoint +- 1/2 of the waypoint reached
-        // distance diameter
-        let mut range: Degrees = 2.0
-            * (self.waypoint_generator.reach_distance() / distance_m)
-                .atan()
-                .to_degrees();
-        // Range should never be > 90.0; otherwise, we would have already reached the waypoint.
-        if range < 5.0 {
-            range = 5.0;
-        }
+        let steering = match cross_track_m {
+            Some(cross_track_m) if cross_track_m.abs() > self.cross_track_corridor_m => {
+                cross_track_correction_steering(state, &waypoint, cross_track_m)
+            }
+            _ => pure_pursuit_steering(state, &waypoint, distance_m),
+        };
 
+        self.drive(throttle, steering);
+    }
+
+    /**
+     * Captures which way to steer while reversing out of a collision, based on the heading to
+     * the current waypoint at the moment of collision. `is_turn_left` tells us which way would
+     * turn the car toward the waypoint while driving forward, but reversing swings the rear the
+     * opposite way a forward turn would, so we steer with the opposite sign. If the waypoint is
+     * nearly straight ahead or straight behind, `is_turn_left` has no real preference either way,
+     * so a side is picked at random instead of silently favoring one direction.
+     */
+    fn begin_collision_recovery(&mut self, state: &TelemetryState) {
+        let waypoint = self.waypoint_generator.get_current_raw_waypoint(&state.location);
+        let goal_heading = relative_degrees(&state.location, &waypoint);
         let difference = difference_d(state.heading, goal_heading);
-        // TODO: We should keep turning until we exactly hit the heading, rather than continually
-        // adjusting as we get inside or outside of the range
-        let steering_magnitude: f32 = if difference < range {
-            0.0
-        } else if difference < 15.0 {
-            0.25
-        } else if difference < 30.0 {
-            0.5
-        } else if difference < 45.0 || throttle > 0.5 {
-            0.75
+        let forward_turn_left = if difference < 1.0 || difference > 179.0 {
+            let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().subsec_nanos();
+            nanos % 2 == 0
         } else {
-            1.0
+            is_turn_left(state.heading, goal_heading)
         };
-
-        let steering: f32 = if is_turn_left(state.heading, goal_heading) {
-            -steering_magnitude
+        self.collision_recovery_steering = if forward_turn_left {
+            -RECOVERY_STEERING_MAGNITUDE
         } else {
-            steering_magnitude
+            RECOVERY_STEERING_MAGNITUDE
         };
-
-        self.drive(throttle, steering);
     }
 
     fn collision_recovery(&mut self, now_ms: MilliSeconds) {
-        // Stop the motor for .5 seconds, then back up for 1 second, then pause
-        // for .5 seconds
-        let stop_ms = 500 as MilliSeconds;
-        let back_up_ms = 1000 as MilliSeconds;
-        let pause_ms = 500 as MilliSeconds;
-        if now_ms < self.collision_time_ms + stop_ms {
+        // Stop the motor, then pulse reverse briefly and return to neutral so the ESC actually
+        // arms its reverse gear instead of braking, then reverse for real, then pause before
+        // resuming.
+        let stop_end_ms = self.collision_time_ms + STOP_MS;
+        let arm_pulse_end_ms = stop_end_ms + ARM_PULSE_MS;
+        let arm_neutral_end_ms = arm_pulse_end_ms + ARM_NEUTRAL_MS;
+        let back_up_end_ms = arm_neutral_end_ms + BACK_UP_MS;
+        let pause_end_ms = back_up_end_ms + PAUSE_MS;
+
+        if now_ms < stop_end_ms {
+            self.drive(0.0f32, 0.0f32);
+        } else if now_ms < arm_pulse_end_ms {
+            self.drive(RECOVERY_THROTTLE, 0.0f32);
+        } else if now_ms < arm_neutral_end_ms {
             self.drive(0.0f32, 0.0f32);
-        } else if now_ms < self.collision_time_ms + stop_ms + back_up_ms {
-            // TODO Choose a random direction
-            self.drive(-0.5f32, -0.5f32);
-        } else if now_ms < self.collision_time_ms + stop_ms + back_up_ms + pause_ms {
+        } else if now_ms < back_up_end_ms {
+            self.drive(RECOVERY_THROTTLE, self.collision_recovery_steering);
+        } else if now_ms < pause_end_ms {
             self.drive(0.0f32, 0.0f32);
         } else {
             self.state = ControlState::Running;
@@ -226,14 +382,99 @@ impl Control {
         } else {
             self.state = ControlState::WaitingForStart;
             self.run = false;
+            match self.telemetry_message_tx.send(TelemetryMessage::FinishCompassCalibration) {
+                Ok(_) => (),
+                Err(e) => warn!("Unable to finish compass calibration: {}", e),
+            }
         }
     }
 
+    /**
+     * Holds position while paused: neutral throttle and steering, waypoint index untouched.
+     */
+    fn paused(&mut self) {
+        self.drive(0.0f32, 0.0f32);
+    }
+
+    /**
+     * Shapes the commanded throttle/steering so every state transition produces a continuous
+     * trajectory instead of an instantaneous jump: steering (and throttle away from zero) is
+     * clamped to `max_{throttle,steering}_delta_per_cycle`, while throttle requests of exactly
+     * 0.0 -- i.e. asking the car to stop -- ramp down at `stop_delta_per_cycle` instead, so
+     * braking can be tuned independently of acceleration.
+     */
     fn drive(&mut self, throttle_percentage: Percentage, steering_percentage: Percentage) {
-        self.driver.drive(throttle_percentage, steering_percentage);
+        let throttle_delta_per_cycle = if throttle_percentage == 0.0 {
+            self.stop_delta_per_cycle
+        } else {
+            self.max_throttle_delta_per_cycle
+        };
+        let throttle = ramp(self.last_throttle, throttle_percentage, throttle_delta_per_cycle);
+        let steering = ramp(
+            self.last_steering,
+            steering_percentage,
+            self.max_steering_delta_per_cycle,
+        );
+        self.last_throttle = throttle;
+        self.last_steering = steering;
+        self.driver.drive(throttle, steering);
     }
 }
 
+/**
+ * Moves `current` toward `target` by at most `max_delta`, in either direction.
+ */
+fn ramp(current: Percentage, target: Percentage, max_delta: Percentage) -> Percentage {
+    let delta = target - current;
+    if delta > max_delta {
+        current + max_delta
+    } else if delta < -max_delta {
+        current - max_delta
+    } else {
+        target
+    }
+}
+
+/**
+ * Pure-pursuit steering: treats `waypoint` as the lookahead point at distance `distance_m`
+ * (clamped to [MIN_LOOKAHEAD_M, MAX_LOOKAHEAD_M]), fits the arc of constant curvature that
+ * passes through it given the vehicle's current heading, and converts that curvature to a
+ * front-wheel steering angle for a car with wheelbase `WHEELBASE_M`, normalized by
+ * `MAX_STEERING_ANGLE_D` into a `Percentage`. Unlike a staircase of heading-difference
+ * thresholds, this steers continuously and tightens naturally as the car nears the waypoint.
+ */
+fn pure_pursuit_steering(state: &TelemetryState, waypoint: &Point, distance_m: Meter) -> Percentage {
+    let lookahead_m = distance_m.max(MIN_LOOKAHEAD_M).min(MAX_LOOKAHEAD_M);
+    let goal_heading = relative_degrees(&state.location, waypoint);
+    let alpha_d = if is_turn_left(state.heading, goal_heading) {
+        -difference_d(state.heading, goal_heading)
+    } else {
+        difference_d(state.heading, goal_heading)
+    };
+    let kappa = 2.0 * alpha_d.to_radians().sin() / lookahead_m;
+    let delta_d = (WHEELBASE_M * kappa).atan().to_degrees();
+    (delta_d / MAX_STEERING_ANGLE_D).max(-1.0).min(1.0)
+}
+
+/**
+ * Cross-track guard steering: corrects both the heading error toward `waypoint` (as in
+ * `pure_pursuit_steering`) and the car's lateral drift from the segment it's supposed to be
+ * following. `cross_track_m` is positive when the car is to the right of the segment, which
+ * needs a corrective turn to the left (negative steering), and negative when to the left, which
+ * needs a turn to the right; this is on top of, not instead of, the usual heading-error term, so
+ * the car both rejoins the line and keeps pointing at the waypoint.
+ */
+fn cross_track_correction_steering(state: &TelemetryState, waypoint: &Point, cross_track_m: Meter) -> Percentage {
+    let goal_heading = relative_degrees(&state.location, waypoint);
+    let heading_error_d = if is_turn_left(state.heading, goal_heading) {
+        -difference_d(state.heading, goal_heading)
+    } else {
+        difference_d(state.heading, goal_heading)
+    };
+    let delta_d = heading_error_d - cross_track_m * CROSS_TRACK_STEERING_D_PER_M;
+    (delta_d / MAX_STEERING_ANGLE_D).max(-1.0).min(1.0)
+}
+
 trait ToMilliseconds {
     fn to_milliseconds(&self) -> MilliSeconds;
 }
@@ -279,6 +520,9 @@ mod tests {
         fn reach_distance(&self) -> Meters {
             1.0
         }
+        fn reset(&mut self) {
+            self.done = false;
+        }
     }
 
     struct DummyDriver {
@@ -302,6 +546,7 @@ mod tests {
     fn test_collision_recovery() {
         let (telemetry_tx, telemetry_rx) = channel();
         let (telemetry_2_tx, telemetry_2_rx) = channel();
+        let (telemetry_message_tx, _telemetry_message_rx) = channel();
 
         // Fake telemetry end point returns test data
         spawn(move || {
@@ -321,7 +566,19 @@ mod tests {
             steering: 0.0,
         });
 
-        let mut control = Control::new(telemetry_tx, telemetry_2_rx, waypoint_generator, driver);
+        let mut control = Control::new(
+            true,
+            2.0,
+            2.0,
+            0,
+            1000.0,
+            1000.0,
+            telemetry_tx,
+            telemetry_2_rx,
+            telemetry_message_tx,
+            waypoint_generator,
+            driver,
+        );
         control.state = ControlState::Running;
         control.run = true;
         control.run_incremental();
@@ -337,27 +594,35 @@ mod tests {
         assert!(control.driver.get_throttle() == 0.0 as Percentage);
         assert!(control.driver.get_steering() == 0.0 as Percentage);
 
+        // Brief reverse pulse to arm the ESC; no turn yet
         control.collision_recovery(now + 600);
         assert!(control.state == ControlState::CollisionRecovery);
         assert!(control.driver.get_throttle() < 0.0 as Percentage);
-        assert!(control.driver.get_steering() != 0.0 as Percentage);
+        assert!(control.driver.get_steering() == 0.0 as Percentage);
+
+        // Neutral again while the ESC registers the end of the pulse
+        control.collision_recovery(now + 800);
+        assert!(control.state == ControlState::CollisionRecovery);
+        assert!(control.driver.get_throttle() == 0.0 as Percentage);
+        assert!(control.driver.get_steering() == 0.0 as Percentage);
 
+        // Actual reverse, steered toward the waypoint
         control.collision_recovery(now + 1400);
         assert!(control.state == ControlState::CollisionRecovery);
         assert!(control.driver.get_throttle() < 0.0 as Percentage);
         assert!(control.driver.get_steering() != 0.0 as Percentage);
 
-        control.collision_recovery(now + 1600);
+        control.collision_recovery(now + 2000);
         assert!(control.state == ControlState::CollisionRecovery);
         assert!(control.driver.get_throttle() == 0.0 as Percentage);
         assert!(control.driver.get_steering() == 0.0 as Percentage);
 
-        control.collision_recovery(now + 1900);
+        control.collision_recovery(now + 2300);
         assert!(control.state == ControlState::CollisionRecovery);
         assert!(control.driver.get_throttle() == 0.0 as Percentage);
         assert!(control.driver.get_steering() == 0.0 as Percentage);
 
-        control.collision_recovery(now + 2100);
+        control.collision_recovery(now + 2500);
         assert!(control.state != ControlState::CollisionRecovery);
     }
 }
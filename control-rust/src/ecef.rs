@@ -0,0 +1,169 @@
+/**
+ * WGS84 geodetic <-> ECEF conversions, and the local East-North-Up frame at an observer used to
+ * compute the elevation and azimuth of a satellite from its ECEF position. This lets us
+ * cross-check the receiver-reported GSV elevation/azimuth against ephemeris-derived positions.
+ */
+use telemetry::Degrees;
+
+/// WGS84 semi-major axis, in meters.
+const WGS84_A_M: f64 = 6378137.0;
+/// WGS84 flattening.
+const WGS84_F: f64 = 1.0 / 298.257223563;
+
+/**
+ * A position in WGS84 geodetic coordinates.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct GeodeticPoint {
+    pub latitude_degrees: f64,
+    pub longitude_degrees: f64,
+    pub altitude_m: f32,
+}
+
+/**
+ * A position in Earth-Centered, Earth-Fixed XYZ coordinates.
+ */
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct EcefPoint {
+    pub x_m: f64,
+    pub y_m: f64,
+    pub z_m: f64,
+}
+
+/**
+ * Converts a WGS84 geodetic position to Earth-Centered, Earth-Fixed XYZ.
+ */
+pub fn geodetic_to_ecef(point: &GeodeticPoint) -> EcefPoint {
+    let eccentricity_squared = WGS84_F * (2.0 - WGS84_F);
+    let latitude_r = point.latitude_degrees.to_radians();
+    let longitude_r = point.longitude_degrees.to_radians();
+    let altitude_m = point.altitude_m as f64;
+
+    let sine_latitude = latitude_r.sin();
+    let prime_vertical_radius_m =
+        WGS84_A_M / (1.0 - eccentricity_squared * sine_latitude * sine_latitude).sqrt();
+
+    EcefPoint {
+        x_m: (prime_vertical_radius_m + altitude_m) * latitude_r.cos() * longitude_r.cos(),
+        y_m: (prime_vertical_radius_m + altitude_m) * latitude_r.cos() * longitude_r.sin(),
+        z_m: (prime_vertical_radius_m * (1.0 - eccentricity_squared) + altitude_m) * sine_latitude,
+    }
+}
+
+/**
+ * Computes the elevation and azimuth of `satellite`, given in ECEF coordinates, as seen from
+ * `observer`. Elevation is measured up from the observer's local horizon (using the observer's
+ * own ECEF vector as the up reference), and azimuth is measured clockwise from true north in the
+ * observer's local East-North-Up frame.
+ */
+pub fn sky_position(observer: &GeodeticPoint, satellite: &EcefPoint) -> (Degrees, Degrees) {
+    let our = geodetic_to_ecef(observer);
+    let dx = EcefPoint {
+        x_m: satellite.x_m - our.x_m,
+        y_m: satellite.y_m - our.y_m,
+        z_m: satellite.z_m - our.z_m,
+    };
+
+    let our_magnitude = (our.x_m * our.x_m + our.y_m * our.y_m + our.z_m * our.z_m).sqrt();
+    let dx_magnitude = (dx.x_m * dx.x_m + dx.y_m * dx.y_m + dx.z_m * dx.z_m).sqrt();
+    let our_dot_dx = our.x_m * dx.x_m + our.y_m * dx.y_m + our.z_m * dx.z_m;
+    let elevation_degrees =
+        90.0 - (our_dot_dx / (our_magnitude * dx_magnitude)).acos().to_degrees();
+
+    // Local tangent-plane basis at the observer: north points along the meridian, east is
+    // perpendicular to it in the equatorial plane.
+    let north_x = -our.z_m * our.x_m;
+    let north_y = -our.z_m * our.y_m;
+    let north_z = our.x_m * our.x_m + our.y_m * our.y_m;
+    let north_magnitude = (north_x * north_x + north_y * north_y + north_z * north_z).sqrt();
+    let north_dot_dx = north_x * dx.x_m + north_y * dx.y_m + north_z * dx.z_m;
+
+    let east_x = -our.y_m;
+    let east_y = our.x_m;
+    let east_magnitude = (east_x * east_x + east_y * east_y).sqrt();
+    let east_dot_dx = east_x * dx.x_m + east_y * dx.y_m;
+
+    let azicos = north_dot_dx / (north_magnitude * dx_magnitude);
+    let azisin = east_dot_dx / (east_magnitude * dx_magnitude);
+    let mut azimuth_degrees = azisin.atan2(azicos).to_degrees();
+    if azimuth_degrees < 0.0 {
+        azimuth_degrees += 360.0;
+    }
+
+    (elevation_degrees as Degrees, azimuth_degrees as Degrees)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{geodetic_to_ecef, sky_position, EcefPoint, GeodeticPoint};
+
+    fn assert_approx_eq(value_1: f64, value_2: f64, tolerance: f64) {
+        assert!((value_1 - value_2).abs() < tolerance);
+    }
+
+    #[test]
+    fn test_geodetic_to_ecef_equator_prime_meridian() {
+        let point = GeodeticPoint {
+            latitude_degrees: 0.0,
+            longitude_degrees: 0.0,
+            altitude_m: 0.0,
+        };
+        let ecef = geodetic_to_ecef(&point);
+        assert_approx_eq(ecef.x_m, 6378137.0, 0.001);
+        assert_approx_eq(ecef.y_m, 0.0, 0.001);
+        assert_approx_eq(ecef.z_m, 0.0, 0.001);
+    }
+
+    #[test]
+    fn test_geodetic_to_ecef_north_pole() {
+        let point = GeodeticPoint {
+            latitude_degrees: 90.0,
+            longitude_degrees: 0.0,
+            altitude_m: 0.0,
+        };
+        let ecef = geodetic_to_ecef(&point);
+        assert_approx_eq(ecef.x_m, 0.0, 0.001);
+        assert_approx_eq(ecef.y_m, 0.0, 0.001);
+        // The polar radius, b = a * (1 - f)
+        assert_approx_eq(ecef.z_m, 6356752.314245, 0.001);
+    }
+
+    #[test]
+    fn test_sky_position_directly_overhead() {
+        let observer = GeodeticPoint {
+            latitude_degrees: 40.0,
+            longitude_degrees: -105.0,
+            altitude_m: 1600.0,
+        };
+        let our = geodetic_to_ecef(&observer);
+        let our_magnitude = (our.x_m * our.x_m + our.y_m * our.y_m + our.z_m * our.z_m).sqrt();
+        let scale = (our_magnitude + 20_200_000.0) / our_magnitude;
+        let satellite = EcefPoint {
+            x_m: our.x_m * scale,
+            y_m: our.y_m * scale,
+            z_m: our.z_m * scale,
+        };
+
+        let (elevation_degrees, _azimuth_degrees) = sky_position(&observer, &satellite);
+        assert_approx_eq(elevation_degrees as f64, 90.0, 0.01);
+    }
+
+    #[test]
+    fn test_sky_position_due_north_on_horizon() {
+        let observer = GeodeticPoint {
+            latitude_degrees: 0.0,
+            longitude_degrees: 0.0,
+            altitude_m: 0.0,
+        };
+        // A point further up the same meridian, far enough away to stay near the horizon.
+        let satellite = GeodeticPoint {
+            latitude_degrees: 1.0,
+            longitude_degrees: 0.0,
+            altitude_m: 0.0,
+        };
+        let satellite_ecef = geodetic_to_ecef(&satellite);
+
+        let (_elevation_degrees, azimuth_degrees) = sky_position(&observer, &satellite_ecef);
+        assert_approx_eq(azimuth_degrees as f64, 0.0, 1.0);
+    }
+}
@@ -1,11 +1,17 @@
 extern crate log;
 use std::sync::mpsc::{Receiver, Sender};
 use std::thread;
+use std::time::SystemTime;
 
+use compass_calibration::{CompassCalibration, CompassCalibrator};
 use location_filter::LocationFilter;
 use telemetry::{Telemetry, Point, TelemetryState};
-use telemetry_message::{AccelerometerMessage, CompassMessage, GpsMessage, TelemetryMessage};
+use telemetry_message::{AccelerometerMessage, CompassMessage, GpsMessage, ImuMessage, TelemetryMessage};
 
+// Once the magnetometer is calibrated, it's no longer subject to the local interference the
+// uncalibrated prior (`compass_std_dev_d`) was guarding against, so the filter can trust it more
+// than an uncalibrated reading: this must stay below the configured `compass_std_dev_d`.
+const CALIBRATED_COMPASS_STD_DEV_D: f32 = 1.0;
 
 #[allow(dead_code)]
 pub struct FilteredTelemetry {
@@ -14,40 +20,79 @@ pub struct FilteredTelemetry {
     accelerometer_message: Box<AccelerometerMessage>,
     gps_message: Box<GpsMessage>,
     compass_message: Box<CompassMessage>,
+    // The venue-configured noise prior for an uncalibrated compass reading; used until a
+    // calibration is fit, since the raw message's own `std_dev` is just a placeholder (see
+    // `sup800f::parse_binary_sensor`).
+    compass_std_dev_d: f32,
+    // Added to the tilt-compensated compass heading to account for the difference between
+    // magnetic and true north at the vehicle's location.
+    magnetic_declination_d: f32,
+    // Collects raw magnetometer samples between `StartCompassCalibration` and
+    // `FinishCompassCalibration`; `None` outside of that window.
+    compass_calibrator: Option<CompassCalibrator>,
+    // The hard/soft-iron correction from the most recent successful calibration, if any.
+    compass_calibration: Option<CompassCalibration>,
+    last_compass_update: SystemTime,
+    last_imu_update: SystemTime,
     state: TelemetryState,
     filter: LocationFilter,
 }
 
 
 impl FilteredTelemetry {
-    pub fn new() -> FilteredTelemetry {
+    /**
+     * `start` and `start_heading_d` are the vehicle's starting pose, and `gps_std_dev_m`/
+     * `compass_std_dev_d` are the noise priors assumed for sensor readings before the filter has
+     * seen any real data; all four normally come from the venue's `config::Config`.
+     */
+    pub fn new(
+        start: Point,
+        start_heading_d: f32,
+        gps_std_dev_m: f32,
+        compass_std_dev_d: f32,
+    ) -> FilteredTelemetry {
         FilteredTelemetry {
             throttle: 0.0,
             steering: 0.0,
             gps_message: Box::new(GpsMessage {
-                point: Point {x: 0.0, y: 0.0 },
-                heading: 0.0,
+                point: start,
+                heading: start_heading_d,
                 speed: 0.0,
-                std_dev_x: 2.0,
-                std_dev_y: 2.0,
+                std_dev_x: gps_std_dev_m,
+                std_dev_y: gps_std_dev_m,
             }),
-            compass_message: Box::new(CompassMessage { heading: 0.0, std_dev: 0.0 }),
-            accelerometer_message: Box::new(AccelerometerMessage { x: 0.0, y: 0.0, z: 0.0 }),
+            compass_message: Box::new(CompassMessage {
+                heading: start_heading_d,
+                std_dev: compass_std_dev_d,
+                magnetic_x: 0.0,
+                magnetic_y: 0.0,
+                magnetic_z: 0.0,
+            }),
+            accelerometer_message: Box::new(AccelerometerMessage { x: 0.0, y: 0.0, z: 1.0 }),
+            compass_std_dev_d: compass_std_dev_d,
+            // TODO: Make this configurable per venue.
+            magnetic_declination_d: 0.0,
+            compass_calibrator: None,
+            compass_calibration: None,
+            last_compass_update: SystemTime::now(),
+            last_imu_update: SystemTime::now(),
             state: TelemetryState {
-                location: Point { x: 0.0, y: 0.0 },
-                heading: 0.0,
+                location: start,
+                heading: start_heading_d,
                 speed: 0.0,
                 stopped: false},
-            // TODO: Fill in the starting values of the Sparkfun AVC. These placeholders aren't a
-            // huge deal because the filter should zero in quickly after a few readings.
-            filter: LocationFilter::new(50.0, 50.0, 315.0),
+            filter: LocationFilter::new(start.x, start.y, start_heading_d),
         }
     }
 
+    /**
+     * `telemetry_requesters` is a list of (request, response) channel pairs, one per consumer
+     * that wants to poll for `TelemetryState` (e.g. the control thread and the MQTT bridge);
+     * each is served independently so any number of consumers can subscribe.
+     */
     pub fn run(
         &mut self,
-        request_telemetry_rx: Receiver<()>,
-        telemetry_tx: Sender<TelemetryState>,
+        telemetry_requesters: Vec<(Receiver<()>, Sender<TelemetryState>)>,
         telemetry_message_rx: Receiver<TelemetryMessage>,
         quit_rx: Receiver<()>
     ) {
@@ -62,19 +107,18 @@ impl FilteredTelemetry {
 
             let mut processed = false;
 
-            while let Ok(_) = request_telemetry_rx.try_recv() {
-                match telemetry_tx.send(self.state) {
-                    Ok(_) => (),
-                    Err(e) => {
-                        error!("Unable to send telemetry: {}", e);
-                        return;
+            for &(ref request_telemetry_rx, ref telemetry_tx) in &telemetry_requesters {
+                while let Ok(_) = request_telemetry_rx.try_recv() {
+                    match telemetry_tx.send(self.state) {
+                        Ok(_) => (),
+                        Err(e) => error!("Unable to send telemetry: {}", e),
                     }
+                    processed = true;
                 }
-                processed = true;
             }
 
             while let Ok(message) = telemetry_message_rx.try_recv() {
-                // TODO: Process the message
+                self.handle_message(&message);
                 processed = true;
             };
 
@@ -137,8 +181,105 @@ impl Telemetry for FilteredTelemetry {
                     gps_message.speed);
             },
             &TelemetryMessage::Compass(ref compass_message) => {
+                if let Some(ref mut calibrator) = self.compass_calibrator {
+                    calibrator.add_sample(
+                        compass_message.magnetic_x,
+                        compass_message.magnetic_y,
+                        compass_message.magnetic_z,
+                    );
+                }
+                let (magnetic_x, magnetic_y, magnetic_z) = match self.compass_calibration {
+                    Some(ref calibration) => calibration.apply(
+                        compass_message.magnetic_x,
+                        compass_message.magnetic_y,
+                        compass_message.magnetic_z,
+                    ),
+                    None => (
+                        compass_message.magnetic_x,
+                        compass_message.magnetic_y,
+                        compass_message.magnetic_z,
+                    ),
+                };
+
+                // Tilt-compensate the heading using the most recently observed
+                // accelerometer reading, since the two arrive as separate messages. The reading
+                // is in g and only reads (0,0,1) at rest, so under hard acceleration/braking/
+                // cornering its magnitude drifts away from 1 and the raw axes can push `asin`
+                // out of its [-1, 1] domain; normalize by the vector's magnitude first so pitch
+                // and roll are always defined.
+                let acceleration_x = self.accelerometer_message.x;
+                let acceleration_y = self.accelerometer_message.y;
+                let acceleration_z = self.accelerometer_message.z;
+                let acceleration_magnitude = (acceleration_x * acceleration_x
+                    + acceleration_y * acceleration_y
+                    + acceleration_z * acceleration_z)
+                    .sqrt();
+                let (acceleration_x, acceleration_y) = if acceleration_magnitude > 0.0 {
+                    (acceleration_x / acceleration_magnitude, acceleration_y / acceleration_magnitude)
+                } else {
+                    (acceleration_x, acceleration_y)
+                };
+                let pitch = (-acceleration_x).max(-1.0).min(1.0).asin();
+                let roll = if pitch.cos() > 0.0 {
+                    (acceleration_y / pitch.cos()).max(-1.0).min(1.0).asin()
+                } else {
+                    0.0
+                };
+                let x_h = magnetic_x * pitch.cos() + magnetic_z * pitch.sin();
+                let y_h = magnetic_x * roll.sin() * pitch.sin()
+                    + magnetic_y * roll.cos()
+                    - magnetic_z * roll.sin() * pitch.cos();
+                let mut heading = (-y_h).atan2(x_h).to_degrees() + self.magnetic_declination_d;
+                while heading < 0.0 {
+                    heading += 360.0;
+                }
+                while heading >= 360.0 {
+                    heading -= 360.0;
+                }
+
+                let std_dev = match self.compass_calibration {
+                    Some(_) => CALIBRATED_COMPASS_STD_DEV_D,
+                    None => self.compass_std_dev_d,
+                };
+                self.compass_message.heading = heading;
+                self.compass_message.std_dev = std_dev;
+
+                let time_diff_s = match SystemTime::now().duration_since(self.last_compass_update) {
+                    Ok(duration) => duration.as_secs() as f32 + duration.subsec_nanos() as f32 / 1_000_000_000.0,
+                    Err(_) => 0.0,
+                };
+                self.last_compass_update = SystemTime::now();
+                if !self.filter.update_compass(heading, std_dev, time_diff_s) {
+                    warn!("Rejected implausible compass reading: {}", heading);
+                }
             },
             &TelemetryMessage::Accelerometer(ref accelerometer_message) => {
+                self.accelerometer_message = Box::new(AccelerometerMessage {
+                    x: accelerometer_message.x,
+                    y: accelerometer_message.y,
+                    z: accelerometer_message.z,
+                });
+            },
+            &TelemetryMessage::Imu(ref imu_message) => {
+                let time_diff_s = match SystemTime::now().duration_since(self.last_imu_update) {
+                    Ok(duration) => duration.as_secs() as f32 + duration.subsec_nanos() as f32 / 1_000_000_000.0,
+                    Err(_) => 0.0,
+                };
+                self.last_imu_update = SystemTime::now();
+                if !self.filter.update_imu(imu_message.yaw_rate_d_s, imu_message.accel_m_s2, time_diff_s) {
+                    warn!("Rejected implausible IMU reading");
+                }
+            },
+            &TelemetryMessage::StartCompassCalibration => {
+                self.compass_calibrator = Some(CompassCalibrator::new());
+            },
+            &TelemetryMessage::FinishCompassCalibration => {
+                if let Some(calibrator) = self.compass_calibrator.take() {
+                    match calibrator.fit() {
+                        Some(calibration) => self.compass_calibration = Some(calibration),
+                        None => warn!("Compass calibration failed: not enough spread in the samples"),
+                    }
+                }
             },
         }
     }
@@ -0,0 +1,177 @@
+/**
+ * A 2D k-d tree over `Point`s, as used by XCSoar's waypoint tree, supporting nearest-neighbor
+ * queries and (lazy) removal. Built once from a fixed set of points; removal just marks a node
+ * so future queries skip it, rather than rebalancing the tree.
+ */
+
+use std::cmp::Ordering;
+
+use telemetry::{Meter, Point, distance};
+
+
+pub struct KdTree {
+    nodes: Vec<Node>,
+    root: Option<usize>,
+}
+
+struct Node {
+    point: Point,
+    index: usize,
+    left: Option<usize>,
+    right: Option<usize>,
+    removed: bool,
+}
+
+impl KdTree {
+    /**
+     * Builds a balanced tree over `points`. Each point's position in `points` is its index, used
+     * by `nearest` and `remove` to identify it.
+     */
+    pub fn new(points: Vec<Point>) -> KdTree {
+        let mut nodes: Vec<Node> = points.into_iter()
+            .enumerate()
+            .map(|(index, point)| Node {
+                point: point,
+                index: index,
+                left: None,
+                right: None,
+                removed: false,
+            })
+            .collect();
+        let mut indices: Vec<usize> = (0..nodes.len()).collect();
+        let root = KdTree::build(&mut nodes, &mut indices, 0);
+        KdTree { nodes: nodes, root: root }
+    }
+
+    fn build(nodes: &mut Vec<Node>, indices: &mut [usize], depth: usize) -> Option<usize> {
+        if indices.is_empty() {
+            return None;
+        }
+        let axis = depth % 2;
+        indices.sort_by(|&a, &b| {
+            let value_a = if axis == 0 { nodes[a].point.x } else { nodes[a].point.y };
+            let value_b = if axis == 0 { nodes[b].point.x } else { nodes[b].point.y };
+            value_a.partial_cmp(&value_b).unwrap_or(Ordering::Equal)
+        });
+
+        let mid = indices.len() / 2;
+        let node_index = indices[mid];
+        let (left_indices, rest) = indices.split_at_mut(mid);
+        let right_indices = &mut rest[1..];
+
+        let left = KdTree::build(nodes, left_indices, depth + 1);
+        let right = KdTree::build(nodes, right_indices, depth + 1);
+        nodes[node_index].left = left;
+        nodes[node_index].right = right;
+        Some(node_index)
+    }
+
+    /**
+     * Returns the index and point of the nearest not-yet-removed node to `point`, or `None` if
+     * every node has been removed.
+     */
+    pub fn nearest(&self, point: &Point) -> Option<(usize, Point)> {
+        let mut best: Option<(usize, Meter)> = None;
+        self.nearest_from(self.root, point, 0, &mut best);
+        best.map(|(node_index, _)| (self.nodes[node_index].index, self.nodes[node_index].point))
+    }
+
+    fn nearest_from(
+        &self,
+        node: Option<usize>,
+        point: &Point,
+        depth: usize,
+        best: &mut Option<(usize, Meter)>,
+    ) {
+        let node_index = match node {
+            Some(node_index) => node_index,
+            None => return,
+        };
+        let current = &self.nodes[node_index];
+
+        if !current.removed {
+            let distance_m = distance(&current.point, point);
+            if best.map_or(true, |(_, best_distance_m)| distance_m < best_distance_m) {
+                *best = Some((node_index, distance_m));
+            }
+        }
+
+        let axis = depth % 2;
+        let (point_value, node_value) = if axis == 0 {
+            (point.x, current.point.x)
+        } else {
+            (point.y, current.point.y)
+        };
+        let (near, far) = if point_value < node_value {
+            (current.left, current.right)
+        } else {
+            (current.right, current.left)
+        };
+
+        self.nearest_from(near, point, depth + 1, best);
+
+        // Only the far side can hold a point closer than what we've already found if it's closer
+        // than our best distance along just this axis.
+        let axis_distance_m = (point_value - node_value).abs();
+        if best.map_or(true, |(_, best_distance_m)| axis_distance_m < best_distance_m) {
+            self.nearest_from(far, point, depth + 1, best);
+        }
+    }
+
+    /**
+     * Marks the node at `index` as removed, so future `nearest` calls skip it.
+     */
+    pub fn remove(&mut self, index: usize) {
+        if let Some(node) = self.nodes.iter_mut().find(|node| node.index == index) {
+            node.removed = true;
+        }
+    }
+
+    /**
+     * Returns true if every node has been removed.
+     */
+    pub fn is_empty(&self) -> bool {
+        self.nodes.iter().all(|node| node.removed)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::KdTree;
+    use telemetry::Point;
+
+    #[test]
+    fn test_nearest() {
+        let tree = KdTree::new(vec![
+            Point { x: 0.0, y: 0.0 },
+            Point { x: 10.0, y: 10.0 },
+            Point { x: -10.0, y: -10.0 },
+            Point { x: 5.0, y: 5.0 },
+        ]);
+        let (index, point) = tree.nearest(&Point { x: 4.0, y: 4.0 }).unwrap();
+        assert!(index == 3);
+        assert!(point.x == 5.0 && point.y == 5.0);
+    }
+
+    #[test]
+    fn test_remove_excludes_point() {
+        let mut tree = KdTree::new(vec![
+            Point { x: 0.0, y: 0.0 },
+            Point { x: 5.0, y: 5.0 },
+        ]);
+        tree.remove(1);
+        let (index, point) = tree.nearest(&Point { x: 4.0, y: 4.0 }).unwrap();
+        assert!(index == 0);
+        assert!(point.x == 0.0 && point.y == 0.0);
+    }
+
+    #[test]
+    fn test_is_empty() {
+        let mut tree = KdTree::new(vec![Point { x: 0.0, y: 0.0 }]);
+        assert!(!tree.is_empty());
+        tree.remove(0);
+        assert!(tree.is_empty());
+        assert!(tree.nearest(&Point { x: 1.0, y: 1.0 }).is_none());
+    }
+}
@@ -1,234 +1,554 @@
-use libc::consts::os::posix88::ENOENT;
-use std::fs::{File, PathExt, remove_dir_all};
-use std::io::{BufRead, BufReader};
+extern crate xml;
+
+use std::cell::Cell;
+use std::fs::{self, File};
+use std::io;
+use std::io::{BufReader, Read, Write};
 use std::path::Path;
 use std::process::Command;
 
+use xml::reader::{EventReader, XmlEvent};
+
+use kd_tree::KdTree;
 use telemetry::{Meter, Point, distance, latitude_longitude_to_point};
 use waypoint_generator::WaypointGenerator;
+use waypoint_graph::WaypointGraph;
+
+/// Radius, in meters, within which two waypoints are auto-linked when no saved link file is
+/// loaded.
+const DEFAULT_LINK_RADIUS_M: Meter = 50.0;
+
+/// Version of the `.links` sidecar file format this module reads and writes. A loaded file whose
+/// `// VERSION` header is older than this is rejected so links are regenerated from scratch
+/// instead of being trusted.
+const WAYPOINT_VERSION: u32 = 1;
+
+/// Beyond this distance from the current waypoint, an approach isn't being tracked at all; see
+/// `KmlWaypointGenerator::reached`.
+const APPROACH_RADIUS_M: Meter = 3.0;
+
+
+/**
+ * Errors produced while loading waypoints from a KML/KMZ course file.
+ */
+#[derive(PartialEq, Debug)]
+pub enum KmlError {
+    /// The KMZ couldn't be unzipped, or the extracted doc.kml wasn't well-formed XML.
+    DocumentNotParsed(String),
+    /// The document parsed fine, but no `<coordinates>` or `<gx:coord>` element held any usable
+    /// waypoints.
+    NoCoordinatesFound,
+    /// The document had no elements at all.
+    EmptyDocument,
+}
+
+
+/**
+ * How `KmlWaypointGenerator` picks the next waypoint to head for.
+ */
+#[derive(PartialEq, Debug)]
+pub enum VisitOrder {
+    /// Follow the routed waypoints in order, today's behavior.
+    Sequential,
+    /// Always head for whichever unvisited routed waypoint is geographically closest.
+    NearestFirst,
+}
 
 
 /**
- * Loads and returns waypoints from a KML file.
+ * Loads waypoints from a KML file into a `WaypointGraph` and routes through them with A*, so
+ * the course isn't locked into one linear order.
  */
 #[allow(dead_code)]
 pub struct KmlWaypointGenerator {
-    waypoints: Vec<Point>,
+    graph: WaypointGraph,
+    route: Vec<Point>,
     current_waypoint: usize,
+    visit_order: VisitOrder,
+    /// Remaining routed waypoints, keyed by their position in `route`. Only populated in
+    /// `NearestFirst` mode.
+    unvisited: Option<KdTree>,
+    /// The waypoint `NearestFirst` last returned from `get_current_waypoint`, cleared by `next`.
+    /// Cached so that the waypoint `reached()` confirmed is the same one `next()` removes.
+    current_target: Cell<Option<(usize, Point)>>,
+    /// The smallest distance to the current waypoint seen so far on this approach, used by
+    /// `reached()` to detect that we've just passed the closest point. Cleared by `next`.
+    min_distance_m: Cell<Option<Meter>>,
 }
 
 
 impl KmlWaypointGenerator {
     /**
-     * Loads waypoints from a KML path file.
+     * Loads waypoints from a KML path file. Walks every `<Placemark>`'s `<LineString>`/`<Point>`
+     * coordinates in document order, and also understands `gx:Track` blocks, whose `<gx:coord>`
+     * elements hold one waypoint each (their paired `<when>` timestamps aren't needed here,
+     * since only the order of waypoints matters).
      */
-    pub fn new(kml_file_name: &str) -> KmlWaypointGenerator {
-        let xml_file = KmlWaypointGenerator::extract_doc_kml(kml_file_name);
-        let waypoints_line = KmlWaypointGenerator::extract_waypoints_line(xml_file);
-        let points = KmlWaypointGenerator::parse_waypoints_line(&waypoints_line[..]);
-        KmlWaypointGenerator::new_from_waypoints(points)
+    pub fn new(
+        kml_file_name: &str,
+        visit_order: VisitOrder,
+    ) -> Result<KmlWaypointGenerator, KmlError> {
+        let xml_file = KmlWaypointGenerator::extract_doc_kml(kml_file_name)?;
+        let waypoints = KmlWaypointGenerator::parse_waypoints(xml_file)?;
+        let mut generator = KmlWaypointGenerator::new_from_waypoints(waypoints);
+        generator.set_visit_order(visit_order);
+
+        let links_file_name = Path::new(kml_file_name).with_extension("links");
+        if links_file_name.is_file() {
+            if let Some(links_file_name) = links_file_name.to_str() {
+                if let Err(e) = generator.load_links(links_file_name) {
+                    warn!("Ignoring invalid link file \"{}\": {:?}", links_file_name, e);
+                }
+            }
+        }
+
+        Ok(generator)
     }
 
     /**
-     * For testing.
+     * Builds the graph from parsed waypoints, linking ones within `DEFAULT_LINK_RADIUS_M` of
+     * each other, and routes from the first waypoint to the last. Defaults to `Sequential`.
      */
     fn new_from_waypoints(waypoints: Vec<Point>) -> KmlWaypointGenerator {
-        KmlWaypointGenerator {
-            waypoints: waypoints,
+        let graph = WaypointGraph::new(waypoints, DEFAULT_LINK_RADIUS_M);
+        let mut generator = KmlWaypointGenerator {
+            graph: graph,
+            route: Vec::new(),
             current_waypoint: 0,
+            visit_order: VisitOrder::Sequential,
+            unvisited: None,
+            current_target: Cell::new(None),
+            min_distance_m: Cell::new(None),
+        };
+        generator.recompute_route();
+        generator
+    }
+
+    /**
+     * Switches to `visit_order`, rebuilding the `NearestFirst` k-d tree over the current route
+     * if needed.
+     */
+    pub fn set_visit_order(&mut self, visit_order: VisitOrder) {
+        self.unvisited = match visit_order {
+            VisitOrder::NearestFirst => Some(KdTree::new(self.route.clone())),
+            VisitOrder::Sequential => None,
+        };
+        self.current_target.set(None);
+        self.min_distance_m.set(None);
+        self.visit_order = visit_order;
+    }
+
+    /**
+     * Recomputes the route from `point` to `goal`, e.g. after the graph's links change or a
+     * waypoint becomes unreachable. Leaves the existing route in place if no path is found.
+     */
+    #[allow(dead_code)]
+    pub fn reroute(&mut self, point: &Point, goal: usize) {
+        if let Some(route) = self.graph.route(point, goal) {
+            self.route = route;
+            self.after_route_changed();
         }
     }
 
+    /**
+     * Rebuilds the graph from `waypoints` and returns the route from `point` to the last
+     * waypoint, in one stateless call, mirroring route_snapper's `calculateRoute`. Lets a course
+     * be re-planned at runtime, e.g. from a telemetry command, without losing the generator's
+     * other state.
+     */
+    #[allow(dead_code)]
+    pub fn recompute(&mut self, waypoints: Vec<Point>, point: &Point) -> Vec<Point> {
+        if waypoints.is_empty() {
+            return self.route.clone();
+        }
+        self.graph = WaypointGraph::new(waypoints, DEFAULT_LINK_RADIUS_M);
+        let goal = self.graph.len() - 1;
+        self.route = self.graph.route(point, goal)
+            .unwrap_or_else(|| (0..self.graph.len()).map(|i| self.graph.node(i)).collect());
+        self.after_route_changed();
+        self.route.clone()
+    }
+
+    /**
+     * Inserts `point` as waypoint `index`, rebuilding the graph's radius links and re-routing.
+     */
+    #[allow(dead_code)]
+    pub fn insert_waypoint(&mut self, index: usize, point: Point) {
+        let mut nodes: Vec<Point> = (0..self.graph.len()).map(|i| self.graph.node(i)).collect();
+        nodes.insert(index, point);
+        self.rebuild_graph_and_route(nodes);
+    }
+
+    /**
+     * Removes waypoint `index`, rebuilding the graph's radius links and re-routing.
+     */
+    #[allow(dead_code)]
+    pub fn remove_waypoint(&mut self, index: usize) {
+        let mut nodes: Vec<Point> = (0..self.graph.len()).map(|i| self.graph.node(i)).collect();
+        nodes.remove(index);
+        self.rebuild_graph_and_route(nodes);
+    }
+
+    /**
+     * Re-parses `kml_file_name` and replaces the current graph and route with the result,
+     * without loading a `.links` sidecar or disturbing `visit_order`.
+     */
+    #[allow(dead_code)]
+    pub fn reload_from_kml(&mut self, kml_file_name: &str) -> Result<(), KmlError> {
+        let xml_file = KmlWaypointGenerator::extract_doc_kml(kml_file_name)?;
+        let waypoints = KmlWaypointGenerator::parse_waypoints(xml_file)?;
+        self.rebuild_graph_and_route(waypoints);
+        Ok(())
+    }
+
+    /**
+     * Shared by `insert_waypoint`/`remove_waypoint`/`reload_from_kml`: re-links `nodes` within
+     * `DEFAULT_LINK_RADIUS_M` and re-routes from the first waypoint to the last. Leaves the
+     * generator untouched if `nodes` is empty.
+     */
+    fn rebuild_graph_and_route(&mut self, nodes: Vec<Point>) {
+        if nodes.is_empty() {
+            return;
+        }
+        self.graph = WaypointGraph::new(nodes, DEFAULT_LINK_RADIUS_M);
+        self.recompute_route();
+    }
+
+    /**
+     * Routes from the first waypoint to the last over the current graph, falling back to the
+     * graph's own node order if the nodes aren't fully linked.
+     */
+    fn recompute_route(&mut self) {
+        let start = self.graph.node(0);
+        let goal = self.graph.len() - 1;
+        self.route = self.graph.route(&start, goal)
+            .unwrap_or_else(|| (0..self.graph.len()).map(|i| self.graph.node(i)).collect());
+        self.after_route_changed();
+    }
+
+    /**
+     * Resets traversal state after `self.route` is replaced: restarts `Sequential` from the
+     * first waypoint, and rebuilds the `NearestFirst` k-d tree over the new route.
+     */
+    fn after_route_changed(&mut self) {
+        self.current_waypoint = 0;
+        if let VisitOrder::NearestFirst = self.visit_order {
+            self.unvisited = Some(KdTree::new(self.route.clone()));
+        }
+        self.current_target.set(None);
+        self.min_distance_m.set(None);
+    }
+
+    /**
+     * Loads a saved link graph from `links_file_name`, following the Xonotic-style sidecar
+     * format: leading `//`-prefixed comment lines, one of which must be `// VERSION N`, followed
+     * by one `from_index to_index` pair per line. Rejects the file (leaving the existing graph
+     * untouched) if it's malformed or `N` is older than `WAYPOINT_VERSION`, so a stale file is
+     * regenerated instead of trusted.
+     */
+    pub fn load_links(&mut self, links_file_name: &str) -> Result<(), KmlError> {
+        let mut contents = String::new();
+        File::open(links_file_name)
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .map_err(|e| KmlError::DocumentNotParsed(format!("Couldn't read link file: {}", e)))?;
+
+        let mut version = None;
+        let mut graph = WaypointGraph::with_no_links(
+            (0..self.graph.len()).map(|i| self.graph.node(i)).collect());
+
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with("//") {
+                let comment = line.trim_start_matches("//").trim();
+                if comment.starts_with("VERSION") {
+                    version = comment.splitn(2, ' ').nth(1).and_then(|s| s.trim().parse().ok());
+                }
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+            let from = fields.next().and_then(|s| s.parse::<usize>().ok());
+            let to = fields.next().and_then(|s| s.parse::<usize>().ok());
+            match (from, to) {
+                (Some(from), Some(to)) => graph.add_link(from, to),
+                _ => {
+                    return Err(KmlError::DocumentNotParsed(
+                        format!("Malformed link line: \"{}\"", line)));
+                }
+            }
+        }
+
+        match version {
+            Some(version) if version >= WAYPOINT_VERSION => {
+                self.graph = graph;
+                self.recompute_route();
+                Ok(())
+            }
+            Some(version) => Err(KmlError::DocumentNotParsed(
+                format!("Link file version {} is older than {}", version, WAYPOINT_VERSION))),
+            None => Err(KmlError::DocumentNotParsed(
+                "Link file missing \"// VERSION\" header".to_string())),
+        }
+    }
+
+    /**
+     * Saves the current link graph to `links_file_name` in the format `load_links` reads.
+     */
+    pub fn save_links(&self, links_file_name: &str) -> Result<(), KmlError> {
+        let mut contents = format!("// Waypoint links\n// VERSION {}\n", WAYPOINT_VERSION);
+        for from in 0..self.graph.len() {
+            for &to in self.graph.links_from(from) {
+                contents.push_str(&format!("{} {}\n", from, to));
+            }
+        }
+
+        File::create(links_file_name)
+            .and_then(|mut file| file.write_all(contents.as_bytes()))
+            .map_err(|e| KmlError::DocumentNotParsed(format!("Couldn't write link file: {}", e)))
+    }
+
     /**
      * Returns a file handle to the doc.kml file from a kml file (in zip format).
      */
-    fn extract_doc_kml(kml_file_name: &str) -> BufReader<File> {
+    fn extract_doc_kml(kml_file_name: &str) -> Result<BufReader<File>, KmlError> {
         let path = Path::new(kml_file_name);
-        if !path.exists() || !path.is_file() {
-            panic!("File does not exist: {}", kml_file_name);
+        if !path.is_file() {
+            return Err(KmlError::DocumentNotParsed(
+                format!("File does not exist: {}", kml_file_name)));
         }
 
-        // A KML file is a zip archive containing a single file named "doc.kml"
-        // that is an XML file
+        // A KML file is a zip archive containing a single file named "doc.kml" that is an XML
+        // file.
         let temp_directory = "/tmp/waypoints";
-        match remove_dir_all(temp_directory) {
-            Ok(_) => (),
-            Err(e) => match e.raw_os_error() {
-                Some(errno) => if errno == ENOENT {
-                        ()  // Directory does not exist; that's fine
-                    } else {
-                        warn!("Failed to remove temp directory: {}", e)
-                    },
-                None => warn!("Failed to remove temp directory: {}", e),
+        if let Err(e) = fs::remove_dir_all(temp_directory) {
+            if e.kind() != io::ErrorKind::NotFound {
+                warn!("Failed to remove temp directory: {}", e);
             }
-        };
-        let zip_io_result = Command::new("unzip")
+        }
+
+        let mut zip_child = match Command::new("unzip")
             .arg(kml_file_name)
             .arg("-d")  // Output directory
             .arg(temp_directory)
-            .spawn();
-        let mut zip_child = match zip_io_result {
-            Ok(child) => (child),
-            Err(e) => panic!("Failed to unzip file: {}", e),
-        };
-
-        match zip_child.wait() {
-            Ok(_) => (),
-            Err(e) => panic!("Failed to unzip file: {}", e),
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(e) => {
+                return Err(KmlError::DocumentNotParsed(format!("Failed to unzip file: {}", e)));
+            }
         };
+        if let Err(e) = zip_child.wait() {
+            return Err(KmlError::DocumentNotParsed(format!("Failed to unzip file: {}", e)));
+        }
 
-        let mut waypoints = Vec::<Point>::new();
         let file_path = Path::new("/tmp/waypoints/doc.kml");
-        let file = match File::open(&file_path) {
-            Ok(f) => f,
-            Err(_) => panic!("Couldn't open doc.kml"),
-        };
-        BufReader::new(file)
-    }
-
-    /**
-     * Returns the waypoints line (e.g. "40.9,-105.3,0 41.1,-105.2,0") from the doc.kml file
-     * extracted from a kml file.
-     */
-    fn extract_waypoints_line<T: BufRead>(xml_file: T) -> String {
-        let mut coordinates_open_tag = false;
-        // We should use a real XML parser here, but Google Earth saves the
-        // <coordinates> tag on one line, then the coordinates on the next,
-        // then the closing </coordinates> tag on the next, so we'll just rely
-        // on that fact
-        for line_option in xml_file.lines() {
-            match line_option {
-                Ok(line) => {
-                    if line.contains("<coordinates>") {
-                        coordinates_open_tag = true;
-                    } else if coordinates_open_tag {
-                        return line;
-                    }
-                }
-                Err(e) => println!("error {}", e),
+        match File::open(&file_path) {
+            Ok(file) => Ok(BufReader::new(file)),
+            Err(e) => {
+                Err(KmlError::DocumentNotParsed(format!("Couldn't open doc.kml: {}", e)))
             }
         }
-        panic!("No waypoints line found");
     }
 
     /**
-     * Returns the waypoints from a waypoint formatted line, e.g. "40.9,-105.3,0 41.1,-105.2,0".
+     * Streams `xml_source` through an event-based XML parser, collecting the latitude/longitude
+     * pairs out of every `<coordinates>` element (comma-separated `lon,lat[,alt]` tuples,
+     * whitespace-separated from each other) and every `<gx:coord>` element (a single
+     * space-separated `lon lat [alt]` tuple), in the order they appear. Matches elements by
+     * local name only, so the `gx:` namespace prefix doesn't need to be resolved.
      */
-    fn parse_waypoints_line(line: &str) -> Vec<Point> {
-        let mut waypoints: Vec<Point> = vec![];
-        let mut latitude = 0.0f64;
-        let mut longitude = 0.0f64;
-        for long_lat_alt in line.split_whitespace() {
-            let mut iterator = long_lat_alt.split(',');
-            let mut success = true;
-            match iterator.next() {
-                Some(longitude_str) => {
-                    let parsed_longitude = longitude_str.parse::<f64>();
-                    match parsed_longitude {
-                        Ok(longitude_) => longitude = longitude_,
-                        Err(e) => {
-                            println!("Unable to parse longitude: '{}', {}", longitude_str, e);
-                            success = false;
-                        },
+    fn parse_waypoints<R: Read>(xml_source: R) -> Result<Vec<Point>, KmlError> {
+        let parser = EventReader::new(xml_source);
+        let mut waypoints = Vec::new();
+        let mut in_coordinates = false;
+        let mut in_gx_coord = false;
+        let mut saw_any_element = false;
+
+        for event in parser {
+            match event {
+                Ok(XmlEvent::StartElement { name, .. }) => {
+                    saw_any_element = true;
+                    match &name.local_name[..] {
+                        "coordinates" => in_coordinates = true,
+                        "coord" => in_gx_coord = true,
+                        _ => (),
                     }
-                },
-                None => println!("No longitude"),
-            }
-
-            match iterator.next() {
-                Some(latitude_str) => {
-                    let parsed_latitude = latitude_str.parse::<f64>();
-                    match parsed_latitude {
-                        Ok(latitude_) => latitude = latitude_,
-                        Err(e) => {
-                            println!(
-                                "Unable to parse latitude: '{}', {}",
-                                 latitude_str,
-                                 e);
-                            success = false;
-                        },
+                }
+                Ok(XmlEvent::EndElement { name }) => {
+                    match &name.local_name[..] {
+                        "coordinates" => in_coordinates = false,
+                        "coord" => in_gx_coord = false,
+                        _ => (),
+                    }
+                }
+                Ok(XmlEvent::Characters(text)) => {
+                    if in_coordinates {
+                        waypoints.extend(parse_coordinates_text(&text));
+                    } else if in_gx_coord {
+                        if let Some(point) = parse_gx_coord_text(&text) {
+                            waypoints.push(point);
+                        }
                     }
                 }
-                None => println!("No latitude"),
+                Ok(_) => (),
+                // The reader reports input without a root element as an error.
+                Err(_) if !saw_any_element => return Err(KmlError::EmptyDocument),
+                Err(e) => return Err(KmlError::DocumentNotParsed(e.to_string())),
             }
+        }
 
-            if success {
-                waypoints.push(latitude_longitude_to_point(latitude, longitude));
-            }
+        if !saw_any_element {
+            return Err(KmlError::EmptyDocument);
+        }
+        if waypoints.is_empty() {
+            return Err(KmlError::NoCoordinatesFound);
         }
-        waypoints
+        Ok(waypoints)
     }
 }
 
 
+/**
+ * Parses a `<coordinates>` element's text, e.g. "-105.3,40.9,0 -105.2,41.1,0", into waypoints.
+ * Tuples that fail to parse are skipped rather than aborting the whole course.
+ */
+fn parse_coordinates_text(text: &str) -> Vec<Point> {
+    text.split_whitespace()
+        .filter_map(|tuple| {
+            let mut fields = tuple.split(',');
+            let longitude = match fields.next().and_then(|s| s.parse::<f64>().ok()) {
+                Some(longitude) => longitude,
+                None => return None,
+            };
+            let latitude = match fields.next().and_then(|s| s.parse::<f64>().ok()) {
+                Some(latitude) => latitude,
+                None => return None,
+            };
+            Some(latitude_longitude_to_point(latitude, longitude))
+        })
+        .collect()
+}
+
+
+/**
+ * Parses a `<gx:coord>` element's text, e.g. "-105.3 40.9 0", into a single waypoint.
+ */
+fn parse_gx_coord_text(text: &str) -> Option<Point> {
+    let mut fields = text.split_whitespace();
+    let longitude = match fields.next().and_then(|s| s.parse::<f64>().ok()) {
+        Some(longitude) => longitude,
+        None => return None,
+    };
+    let latitude = match fields.next().and_then(|s| s.parse::<f64>().ok()) {
+        Some(latitude) => latitude,
+        None => return None,
+    };
+    Some(latitude_longitude_to_point(latitude, longitude))
+}
+
+
 impl WaypointGenerator for KmlWaypointGenerator {
-    #[allow(unused_variables)]
-    fn get_current_waypoint(&self, point: &Point) -> Option<Point> {
-        if !self.done() {
-            Some(self.waypoints[self.current_waypoint])
-        } else {
-            None
+    fn get_current_waypoint(&self, point: &Point) -> Point {
+        match self.visit_order {
+            VisitOrder::Sequential => self.route[self.current_waypoint.min(self.route.len() - 1)],
+            VisitOrder::NearestFirst => {
+                if let Some((_, cached_point)) = self.current_target.get() {
+                    return cached_point;
+                }
+                let nearest = self.unvisited.as_ref().and_then(|tree| tree.nearest(point));
+                match nearest {
+                    Some((index, nearest_point)) => {
+                        self.current_target.set(Some((index, nearest_point)));
+                        nearest_point
+                    }
+                    None => self.route[self.route.len() - 1],
+                }
+            }
         }
     }
 
-    fn get_current_raw_waypoint(&self, point: &Point) -> Option<Point> {
+    fn get_current_raw_waypoint(&self, point: &Point) -> Point {
         self.get_current_waypoint(point)
     }
 
     fn next(&mut self) {
-        self.current_waypoint += 1;
+        self.min_distance_m.set(None);
+        match self.visit_order {
+            VisitOrder::Sequential => self.current_waypoint += 1,
+            VisitOrder::NearestFirst => {
+                if let Some((index, _)) = self.current_target.get() {
+                    if let Some(tree) = self.unvisited.as_mut() {
+                        tree.remove(index);
+                    }
+                }
+                self.current_target.set(None);
+            }
+        }
     }
 
-    #[allow(unused_variables)]
+    /**
+     * A waypoint is reached once we're within the tight capture radius (`reach_distance()`), or
+     * once we're within the larger `APPROACH_RADIUS_M` and the distance has started increasing
+     * relative to the previous sample, meaning we just passed our closest approach. Fixed-radius
+     * capture alone misses waypoints that GPS jitter or vehicle speed carry the car past between
+     * samples.
+     */
     fn reached(&self, point: &Point) -> bool {
-        // TODO: Change this so that it returns true if we're within a certain distance (e.g. 1m)
-        // or if we are within a certain distance (e.g. 3m) and we start getting farther away
-        let current_option = self.get_current_waypoint(point);
-        let current = match current_option {
-            Some(point) => point,
-            None => return false,
-        };
-        if distance(&current, point) < 1.0 {
+        if self.done() {
+            return false;
+        }
+
+        let distance_m = distance(&self.get_current_waypoint(point), point);
+        let previous_min_distance_m = self.min_distance_m.get();
+        let min_distance_m = previous_min_distance_m.map_or(distance_m, |m| m.min(distance_m));
+        self.min_distance_m.set(Some(min_distance_m));
+
+        if distance_m <= self.reach_distance() {
             return true;
         }
-        return false;
+        match previous_min_distance_m {
+            Some(previous_min_distance_m) => {
+                distance_m <= APPROACH_RADIUS_M && distance_m > previous_min_distance_m
+            }
+            None => false,
+        }
     }
 
     fn done(&self) -> bool {
-        if self.current_waypoint >= self.waypoints.len() {
-            return true;
+        match self.visit_order {
+            VisitOrder::Sequential => self.current_waypoint >= self.route.len(),
+            VisitOrder::NearestFirst => self.unvisited.as_ref().map_or(true, |tree| tree.is_empty()),
         }
-        return false;
     }
 
     #[allow(unused_variables)]
     fn reach_distance(&self) -> Meter {
         1.0
     }
+
+    fn reset(&mut self) {
+        self.current_waypoint = 0;
+        self.min_distance_m.set(None);
+        if let VisitOrder::NearestFirst = self.visit_order {
+            self.unvisited = Some(KdTree::new(self.route.clone()));
+        }
+        self.current_target.set(None);
+    }
 }
 
 
 #[cfg(test)]
 mod tests {
-    use num::traits::{Float, FromPrimitive};
-    use std::io::BufRead;
+    use std::fs::File;
+    use std::io::Write;
 
-    use super::KmlWaypointGenerator;
+    use super::{KmlError, KmlWaypointGenerator, VisitOrder};
     use telemetry::Point;
     use waypoint_generator::WaypointGenerator;
 
-    fn assert_approx_eq<T: Float + FromPrimitive>(value_1: T, value_2: T) {
-        assert!(approx_eq(value_1, value_2));
-    }
-    fn approx_eq<T: Float + FromPrimitive>(value_1: T, value_2: T) -> bool {
-        // Yeah, I know this is bad, see
-        // http://randomascii.wordpress.com/2012/02/25/comparing-floating-point-numbers-2012-edition/
-
-        let diff = (value_1 - value_2).abs();
-        // This is the best we can do with f32
-        diff < FromPrimitive::from_f32(0.00001f32).unwrap()
-    }
-
     #[test]
     fn test_get_current_waypoint() {
         let first = Point { x: 1.0, y: 1.0 };
@@ -236,14 +556,7 @@ mod tests {
         let waypoint_generator = KmlWaypointGenerator::new_from_waypoints(
             vec![first, other]
         );
-        let current_option = waypoint_generator.get_current_waypoint(&other);
-        let current = match current_option {
-            Some(point) => point,
-            None => {
-                assert!(false);
-                Point { x: 0.0, y: 0.0 }  // This should never be reached
-            }
-        };
+        let current = waypoint_generator.get_current_waypoint(&other);
         assert!(current.x == first.x);
         assert!(current.y == first.y);
     }
@@ -256,14 +569,7 @@ mod tests {
         let waypoint_generator = KmlWaypointGenerator::new_from_waypoints(
             vec![first, other]
         );
-        let current_option = waypoint_generator.get_current_waypoint(&other);
-        let current = match current_option {
-            Some(point) => point,
-            None => {
-                assert!(false);
-                Point { x: 0.0, y: 0.0 }  // This should never be reached
-            }
-        };
+        let current = waypoint_generator.get_current_raw_waypoint(&other);
         assert!(current.x == first.x);
         assert!(current.y == first.y);
     }
@@ -277,38 +583,18 @@ mod tests {
             vec![first, second]
         );
 
-        let current_option = waypoint_generator.get_current_waypoint(&other);
-        let current = match current_option {
-            Some(point) => point,
-            None => {
-                assert!(false);
-                Point { x: 0.0, y: 0.0 }  // This should never be reached
-            }
-        };
+        let current = waypoint_generator.get_current_waypoint(&other);
         assert!(current.x == first.x);
         assert!(current.y == first.y);
 
         waypoint_generator.next();
-        let current_option_2 = waypoint_generator.get_current_waypoint(&other);
-        let current_2 = match current_option_2 {
-            Some(point) => point,
-            None => {
-                assert!(false);
-                panic!("This should never be reached");
-            }
-        };
+        let current_2 = waypoint_generator.get_current_waypoint(&other);
         assert!(current_2.x == second.x);
         assert!(current_2.y == second.y);
 
         for _ in 0..3 {
             waypoint_generator.next();
-            match waypoint_generator.get_current_waypoint(&other) {
-                Some(_) => {
-                    assert!(false);
-                    panic!("This should never be reached");
-                },
-                None => ()
-            }
+            assert!(waypoint_generator.done());
         }
     }
 
@@ -319,36 +605,51 @@ mod tests {
         let waypoint_generator = KmlWaypointGenerator::new_from_waypoints(
             vec![first, other]
         );
-        let current_option = waypoint_generator.get_current_waypoint(&other);
-        let current = match current_option {
-            Some(point) => point,
-            None => {
-                assert!(false);
-                panic!("This should never be reached");
-            }
+
+        // Outside the approach radius: not reached.
+        assert!(!waypoint_generator.reached(&Point { x: first.x + 10.0, y: first.y }));
+
+        // Within the capture radius: reached immediately, regardless of approach history.
+        let captured = Point {
+            x: first.x + waypoint_generator.reach_distance() * 0.5,
+            y: first.y,
         };
+        assert!(waypoint_generator.reached(&captured));
+    }
+
+    #[test]
+    fn test_reached_detects_overshoot() {
+        // Approaching within the wider approach radius but outside the tight capture radius
+        // should only be "reached" once the distance starts increasing again, i.e. once we've
+        // passed the closest point of approach.
+        let first = Point { x: 1.0, y: 1.0 };
+        let other = Point { x: 200.0, y: 200.0 };
+        let waypoint_generator = KmlWaypointGenerator::new_from_waypoints(
+            vec![first, other]
+        );
 
-        let mut current = first;
+        assert!(!waypoint_generator.reached(&Point { x: first.x + 2.5, y: first.y }));
+        // Still closing in: not reached yet.
+        assert!(!waypoint_generator.reached(&Point { x: first.x + 2.0, y: first.y }));
+        // Distance increased relative to the closest point seen so far: just passed it.
+        assert!(waypoint_generator.reached(&Point { x: first.x + 2.2, y: first.y }));
+    }
 
-        // Exactly on the point
-        assert!(waypoint_generator.reached(&current));
+    #[test]
+    fn test_reached_resets_approach_state_on_next() {
+        let first = Point { x: 1.0, y: 1.0 };
+        let other = Point { x: 200.0, y: 200.0 };
+        let mut waypoint_generator = KmlWaypointGenerator::new_from_waypoints(
+            vec![first, other]
+        );
 
-        // Within reach_distance of the point
-        current.x += waypoint_generator.reach_distance() * 0.999;
-        assert!(waypoint_generator.reached(&current));
-        current.x = first.x;
-        current.y += waypoint_generator.reach_distance() * 0.999;
-        assert!(waypoint_generator.reached(&current));
+        assert!(!waypoint_generator.reached(&Point { x: first.x + 2.0, y: first.y }));
+        waypoint_generator.next();
 
-        // Outside
-        current.x += waypoint_generator.reach_distance() * 0.999;
-        assert!(!waypoint_generator.reached(&current));
-        current.x += 1000.0;
-        current.y += 1000.0;
-        assert!(!waypoint_generator.reached(&current));
-        current.x -= 5000.0;
-        current.y -= 5000.0;
-        assert!(!waypoint_generator.reached(&current));
+        // Without resetting the approach state on `next()`, this sample would look like an
+        // overshoot of `first` (2.5 > 2.0); instead it should be judged as a fresh approach to
+        // the new current waypoint, `other`.
+        assert!(!waypoint_generator.reached(&Point { x: other.x + 2.5, y: other.y }));
     }
 
     #[test]
@@ -363,70 +664,195 @@ mod tests {
     }
 
     #[test]
-    fn test_extract_waypoints_line() {
-        let xml_template = (r#"<?xml version="1.0" encoding="UTF-8"?>
-<kml xmlns="http://www.opengis.net/kml/2.2" xmlns:gx="http://www.google.com/kml/ext/2.2" xmlns:kml="http://www.opengis.net/kml/2.2" xmlns:atom="http://www.w3.org/2005/Atom">
+    fn test_nearest_first_visits_closest_waypoint() {
+        let far = Point { x: 100.0, y: 100.0 };
+        let near = Point { x: 1.0, y: 1.0 };
+        let mut waypoint_generator = KmlWaypointGenerator::new_from_waypoints(
+            vec![far, near]
+        );
+        waypoint_generator.set_visit_order(VisitOrder::NearestFirst);
+
+        let here = Point { x: 0.0, y: 0.0 };
+        let current = waypoint_generator.get_current_waypoint(&here);
+        assert!(current.x == near.x && current.y == near.y);
+
+        waypoint_generator.next();
+        assert!(!waypoint_generator.done());
+        let current = waypoint_generator.get_current_waypoint(&here);
+        assert!(current.x == far.x && current.y == far.y);
+
+        waypoint_generator.next();
+        assert!(waypoint_generator.done());
+    }
+
+    #[test]
+    fn test_recompute_routes_from_given_point() {
+        // 10 and 110 are farther apart than DEFAULT_LINK_RADIUS_M, so the only route between
+        // them is via 60, making the resulting route deterministic.
+        let mut waypoint_generator = KmlWaypointGenerator::new_from_waypoints(
+            vec![Point { x: 0.0, y: 0.0 }, Point { x: 1.0, y: 1.0 }]
+        );
+        let waypoints = vec![
+            Point { x: 10.0, y: 10.0 },
+            Point { x: 60.0, y: 10.0 },
+            Point { x: 110.0, y: 10.0 },
+        ];
+        let route = waypoint_generator.recompute(waypoints, &Point { x: 10.0, y: 10.0 });
+        assert!(route.len() == 3);
+        assert!(route[0].x == 10.0);
+        assert!(route[2].x == 110.0);
+        assert!(!waypoint_generator.done());
+    }
+
+    #[test]
+    fn test_insert_and_remove_waypoint() {
+        // 0 and 80 are farther apart than DEFAULT_LINK_RADIUS_M, so the only route between them
+        // once 40 is inserted is via the midpoint, making the resulting route deterministic.
+        let mut waypoint_generator = KmlWaypointGenerator::new_from_waypoints(
+            vec![Point { x: 0.0, y: 0.0 }, Point { x: 80.0, y: 0.0 }]
+        );
+        waypoint_generator.insert_waypoint(1, Point { x: 40.0, y: 0.0 });
+        let current = waypoint_generator.get_current_waypoint(&Point { x: 0.0, y: 0.0 });
+        assert!(current.x == 0.0);
+        waypoint_generator.next();
+        let current = waypoint_generator.get_current_waypoint(&Point { x: 0.0, y: 0.0 });
+        assert!(current.x == 40.0);
+
+        waypoint_generator.remove_waypoint(0);
+        let current = waypoint_generator.get_current_waypoint(&Point { x: 0.0, y: 0.0 });
+        assert!(current.x == 40.0);
+    }
+
+    #[test]
+    fn test_save_and_load_links() {
+        let waypoints = vec![
+            Point { x: 0.0, y: 0.0 },
+            Point { x: 10.0, y: 10.0 },
+            Point { x: 20.0, y: 0.0 },
+        ];
+        let mut saved = KmlWaypointGenerator::new_from_waypoints(waypoints.clone());
+        let links_file_name = "/tmp/test_save_and_load_links.links";
+        saved.save_links(links_file_name).unwrap();
+
+        let mut loaded = KmlWaypointGenerator::new_from_waypoints(waypoints);
+        loaded.load_links(links_file_name).unwrap();
+        assert!(loaded.route.len() == saved.route.len());
+    }
+
+    #[test]
+    fn test_load_links_rejects_old_version() {
+        let links_file_name = "/tmp/test_load_links_rejects_old_version.links";
+        {
+            let mut file = File::create(links_file_name).unwrap();
+            file.write_all(b"// VERSION 0\n0 1\n").unwrap();
+        }
+
+        let waypoints = vec![Point { x: 0.0, y: 0.0 }, Point { x: 10.0, y: 10.0 }];
+        let mut waypoint_generator = KmlWaypointGenerator::new_from_waypoints(waypoints);
+        match waypoint_generator.load_links(links_file_name) {
+            Err(KmlError::DocumentNotParsed(_)) => (),
+            other => panic!("Expected DocumentNotParsed, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_load_links_rejects_missing_version_header() {
+        let links_file_name = "/tmp/test_load_links_rejects_missing_version_header.links";
+        {
+            let mut file = File::create(links_file_name).unwrap();
+            file.write_all(b"0 1\n").unwrap();
+        }
+
+        let waypoints = vec![Point { x: 0.0, y: 0.0 }, Point { x: 10.0, y: 10.0 }];
+        let mut waypoint_generator = KmlWaypointGenerator::new_from_waypoints(waypoints);
+        match waypoint_generator.load_links(links_file_name) {
+            Err(KmlError::DocumentNotParsed(_)) => (),
+            other => panic!("Expected DocumentNotParsed, got {:?}", other),
+        }
+    }
+
+    fn wrap_kml(inner: &str) -> String {
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<kml xmlns="http://www.opengis.net/kml/2.2" xmlns:gx="http://www.google.com/kml/ext/2.2">
 <Document>
-	<name>rally-1-loop.kmz</name>
-	<Style id="s_ylw-pushpin_hl">
-		<IconStyle>
-			<scale>1.3</scale>
-			<Icon>
-				<href>http://maps.google.com/mapfiles/kml/pushpin/ylw-pushpin.png</href>
-			</Icon>
-			<hotSpot x="20" y="2" xunits="pixels" yunits="pixels"/>
-		</IconStyle>
-	</Style>
-	<StyleMap id="m_ylw-pushpin">
-		<Pair>
-			<key>normal</key>
-			<styleUrl>#s_ylw-pushpin</styleUrl>
-		</Pair>
-		<Pair>
-			<key>highlight</key>
-			<styleUrl>#s_ylw-pushpin_hl</styleUrl>
-		</Pair>
-	</StyleMap>
-	<Style id="s_ylw-pushpin">
-		<IconStyle>
-			<scale>1.1</scale>
-			<Icon>
-				<href>http://maps.google.com/mapfiles/kml/pushpin/ylw-pushpin.png</href>
-			</Icon>
-			<hotSpot x="20" y="2" xunits="pixels" yunits="pixels"/>
-		</IconStyle>
-	</Style>
-	<Placemark>
-		<name>Rally 1 loop</name>
-		<styleUrl>#m_ylw-pushpin</styleUrl>
-		<LineString>
-			<tessellate>1</tessellate>
-			<coordinates>
-                {}
-			</coordinates>
-		</LineString>
-	</Placemark>
+{}
 </Document>
 </kml>
-"#);
-        let first = Point { x: 1.0, y: -5.0 };
-        let second = Point { x: -3.0, y: 10.0 };
-        let coordinates_line = format!("{},{},0 {},{},0", first.x, first.y, second.x, second.y);
-        let xml_string = xml_template.replace("{}", &coordinates_line[..]);
-        // Hey, Rust already defines a impl<'a> BufRead for &'a [u8]! Cool!
-        let xml_buffer = xml_string.as_bytes();
-        assert!(
-            KmlWaypointGenerator::extract_waypoints_line(xml_buffer).trim() == coordinates_line
-        );
+"#,
+            inner)
+    }
+
+    #[test]
+    fn test_parse_waypoints_linestring() {
+        let xml = wrap_kml(r#"
+            <Placemark>
+                <name>Rally 1 loop</name>
+                <LineString>
+                    <tessellate>1</tessellate>
+                    <coordinates>-105.3,40.9,0 -105.2,41.1,0</coordinates>
+                </LineString>
+            </Placemark>
+        "#);
+        let waypoints = KmlWaypointGenerator::parse_waypoints(xml.as_bytes()).unwrap();
+        assert!(waypoints.len() == 2);
+    }
+
+    #[test]
+    fn test_parse_waypoints_multiple_placemarks() {
+        let xml = wrap_kml(r#"
+            <Placemark>
+                <Point><coordinates>-105.3,40.9,0</coordinates></Point>
+            </Placemark>
+            <Placemark>
+                <Point><coordinates>-105.2,41.1,0</coordinates></Point>
+            </Placemark>
+            <Placemark>
+                <Point><coordinates>-105.1,41.2,0</coordinates></Point>
+            </Placemark>
+        "#);
+        let waypoints = KmlWaypointGenerator::parse_waypoints(xml.as_bytes()).unwrap();
+        assert!(waypoints.len() == 3);
+    }
+
+    #[test]
+    fn test_parse_waypoints_gx_track() {
+        let xml = wrap_kml(r#"
+            <Placemark>
+                <gx:Track>
+                    <when>2026-07-26T12:00:00Z</when>
+                    <when>2026-07-26T12:00:01Z</when>
+                    <gx:coord>-105.3 40.9 0</gx:coord>
+                    <gx:coord>-105.2 41.1 0</gx:coord>
+                </gx:Track>
+            </Placemark>
+        "#);
+        let waypoints = KmlWaypointGenerator::parse_waypoints(xml.as_bytes()).unwrap();
+        assert!(waypoints.len() == 2);
+    }
+
+    #[test]
+    fn test_parse_waypoints_no_coordinates_found() {
+        let xml = wrap_kml("<Placemark><name>Empty</name></Placemark>");
+        match KmlWaypointGenerator::parse_waypoints(xml.as_bytes()) {
+            Err(KmlError::NoCoordinatesFound) => (),
+            other => panic!("Expected NoCoordinatesFound, got {:?}", other),
+        }
     }
 
-    fn test_parse_waypoints_line() {
-        let first = Point { x: 1.0, y: -5.0 };
-        let second = Point { x: -3.0, y: 10.0 };
-        let coordinates_line = format!("{},{},0 {},{},0", first.x, first.y, second.x, second.y);
-        let points = KmlWaypointGenerator::parse_waypoints_line(&coordinates_line[..]);
-        assert!(points.len() == 2);
-        assert!(points[0] == first);
-        assert!(points[1] == second);
+    #[test]
+    fn test_parse_waypoints_empty_document() {
+        match KmlWaypointGenerator::parse_waypoints("".as_bytes()) {
+            Err(KmlError::EmptyDocument) => (),
+            other => panic!("Expected EmptyDocument, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_waypoints_malformed_document() {
+        match KmlWaypointGenerator::parse_waypoints("<kml><Document>".as_bytes()) {
+            Err(KmlError::DocumentNotParsed(_)) => (),
+            other => panic!("Expected DocumentNotParsed, got {:?}", other),
+        }
     }
 }
@@ -5,7 +5,9 @@
 #![feature(path)]
 #![feature(std_misc)]
 
+pub mod ecef;
 pub mod filtered_telemetry;
+pub mod kd_tree;
 pub mod kml_waypoint_generator;
 pub mod location_filter;
 pub mod logger;
@@ -13,3 +15,4 @@ pub mod stdout_logger;
 pub mod telemetry;
 pub mod telemetry_message;
 pub mod waypoint_generator;
+pub mod waypoint_graph;
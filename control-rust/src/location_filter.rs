@@ -1,72 +1,113 @@
 use telemetry::{rotate_degrees_clockwise, wrap_degrees, Point};
 
+// Chi-square thresholds (~95% confidence) for the innovation gate in `update`. A sample whose
+// Mahalanobis distance exceeds its threshold is rejected as an implausible measurement (GPS
+// multipath, magnetometer interference) rather than being allowed to drag the estimate off.
+const COMPASS_CHI2_THRESHOLD: f32 = 7.8; // the compass only meaningfully constrains heading
+const MULTI_STATE_CHI2_THRESHOLD: f32 = 9.5; // GPS position/heading/speed, or the IMU's yaw rate/acceleration pair
+
 #[allow(dead_code)]
 struct LocationFilter {
-    gps_observer_matrix: [[f32; 4]; 4],              // H
-    compass_observer_matrix: [[f32; 4]; 4],          // H
-    dead_reckoning_observer_matrix: [[f32; 4]; 4],   // H
-    gps_measurement_noise: [[f32; 4]; 4],            // R
-    compass_measurement_noise: [[f32; 4]; 4],        // R
-    dead_reckoning_measurement_noise: [[f32; 4]; 4], // R
-
-    // x m, y m, heading d, speed m/s
-    estimates: [[f32; 1]; 4],     // x
-    covariance: [[f32; 4]; 4],    // P
-    process_noise: [[f32; 4]; 4], // Q
+    gps_observer_matrix: [[f32; 6]; 6],              // H
+    compass_observer_matrix: [[f32; 6]; 6],          // H
+    dead_reckoning_observer_matrix: [[f32; 6]; 6],   // H
+    imu_observer_matrix: [[f32; 6]; 6],              // H
+    gps_measurement_noise: [[f32; 6]; 6],            // R
+    compass_measurement_noise: [[f32; 6]; 6],        // R
+    dead_reckoning_measurement_noise: [[f32; 6]; 6], // R
+    imu_measurement_noise: [[f32; 6]; 6],            // R
+
+    // x m, y m, heading d, speed m/s, yaw rate d/s, acceleration m/s^2
+    estimates: [[f32; 1]; 6],     // x
+    covariance: [[f32; 6]; 6],    // P
+    process_noise: [[f32; 6]; 6], // Q
     last_observation_time_s: f32,
 
     // These paremeters are just scratch space for the
     // computations in update so that we can avoid reallocations
-    out: [[f32; 4]; 4],
-    out2: [[f32; 4]; 4],
-    out3: [[f32; 4]; 4],
-    out41: [[f32; 1]; 4],
-    out41_2: [[f32; 1]; 4],
-    kalman_gain: [[f32; 4]; 4],
+    out: [[f32; 6]; 6],
+    out2: [[f32; 6]; 6],
+    out3: [[f32; 6]; 6],
+    out61: [[f32; 1]; 6],
+    out61_2: [[f32; 1]; 6],
+    kalman_gain: [[f32; 6]; 6],
 }
 
 impl LocationFilter {
     #[allow(dead_code)]
     pub fn new(x_m: f32, y_m: f32, heading_d: f32) -> LocationFilter {
         let lf = LocationFilter {
-            gps_observer_matrix: identity(),
+            gps_observer_matrix: [
+                [1f32, 0f32, 0f32, 0f32, 0f32, 0f32],
+                [0f32, 1f32, 0f32, 0f32, 0f32, 0f32],
+                [0f32, 0f32, 1f32, 0f32, 0f32, 0f32],
+                [0f32, 0f32, 0f32, 1f32, 0f32, 0f32],
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0f32],
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0f32],
+            ],
             compass_observer_matrix: [
-                [0f32, 0f32, 0f32, 0f32],
-                [0f32, 0f32, 0f32, 0f32],
-                [0f32, 0f32, 1f32, 0f32],
-                [0f32, 0f32, 0f32, 0f32],
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0f32],
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0f32],
+                [0f32, 0f32, 1f32, 0f32, 0f32, 0f32],
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0f32],
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0f32],
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0f32],
             ],
             dead_reckoning_observer_matrix: [
-                [0f32, 0f32, 0f32, 0f32],
-                [0f32, 0f32, 0f32, 0f32],
-                [0f32, 0f32, 0f32, 0f32],
-                [0f32, 0f32, 0f32, 1f32],
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0f32],
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0f32],
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0f32],
+                [0f32, 0f32, 0f32, 1f32, 0f32, 0f32],
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0f32],
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0f32],
+            ],
+            imu_observer_matrix: [
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0f32],
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0f32],
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0f32],
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0f32],
+                [0f32, 0f32, 0f32, 0f32, 1f32, 0f32],
+                [0f32, 0f32, 0f32, 0f32, 0f32, 1f32],
             ],
             gps_measurement_noise: [
-                [0f32, 0f32, 0f32, 0f32], // x_m will be filled in by the GPS accuracy
-                [0f32, 0f32, 0f32, 0f32], // y_m will be filled in by the GPS accuracy
-                [0f32, 0f32, 5f32, 0f32], // This degrees value is a guess
-                [0f32, 0f32, 0f32, 1f32], // This speed value is a guess
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0f32], // x_m will be filled in by the GPS accuracy
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0f32], // y_m will be filled in by the GPS accuracy
+                [0f32, 0f32, 5f32, 0f32, 0f32, 0f32], // This degrees value is a guess
+                [0f32, 0f32, 0f32, 1f32, 0f32, 0f32], // This speed value is a guess
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0f32], // GPS doesn't observe yaw rate
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0f32], // GPS doesn't observe acceleration
             ],
             compass_measurement_noise: [
-                [0f32, 0f32, 0f32, 0f32],
-                [0f32, 0f32, 0f32, 0f32],
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0f32],
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0f32],
                 // This degrees value is a guess. It's kept artificially high
                 // because I've observed a lot of local interference as I drove
                 // around before. TODO: Ignore magnetometer readings with bad
                 // magnitudes that are obviously invalid and tune this down.
-                [0f32, 0f32, 45f32, 0f32],
-                [0f32, 0f32, 0f32, 0f32],
+                [0f32, 0f32, 45f32, 0f32, 0f32, 0f32],
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0f32],
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0f32],
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0f32],
             ],
             dead_reckoning_measurement_noise: [
-                [0f32, 0f32, 0f32, 0f32],
-                [0f32, 0f32, 0f32, 0f32],
-                [0f32, 0f32, 0f32, 0f32],
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0f32],
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0f32],
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0f32],
                 // This speed value is a guess but should be higher than
                 // the GPS speed value
-                [0f32, 0f32, 0f32, 3f32],
+                [0f32, 0f32, 0f32, 3f32, 0f32, 0f32],
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0f32],
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0f32],
             ],
-            estimates: [[x_m], [y_m], [heading_d], [0.0f32]],
+            imu_measurement_noise: [
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0f32],
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0f32],
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0f32],
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0f32],
+                [0f32, 0f32, 0f32, 0f32, 4f32, 0f32], // This yaw rate value is a guess
+                [0f32, 0f32, 0f32, 0f32, 0f32, 0.5f32], // This acceleration value is a guess
+            ],
+            estimates: [[x_m], [y_m], [heading_d], [0.0f32], [0.0f32], [0.0f32]],
             // This will be populated as the filter runs
             // TODO: Ideally, this should be initialized to those values,
             // but for right now, identity matrix is fine
@@ -77,38 +118,44 @@ impl LocationFilter {
 
             // These paremeters are just scratch space for the
             // computations in update so that we can avoid reallocations
-            out: [[0.0f32; 4]; 4],
-            out2: [[0.0f32; 4]; 4],
-            out3: [[0.0f32; 4]; 4],
-            out41: [[0.0f32; 1]; 4],
-            out41_2: [[0.0f32; 1]; 4],
-            kalman_gain: [[0.0f32; 4]; 4],
+            out: [[0.0f32; 6]; 6],
+            out2: [[0.0f32; 6]; 6],
+            out3: [[0.0f32; 6]; 6],
+            out61: [[0.0f32; 1]; 6],
+            out61_2: [[0.0f32; 1]; 6],
+            kalman_gain: [[0.0f32; 6]; 6],
         };
         assert!(lf.dead_reckoning_measurement_noise[3][3] > lf.gps_measurement_noise[3][3]);
         return lf;
     }
 
     /**
-     * Runs the Kalman update using the provided measurements.
+     * Runs the Kalman update using the provided measurements. Returns false, leaving the
+     * correction unapplied (the prediction step above still runs), if the measurement's
+     * Mahalanobis distance from the predicted state exceeds `chi2_threshold` - callers can use
+     * this to log rejected GPS/compass/IMU samples.
      */
     #[allow(dead_code)]
     pub fn update(
         &mut self,
-        measurements_: &[f32; 4],
-        observer_matrix: &[[f32; 4]; 4],
-        measurement_noise: &[[f32; 4]; 4],
+        measurements_: &[f32; 6],
+        observer_matrix: &[[f32; 6]; 6],
+        measurement_noise: &[[f32; 6]; 6],
         time_diff_s: f32,
-    ) {
-        // For convenience, we let users supply measurements as [f32; 4], but
-        // because we're doing matrix stuff, we need to convert them to 4x1
+        chi2_threshold: f32,
+    ) -> bool {
+        // For convenience, we let users supply measurements as [f32; 6], but
+        // because we're doing matrix stuff, we need to convert them to 6x1
         let measurements = [
             [measurements_[0]],
             [measurements_[1]],
             [measurements_[2]],
             [measurements_[3]],
+            [measurements_[4]],
+            [measurements_[5]],
         ];
         // Prediction step
-        // x = A * x + B
+        // x = A * x
         let heading_d = self.estimated_heading_d();
         let delta = rotate_degrees_clockwise(
             &Point {
@@ -119,70 +166,143 @@ impl LocationFilter {
         );
         let transition = [
             // A
-            [1.0f32, 0.0f32, 0.0f32, delta.x],
-            [0.0f32, 1.0f32, 0.0f32, delta.y],
-            [0.0f32, 0.0f32, 1.0f32, 0.0f32],
-            [0.0f32, 0.0f32, 0.0f32, 1.0f32],
+            [1.0f32, 0.0f32, 0.0f32, delta.x, 0.0f32, 0.0f32],
+            [0.0f32, 1.0f32, 0.0f32, delta.y, 0.0f32, 0.0f32],
+            [0.0f32, 0.0f32, 1.0f32, 0.0f32, time_diff_s, 0.0f32],
+            [0.0f32, 0.0f32, 0.0f32, 1.0f32, 0.0f32, time_diff_s],
+            [0.0f32, 0.0f32, 0.0f32, 0.0f32, 1.0f32, 0.0f32],
+            [0.0f32, 0.0f32, 0.0f32, 0.0f32, 0.0f32, 1.0f32],
         ];
-        // TODO: Add acceleration and turn values
-        multiply44x41(&transition, &self.estimates, &mut self.out41);
-        self.estimates = self.out41;
-        //print44("1. A=", &transition);
-        //print44("   P=", &self.covariance);
-        //print41("   x=", &self.estimates);
-        //print44("2. H=", observer_matrix);
-        //print41("   z=", &measurements);
-        //print41("3. x=", &self.estimates);
+        multiply66x61(&transition, &self.estimates, &mut self.out61);
+        self.estimates = self.out61;
+        //print66("1. A=", &transition);
+        //print66("   P=", &self.covariance);
+        //print61("   x=", &self.estimates);
+        //print66("2. H=", observer_matrix);
+        //print61("   z=", &measurements);
+        //print61("3. x=", &self.estimates);
 
         // Update uncertainty
         // P = A * P * A' + Q
-        multiply44x44(&transition, &self.covariance, &mut self.out);
+        multiply66x66(&transition, &self.covariance, &mut self.out);
         transpose(&transition, &mut self.out2);
-        multiply44x44(&self.out, &self.out2, &mut self.out3);
+        multiply66x66(&self.out, &self.out2, &mut self.out3);
         add(&self.out3, &self.process_noise, &mut self.out);
         self.covariance = self.out;
-        //print44("4. P=", &self.covariance);
+        //print66("4. P=", &self.covariance);
 
         // Compute the Kalman gain
         // K = P * H' * inv(H * P * H' + R)
-        multiply44x44(observer_matrix, &self.covariance, &mut self.out);
+        multiply66x66(observer_matrix, &self.covariance, &mut self.out);
         transpose(observer_matrix, &mut self.out2); // out2 = H'
-        multiply44x44(&self.out, &self.out2, &mut self.out3);
+        multiply66x66(&self.out, &self.out2, &mut self.out3);
         add(&self.out3, measurement_noise, &mut self.out);
-        //print44("  H * P * H' + R =", &self.out);
+        //print66("  H * P * H' + R =", &self.out);
         invert(&self.out, &mut self.out3); // out3 = inv(H * P * H' + R)
-        multiply44x44(&self.covariance, &self.out2, &mut self.out); // out = P * H'
-        multiply44x44(&self.out, &self.out3, &mut self.kalman_gain);
-        //print44("5. K=", &self.kalman_gain);
-
-        // Determine innovation or residual and update our estimate
-        // x = x + K * (z - H * x)
-        multiply44x41(observer_matrix, &self.estimates, &mut self.out41);
-        subtract41(&measurements, &self.out41, &mut self.out41_2);
-        let mut heading_d = self.out41_2[2][0];
+        multiply66x66(&self.covariance, &self.out2, &mut self.out); // out = P * H'
+        multiply66x66(&self.out, &self.out3, &mut self.kalman_gain);
+        //print66("5. K=", &self.kalman_gain);
+
+        // Determine the innovation
+        // y = z - H * x
+        multiply66x61(observer_matrix, &self.estimates, &mut self.out61);
+        subtract61(&measurements, &self.out61, &mut self.out61_2); // out61_2 = y
+        let mut heading_d = self.out61_2[2][0];
         while heading_d > 180.0 {
             heading_d -= 360.0;
         }
         while heading_d <= -180.0 {
             heading_d += 360.0;
         }
-        self.out41_2[2][0] = heading_d;
+        self.out61_2[2][0] = heading_d;
+
+        // Chi-square gate: reject an implausible measurement (GPS multipath, compass
+        // interference) using its Mahalanobis distance d2 = y' * inv(S) * y rather than
+        // letting it drag the estimate off course. The prediction step above still applies.
+        let mut d2 = 0.0f32;
+        for row in 0..6 {
+            let mut row_sum = 0.0f32;
+            for column in 0..6 {
+                row_sum += self.out61_2[column][0] * self.out3[column][row];
+            }
+            d2 += row_sum * self.out61_2[row][0];
+        }
+        if d2 > chi2_threshold {
+            return false;
+        }
 
-        multiply44x41(&self.kalman_gain, &self.out41_2, &mut self.out41);
-        add41(&self.estimates, &self.out41, &mut self.out41_2);
-        self.estimates = self.out41_2;
+        // x = x + K * y
+        multiply66x61(&self.kalman_gain, &self.out61_2, &mut self.out61);
+        add61(&self.estimates, &self.out61, &mut self.out61_2);
+        self.estimates = self.out61_2;
         self.estimates[2][0] = wrap_degrees(self.estimates[2][0]);
 
-        //print41("6. x=", &self.estimates);
+        //print61("6. x=", &self.estimates);
 
-        // Update the covariance
-        // P = (I - K * H) * P
-        multiply44x44(&self.kalman_gain, observer_matrix, &mut self.out);
+        // Update the covariance using the Joseph stabilized form
+        // P = (I - K * H) * P * (I - K * H)' + K * R * K'
+        // The simpler P = (I - K * H) * P accumulates f32 round-off that can drive P
+        // non-symmetric and non-positive-definite; this form stays symmetric-PSD.
+        multiply66x66(&self.kalman_gain, observer_matrix, &mut self.out); // out = K * H
         let id = identity();
-        subtract44(&id, &self.out, &mut self.out2);
-        multiply44x44(&self.out2, &self.covariance, &mut self.out);
-        self.covariance = self.out;
-        //print44("7. P=", &self.covariance);
+        subtract66(&id, &self.out, &mut self.out2); // out2 = I - K * H
+        transpose(&self.out2, &mut self.out3); // out3 = (I - K * H)'
+        multiply66x66(&self.out2, &self.covariance, &mut self.out); // out = (I - K * H) * P
+        multiply66x66(&self.out, &self.out3, &mut self.out2); // out2 = (I - K * H) * P * (I - K * H)'
+
+        multiply66x66(&self.kalman_gain, measurement_noise, &mut self.out); // out = K * R
+        transpose(&self.kalman_gain, &mut self.out3); // out3 = K'
+        multiply66x66(&self.out, &self.out3, &mut self.covariance); // covariance = K * R * K'
+
+        add(&self.out2, &self.covariance, &mut self.out); // out = Joseph-form P
+
+        // Force exact symmetry; round-off can otherwise leave P[i][j] != P[j][i].
+        transpose(&self.out, &mut self.out3);
+        add(&self.out, &self.out3, &mut self.out2);
+        for row in 0..6 {
+            for column in 0..6 {
+                self.out2[row][column] *= 0.5f32;
+            }
+        }
+        self.covariance = self.out2;
+        //print66("7. P=", &self.covariance);
+
+        true
+    }
+
+    /**
+     * Fuses a tilt-compensated compass heading reading into the filter. Returns false if the
+     * reading was rejected by the chi-square gate as implausible.
+     */
+    #[allow(dead_code)]
+    pub fn update_compass(&mut self, heading_d: f32, std_dev: f32, time_diff_s: f32) -> bool {
+        let observer_matrix = self.compass_observer_matrix;
+        let mut measurement_noise = self.compass_measurement_noise;
+        measurement_noise[2][2] = std_dev * std_dev;
+        self.update(
+            &[0.0, 0.0, heading_d, 0.0, 0.0, 0.0],
+            &observer_matrix,
+            &measurement_noise,
+            time_diff_s,
+            COMPASS_CHI2_THRESHOLD,
+        )
+    }
+
+    /**
+     * Fuses a gyroscope yaw rate and accelerometer forward acceleration reading into the filter.
+     * Returns false if the reading was rejected by the chi-square gate as implausible.
+     */
+    #[allow(dead_code)]
+    pub fn update_imu(&mut self, yaw_rate_d_s: f32, accel_m_s2: f32, time_diff_s: f32) -> bool {
+        let observer_matrix = self.imu_observer_matrix;
+        let measurement_noise = self.imu_measurement_noise;
+        self.update(
+            &[0.0, 0.0, 0.0, 0.0, yaw_rate_d_s, accel_m_s2],
+            &observer_matrix,
+            &measurement_noise,
+            time_diff_s,
+            MULTI_STATE_CHI2_THRESHOLD,
+        )
     }
 
     #[allow(dead_code)]
@@ -198,22 +318,34 @@ impl LocationFilter {
     pub fn estimated_speed_m_s(&self) -> f32 {
         self.estimates[3][0]
     }
+
+    #[allow(dead_code)]
+    pub fn estimated_yaw_rate_d_s(&self) -> f32 {
+        self.estimates[4][0]
+    }
+
+    #[allow(dead_code)]
+    pub fn estimated_accel_m_s2(&self) -> f32 {
+        self.estimates[5][0]
+    }
 }
 
-fn identity() -> [[f32; 4]; 4] {
+fn identity() -> [[f32; 6]; 6] {
     [
-        [1f32, 0f32, 0f32, 0f32],
-        [0f32, 1f32, 0f32, 0f32],
-        [0f32, 0f32, 1f32, 0f32],
-        [0f32, 0f32, 0f32, 1f32],
+        [1f32, 0f32, 0f32, 0f32, 0f32, 0f32],
+        [0f32, 1f32, 0f32, 0f32, 0f32, 0f32],
+        [0f32, 0f32, 1f32, 0f32, 0f32, 0f32],
+        [0f32, 0f32, 0f32, 1f32, 0f32, 0f32],
+        [0f32, 0f32, 0f32, 0f32, 1f32, 0f32],
+        [0f32, 0f32, 0f32, 0f32, 0f32, 1f32],
     ]
 }
 
-fn multiply44x44(a: &[[f32; 4]; 4], b: &[[f32; 4]; 4], out: &mut [[f32; 4]; 4]) {
+fn multiply66x66(a: &[[f32; 6]; 6], b: &[[f32; 6]; 6], out: &mut [[f32; 6]; 6]) {
     for row in 0..a.len() {
         for column in 0..a[0].len() {
             let mut sum: f32 = 0.0;
-            for iter in 0..4 {
+            for iter in 0..6 {
                 sum += a[row][iter] * b[iter][column];
             }
             out[row][column] = sum;
@@ -221,11 +353,11 @@ fn multiply44x44(a: &[[f32; 4]; 4], b: &[[f32; 4]; 4], out: &mut [[f32; 4]; 4])
     }
 }
 
-fn multiply44x41(a: &[[f32; 4]; 4], b: &[[f32; 1]; 4], out: &mut [[f32; 1]; 4]) {
+fn multiply66x61(a: &[[f32; 6]; 6], b: &[[f32; 1]; 6], out: &mut [[f32; 1]; 6]) {
     for row in 0..a.len() {
         for column in 0..b[0].len() {
             let mut sum: f32 = 0.0;
-            for iter in 0..4 {
+            for iter in 0..6 {
                 sum += a[row][iter] * b[iter][column];
             }
             out[row][column] = sum;
@@ -233,7 +365,7 @@ fn multiply44x41(a: &[[f32; 4]; 4], b: &[[f32; 1]; 4], out: &mut [[f32; 1]; 4])
     }
 }
 
-fn add(a: &[[f32; 4]; 4], b: &[[f32; 4]; 4], out: &mut [[f32; 4]; 4]) {
+fn add(a: &[[f32; 6]; 6], b: &[[f32; 6]; 6], out: &mut [[f32; 6]; 6]) {
     for row in 0..a.len() {
         for column in 0..a[0].len() {
             out[row][column] = a[row][column] + b[row][column];
@@ -241,7 +373,7 @@ fn add(a: &[[f32; 4]; 4], b: &[[f32; 4]; 4], out: &mut [[f32; 4]; 4]) {
     }
 }
 
-fn subtract44(a: &[[f32; 4]; 4], b: &[[f32; 4]; 4], out: &mut [[f32; 4]; 4]) {
+fn subtract66(a: &[[f32; 6]; 6], b: &[[f32; 6]; 6], out: &mut [[f32; 6]; 6]) {
     for row in 0..a.len() {
         for column in 0..a[0].len() {
             out[row][column] = a[row][column] - b[row][column];
@@ -249,7 +381,7 @@ fn subtract44(a: &[[f32; 4]; 4], b: &[[f32; 4]; 4], out: &mut [[f32; 4]; 4]) {
     }
 }
 
-fn subtract41(a: &[[f32; 1]; 4], b: &[[f32; 1]; 4], out: &mut [[f32; 1]; 4]) {
+fn subtract61(a: &[[f32; 1]; 6], b: &[[f32; 1]; 6], out: &mut [[f32; 1]; 6]) {
     for row in 0..a.len() {
         for column in 0..a[0].len() {
             out[row][column] = a[row][column] - b[row][column];
@@ -257,7 +389,7 @@ fn subtract41(a: &[[f32; 1]; 4], b: &[[f32; 1]; 4], out: &mut [[f32; 1]; 4]) {
     }
 }
 
-fn add41(a: &[[f32; 1]; 4], b: &[[f32; 1]; 4], out: &mut [[f32; 1]; 4]) {
+fn add61(a: &[[f32; 1]; 6], b: &[[f32; 1]; 6], out: &mut [[f32; 1]; 6]) {
     for row in 0..a.len() {
         for column in 0..a[0].len() {
             out[row][column] = a[row][column] + b[row][column];
@@ -265,10 +397,13 @@ fn add41(a: &[[f32; 1]; 4], b: &[[f32; 1]; 4], out: &mut [[f32; 1]; 4]) {
     }
 }
 
-fn invert(a: &[[f32; 4]; 4], out: &mut [[f32; 4]; 4]) {
+fn invert(a: &[[f32; 6]; 6], out: &mut [[f32; 6]; 6]) {
     if _invert(a, out) == false {
-        // Just fudge it
-        let mut new_a: [[f32; 4]; 4] = [[0f32; 4]; 4];
+        // Just fudge it. The Joseph-form covariance update keeps P itself well-conditioned,
+        // but H * P * H' + R is still structurally singular whenever observer_matrix doesn't
+        // observe every state (e.g. the compass and IMU updates only fill in one or two rows),
+        // which leaves zero rows/columns in the matrix being inverted here.
+        let mut new_a: [[f32; 6]; 6] = [[0f32; 6]; 6];
         for row in 0..a.len() {
             for column in 0..a[0].len() {
                 if row == column && a[row][column] == 0.0f32 {
@@ -283,51 +418,56 @@ fn invert(a: &[[f32; 4]; 4], out: &mut [[f32; 4]; 4]) {
     }
 }
 
-fn _invert(a: &[[f32; 4]; 4], out: &mut [[f32; 4]; 4]) -> bool {
-    let s0: f32 = a[0][0] * a[1][1] - a[1][0] * a[0][1];
-    let s1: f32 = a[0][0] * a[1][2] - a[1][0] * a[0][2];
-    let s2: f32 = a[0][0] * a[1][3] - a[1][0] * a[0][3];
-    let s3: f32 = a[0][1] * a[1][2] - a[1][1] * a[0][2];
-    let s4: f32 = a[0][1] * a[1][3] - a[1][1] * a[0][3];
-    let s5: f32 = a[0][2] * a[1][3] - a[1][2] * a[0][3];
-
-    let c5: f32 = a[2][2] * a[3][3] - a[3][2] * a[2][3];
-    let c4: f32 = a[2][1] * a[3][3] - a[3][1] * a[2][3];
-    let c3: f32 = a[2][1] * a[3][2] - a[3][1] * a[2][2];
-    let c2: f32 = a[2][0] * a[3][3] - a[3][0] * a[2][3];
-    let c1: f32 = a[2][0] * a[3][2] - a[3][0] * a[2][2];
-    let c0: f32 = a[2][0] * a[3][1] - a[3][0] * a[2][1];
-
-    let det = s0 * c5 - s1 * c4 + s2 * c3 + s3 * c2 - s4 * c1 + s5 * c0;
-    if det == 0.0 {
-        return false;
-    }
-    let invdet: f32 = 1.0f32 / det;
-
-    out[0][0] = (a[1][1] * c5 - a[1][2] * c4 + a[1][3] * c3) * invdet;
-    out[0][1] = (-a[0][1] * c5 + a[0][2] * c4 - a[0][3] * c3) * invdet;
-    out[0][2] = (a[3][1] * s5 - a[3][2] * s4 + a[3][3] * s3) * invdet;
-    out[0][3] = (-a[2][1] * s5 + a[2][2] * s4 - a[2][3] * s3) * invdet;
-
-    out[1][0] = (-a[1][0] * c5 + a[1][2] * c2 - a[1][3] * c1) * invdet;
-    out[1][1] = (a[0][0] * c5 - a[0][2] * c2 + a[0][3] * c1) * invdet;
-    out[1][2] = (-a[3][0] * s5 + a[3][2] * s2 - a[3][3] * s1) * invdet;
-    out[1][3] = (a[2][0] * s5 - a[2][2] * s2 + a[2][3] * s1) * invdet;
+// Gauss-Jordan elimination with partial pivoting. The closed-form cofactor expansion used by
+// the old 4x4 version doesn't scale to 6x6 by hand, so this runs the augmented-matrix reduction
+// instead: row-reduce [a | I] until the left half is I, and the right half is a^-1.
+fn _invert(a: &[[f32; 6]; 6], out: &mut [[f32; 6]; 6]) -> bool {
+    let mut work = *a;
+    let mut result = identity();
+
+    for pivot in 0..6 {
+        let mut pivot_row = pivot;
+        let mut pivot_value = work[pivot][pivot].abs();
+        for row in (pivot + 1)..6 {
+            if work[row][pivot].abs() > pivot_value {
+                pivot_row = row;
+                pivot_value = work[row][pivot].abs();
+            }
+        }
+        if pivot_value == 0.0f32 {
+            return false;
+        }
+        if pivot_row != pivot {
+            work.swap(pivot, pivot_row);
+            result.swap(pivot, pivot_row);
+        }
 
-    out[2][0] = (a[1][0] * c4 - a[1][1] * c2 + a[1][3] * c0) * invdet;
-    out[2][1] = (-a[0][0] * c4 + a[0][1] * c2 - a[0][3] * c0) * invdet;
-    out[2][2] = (a[3][0] * s4 - a[3][1] * s2 + a[3][3] * s0) * invdet;
-    out[2][3] = (-a[2][0] * s4 + a[2][1] * s2 - a[2][3] * s0) * invdet;
+        let scale = work[pivot][pivot];
+        for column in 0..6 {
+            work[pivot][column] /= scale;
+            result[pivot][column] /= scale;
+        }
 
-    out[3][0] = (-a[1][0] * c3 + a[1][1] * c1 - a[1][2] * c0) * invdet;
-    out[3][1] = (a[0][0] * c3 - a[0][1] * c1 + a[0][2] * c0) * invdet;
-    out[3][2] = (-a[3][0] * s3 + a[3][1] * s1 - a[3][2] * s0) * invdet;
-    out[3][3] = (a[2][0] * s3 - a[2][1] * s1 + a[2][2] * s0) * invdet;
+        for row in 0..6 {
+            if row == pivot {
+                continue;
+            }
+            let factor = work[row][pivot];
+            if factor == 0.0f32 {
+                continue;
+            }
+            for column in 0..6 {
+                work[row][column] -= factor * work[pivot][column];
+                result[row][column] -= factor * result[pivot][column];
+            }
+        }
+    }
 
-    return true;
+    *out = result;
+    true
 }
 
-fn transpose(a: &[[f32; 4]; 4], out: &mut [[f32; 4]; 4]) {
+fn transpose(a: &[[f32; 6]; 6], out: &mut [[f32; 6]; 6]) {
     for row in 0..a.len() {
         for column in 0..a[0].len() {
             out[row][column] = a[column][row];
@@ -335,7 +475,7 @@ fn transpose(a: &[[f32; 4]; 4], out: &mut [[f32; 4]; 4]) {
     }
 }
 
-//fn print44(message: &str, a: &[[f32; 4]; 4]) {
+//fn print66(message: &str, a: &[[f32; 6]; 6]) {
 //    println!("{}", message);
 //    for row in 0..a.len() {
 //        for column in 0..a[0].len() {
@@ -346,7 +486,7 @@ fn transpose(a: &[[f32; 4]; 4], out: &mut [[f32; 4]; 4]) {
 //}
 //
 //
-//fn print41(message: &str, a: &[[f32; 1]; 4]) {
+//fn print61(message: &str, a: &[[f32; 1]; 6]) {
 //    print!("{}", message);
 //    for row in 0..a.len() {
 //        print!("{}\t", a[row][0]);
@@ -356,10 +496,10 @@ fn transpose(a: &[[f32; 4]; 4], out: &mut [[f32; 4]; 4]) {
 
 #[cfg(test)]
 mod tests {
-    use super::{add, identity, invert, multiply44x44, LocationFilter};
+    use super::{add, identity, invert, multiply66x66, LocationFilter, COMPASS_CHI2_THRESHOLD};
     use telemetry::{rotate_degrees_clockwise, Point};
 
-    fn assert_equal(a: &[[f32; 4]; 4], b: &[[f32; 4]; 4]) {
+    fn assert_equal(a: &[[f32; 6]; 6], b: &[[f32; 6]; 6]) {
         for row in 0..a.len() {
             for column in 0..a[0].len() {
                 let diff = (a[row][column] - b[row][column]).abs();
@@ -379,27 +519,34 @@ mod tests {
     }
 
     #[test]
-    fn test_multiply44x44() {
-        let mut out = [[0.0f32; 4]; 4];
+    fn test_multiply66x66() {
+        let mut out = [[0.0f32; 6]; 6];
         let identity_ = identity();
 
-        multiply44x44(&identity_, &identity_, &mut out);
+        multiply66x66(&identity_, &identity_, &mut out);
         assert_equal(&out, &identity_);
 
-        let array = [[1.0f32; 4], [2.0f32; 4], [3.0f32; 4], [4.0f32; 4]];
-        multiply44x44(&identity_, &array, &mut out);
+        let array = [
+            [1.0f32; 6],
+            [2.0f32; 6],
+            [3.0f32; 6],
+            [4.0f32; 6],
+            [5.0f32; 6],
+            [6.0f32; 6],
+        ];
+        multiply66x66(&identity_, &array, &mut out);
         assert_equal(&out, &array);
-        multiply44x44(&array, &identity_, &mut out);
+        multiply66x66(&array, &identity_, &mut out);
         assert_equal(&out, &array);
 
-        multiply44x44(&array, &array, &mut out);
-        assert!(out[0][0] == 10.0);
-        assert!(out[1][0] == 20.0);
+        multiply66x66(&array, &array, &mut out);
+        assert!(out[0][0] == 21.0);
+        assert!(out[1][0] == 42.0);
     }
 
     #[test]
     fn test_add() {
-        let mut out = [[0.0f32; 4]; 4];
+        let mut out = [[0.0f32; 6]; 6];
         let identity_ = identity();
 
         add(&identity_, &identity_, &mut out);
@@ -418,7 +565,7 @@ mod tests {
 
     #[test]
     fn test_invert() {
-        let mut out = [[0.0f32; 4]; 4];
+        let mut out = [[0.0f32; 6]; 6];
         let identity_ = identity();
 
         invert(&identity_, &mut out);
@@ -433,7 +580,7 @@ mod tests {
 
         invert(&array, &mut out);
         let copy = out;
-        multiply44x44(&array, &copy, &mut out);
+        multiply66x66(&array, &copy, &mut out);
         assert_equal(&out, &identity_);
     }
 
@@ -457,7 +604,7 @@ mod tests {
         // we'll just manually set it now
         location_filter.estimates[3][0] = speed_m_s;
 
-        let measurements: [f32; 4] = [0.0, 0.0, heading_d, 0.0];
+        let measurements: [f32; 6] = [0.0, 0.0, heading_d, 0.0, 0.0, 0.0];
 
         let seconds = 5u32;
         let compass_observer_matrix = location_filter.compass_observer_matrix;
@@ -468,6 +615,7 @@ mod tests {
                 &compass_observer_matrix,
                 &compass_measurement_noise,
                 1.0f32,
+                COMPASS_CHI2_THRESHOLD,
             );
         }
 
@@ -0,0 +1,104 @@
+//! Lets an operator raise or lower the file log's verbosity and toggle live log tailing over
+//! the command socket at runtime, instead of baking both in for the lifetime of the process.
+
+use log::{Log, Metadata, Record, SetLoggerError};
+use simplelog::{Config, TermLogger, WriteLogger};
+use std::fs::File;
+use std::io::Write;
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+pub use log::LevelFilter;
+
+static FILE_LEVEL: AtomicUsize = AtomicUsize::new(LevelFilter::Info as usize);
+static TAIL_SINK: Mutex<Option<UnixStream>> = Mutex::new(None);
+
+/// Combines a fixed `Warn` terminal logger with a file logger whose level can be changed after
+/// startup, and echoes logged lines out a Unix socket while `log-tail` is enabled.
+struct RuntimeLogger {
+    term_logger: Box<TermLogger>,
+    file_logger: Option<Box<WriteLogger<File>>>,
+}
+
+impl Log for RuntimeLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        self.term_logger.log(record);
+
+        if record.level() > file_level() {
+            return;
+        }
+        if let Some(ref file_logger) = self.file_logger {
+            file_logger.log(record);
+        }
+        if let Ok(mut sink) = TAIL_SINK.lock() {
+            if let Some(ref mut stream) = *sink {
+                let _ = write!(stream, "{} - {}\n", record.level(), record.args());
+            }
+        }
+    }
+
+    fn flush(&self) {
+        self.term_logger.flush();
+        if let Some(ref file_logger) = self.file_logger {
+            file_logger.flush();
+        }
+    }
+}
+
+/// Installs the runtime logger: a `Warn`-level terminal logger plus, if `file` was opened
+/// successfully, a file logger starting at `initial_level` and adjustable afterward via
+/// `set_file_level`.
+pub fn init(initial_level: LevelFilter, file: Option<File>) -> Result<(), SetLoggerError> {
+    FILE_LEVEL.store(initial_level as usize, Ordering::SeqCst);
+    let term_logger = TermLogger::new(LevelFilter::Warn, Config::default()).unwrap();
+    let file_logger = file.map(|file| WriteLogger::new(LevelFilter::Trace, Config::default(), file));
+
+    log::set_max_level(LevelFilter::Trace);
+    log::set_boxed_logger(Box::new(RuntimeLogger {
+        term_logger: term_logger,
+        file_logger: file_logger,
+    }))
+}
+
+fn file_level() -> LevelFilter {
+    match FILE_LEVEL.load(Ordering::SeqCst) {
+        n if n == LevelFilter::Off as usize => LevelFilter::Off,
+        n if n == LevelFilter::Error as usize => LevelFilter::Error,
+        n if n == LevelFilter::Warn as usize => LevelFilter::Warn,
+        n if n == LevelFilter::Info as usize => LevelFilter::Info,
+        n if n == LevelFilter::Debug as usize => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// Changes the file logger's active level at runtime, e.g. in response to a `log-level` command
+/// on the Unix command socket. Does not affect the `Warn`-level terminal logger.
+pub fn set_file_level(level: LevelFilter) {
+    FILE_LEVEL.store(level as usize, Ordering::SeqCst);
+}
+
+/// Parses a `log-level` command argument into a `LevelFilter`, e.g. `"debug"` -> `Debug`.
+pub fn parse_level(name: &str) -> Option<LevelFilter> {
+    match name {
+        "off" => Some(LevelFilter::Off),
+        "error" => Some(LevelFilter::Error),
+        "warn" => Some(LevelFilter::Warn),
+        "info" => Some(LevelFilter::Info),
+        "debug" => Some(LevelFilter::Debug),
+        "trace" => Some(LevelFilter::Trace),
+        _ => None,
+    }
+}
+
+/// Starts or stops echoing newly logged lines out `stream`, e.g. in response to a `log-tail`
+/// command on the Unix command socket. Pass `None` to stop tailing.
+pub fn set_tail_sink(stream: Option<UnixStream>) {
+    if let Ok(mut sink) = TAIL_SINK.lock() {
+        *sink = stream;
+    }
+}
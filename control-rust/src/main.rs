@@ -5,13 +5,18 @@ extern crate chrono;
 extern crate enum_primitive;
 extern crate getopts;
 extern crate num;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
 extern crate simplelog;
 
+use config::Config;
 use getopts::{Matches, Options};
-use simplelog::{CombinedLogger, Config, LevelFilter, TermLogger, WriteLogger};
+use log_control::LevelFilter;
 use std::error::Error;
 use std::fs::File;
-use std::io::Read;
+use std::io::{ErrorKind, Read};
 use std::os::unix::net::UnixStream;
 use std::path::Path;
 use std::str::from_utf8;
@@ -21,24 +26,36 @@ use std::time::Duration;
 
 use control::Control;
 use filtered_telemetry::FilteredTelemetry;
-use kml_waypoint_generator::KmlWaypointGenerator;
+use kml_waypoint_generator::{KmlWaypointGenerator, VisitOrder};
+use mqtt_bridge::{MqttBridge, COMMAND_TOPIC, TELEMETRY_TOPIC};
 use socket_driver::SocketDriver;
-use telemetry::TelemetryState;
+use telemetry::{latitude_longitude_to_point, TelemetryState};
 use telemetry_message::{CommandMessage, TelemetryMessage};
 use telemetry_provider::TelemetryProvider;
 
+mod compass_calibration;
+mod config;
 mod control;
 mod driver;
+mod ecef;
 mod filtered_telemetry;
+mod kd_tree;
 mod kml_waypoint_generator;
 mod location_filter;
+mod log_control;
+mod mixer;
+mod mqtt_bridge;
 mod nmea;
+mod serial_telemetry_downlink;
 mod socket_driver;
+mod sup800f;
 mod telemetry;
 mod telemetry_message;
 mod telemetry_provider;
 mod termios;
+mod ubx;
 mod waypoint_generator;
+mod waypoint_graph;
 
 macro_rules! warn_err {
     ($option:expr) => {
@@ -56,6 +73,14 @@ fn main() {
     };
     info!("Starting up");
 
+    let config = match options.opt_str("config") {
+        Some(config_file_name) => match config::load(&config_file_name) {
+            Ok(config) => config,
+            Err(e) => panic!("Unable to load config file: {}", e),
+        },
+        None => Config::default(),
+    };
+
     let mut quitters = Vec::new();
 
     let (request_telemetry_tx, request_telemetry_rx) = channel();
@@ -63,6 +88,7 @@ fn main() {
     let (command_tx, command_rx) = channel();
     let (quit_command_tx, quit_command_rx) = channel();
     quitters.push(quit_command_tx);
+    let (telemetry_message_tx, telemetry_message_rx) = channel();
 
     // TODO: Send quit when Ctrl + C is pressed
     let mut join_handles = Vec::new();
@@ -75,43 +101,70 @@ fn main() {
             Ok(throttle_value) => throttle_value,
             Err(_) => panic!("Invalid throttle, should be between 0.25 and 1.0"),
         },
-        None => 1.0,
+        None => config.max_throttle,
     };
 
     join_handles.push(spawn_control(
         &path_file_name,
         max_throttle,
+        config.restart_on_start,
+        config.max_throttle_delta_per_s,
+        config.max_steering_delta_per_s,
+        config.stop_duration_ms,
+        config.cross_track_corridor_m,
+        config.max_cross_track_m,
+        config.nearest_first_waypoints,
+        config.driver_socket_path.clone(),
         request_telemetry_tx,
         telemetry_rx,
+        telemetry_message_tx.clone(),
         command_rx,
         quit_command_rx,
     ));
 
-    let (telemetry_message_tx, telemetry_message_rx) = channel();
     let (quit_termio_tx, quit_termio_rx) = channel();
     quitters.push(quit_termio_tx);
     join_handles.push(spawn_telemetry_provider(
+        config.binary_frames_per_gps_fix,
         telemetry_message_tx,
         quit_termio_rx,
     ));
 
-    let (quit_telemetry_tx, quit_telemetry_rx) = channel();
-
-    join_handles.push(spawn_telemetry(
-        request_telemetry_rx,
-        telemetry_tx,
-        telemetry_message_rx,
-        quit_telemetry_rx,
-    ));
-    quitters.push(quit_telemetry_tx);
+    let mut telemetry_requesters = vec![(request_telemetry_rx, telemetry_tx)];
 
     let (quit_command_message_tx, quit_command_message_rx) = channel();
     quitters.push(quit_command_message_tx);
     join_handles.push(spawn_command_message_listener(
-        command_tx,
+        config.command_socket_path.clone(),
+        command_tx.clone(),
         quit_command_message_rx,
     ));
 
+    let mqtt_broker = options.opt_str("mqtt-broker").or_else(|| config.mqtt_broker.clone());
+    if let Some(mqtt_broker) = mqtt_broker {
+        let (request_mqtt_telemetry_tx, request_mqtt_telemetry_rx) = channel();
+        let (mqtt_telemetry_tx, mqtt_telemetry_rx) = channel();
+        let (quit_mqtt_tx, quit_mqtt_rx) = channel();
+        quitters.push(quit_mqtt_tx);
+        telemetry_requesters.push((request_mqtt_telemetry_rx, mqtt_telemetry_tx));
+        join_handles.push(spawn_mqtt_bridge(
+            mqtt_broker,
+            request_mqtt_telemetry_tx,
+            mqtt_telemetry_rx,
+            command_tx,
+            quit_mqtt_rx,
+        ));
+    }
+
+    let (quit_telemetry_tx, quit_telemetry_rx) = channel();
+    quitters.push(quit_telemetry_tx);
+    join_handles.push(spawn_telemetry(
+        &config,
+        telemetry_requesters,
+        telemetry_message_rx,
+        quit_telemetry_rx,
+    ));
+
     sleep(Duration::from_millis(1000));
 
     for quitter in quitters {
@@ -131,17 +184,41 @@ fn main() {
 fn spawn_control(
     path_file_name: &str,
     max_throttle: f32,
+    restart_on_start: bool,
+    max_throttle_delta_per_s: f32,
+    max_steering_delta_per_s: f32,
+    stop_duration_ms: u64,
+    cross_track_corridor_m: f32,
+    max_cross_track_m: f32,
+    nearest_first_waypoints: bool,
+    driver_socket_path: String,
     request_telemetry_tx: Sender<()>,
     telemetry_rx: Receiver<TelemetryState>,
+    telemetry_message_tx: Sender<TelemetryMessage>,
     command_rx: Receiver<CommandMessage>,
     quit_rx: Receiver<()>,
 ) -> JoinHandle<()> {
-    let waypoint_generator = Box::new(KmlWaypointGenerator::new(&path_file_name));
+    let visit_order = if nearest_first_waypoints {
+        VisitOrder::NearestFirst
+    } else {
+        VisitOrder::Sequential
+    };
+    let waypoint_generator = match KmlWaypointGenerator::new(&path_file_name, visit_order) {
+        Ok(waypoint_generator) => Box::new(waypoint_generator),
+        Err(e) => panic!("Unable to load waypoints file \"{}\": {:?}", path_file_name, e),
+    };
     spawn(move || {
-        let driver = Box::new(SocketDriver::new(max_throttle));
+        let driver = Box::new(SocketDriver::new(max_throttle, &driver_socket_path));
         let mut control = Control::new(
+            restart_on_start,
+            max_throttle_delta_per_s,
+            max_steering_delta_per_s,
+            stop_duration_ms,
+            cross_track_corridor_m,
+            max_cross_track_m,
             request_telemetry_tx,
             telemetry_rx,
+            telemetry_message_tx,
             waypoint_generator,
             driver,
         );
@@ -151,97 +228,216 @@ fn spawn_control(
 }
 
 fn spawn_telemetry_provider(
+    binary_frames_per_gps_fix: i32,
     telemetry_message_tx: Sender<TelemetryMessage>,
     quit_rx: Receiver<()>,
 ) -> JoinHandle<()> {
     spawn(move || {
-        let mut provider = TelemetryProvider::new(telemetry_message_tx);
+        let mut provider = TelemetryProvider::new(telemetry_message_tx, binary_frames_per_gps_fix);
         provider.run(quit_rx);
     })
 }
 
 fn spawn_telemetry(
-    request_telemetry_rx: Receiver<()>,
-    telemetry_tx: Sender<TelemetryState>,
+    config: &Config,
+    telemetry_requesters: Vec<(Receiver<()>, Sender<TelemetryState>)>,
     telemetry_message_rx: Receiver<TelemetryMessage>,
     quit_rx: Receiver<()>,
 ) -> JoinHandle<()> {
+    let start = latitude_longitude_to_point(config.start_latitude, config.start_longitude);
+    let start_heading_d = config.start_heading_d;
+    let gps_std_dev_m = config.gps_std_dev_m;
+    let compass_std_dev_d = config.compass_std_dev_d;
     spawn(move || {
-        let mut telemetry = FilteredTelemetry::new();
+        let mut telemetry =
+            FilteredTelemetry::new(start, start_heading_d, gps_std_dev_m, compass_std_dev_d);
         telemetry.run(
-            request_telemetry_rx,
-            telemetry_tx,
+            telemetry_requesters,
             telemetry_message_rx,
             quit_rx,
         );
     })
 }
 
-fn spawn_command_message_listener(
+fn spawn_mqtt_bridge(
+    broker_address: String,
+    request_telemetry_tx: Sender<()>,
+    telemetry_rx: Receiver<TelemetryState>,
     command_tx: Sender<CommandMessage>,
     quit_rx: Receiver<()>,
 ) -> JoinHandle<()> {
     spawn(move || {
-        // Keep listening for start and stop messages on a Unix socket
-        let server = Path::new("/tmp/command-socket");
-        let mut socket = match UnixStream::connect(&server) {
-            Ok(socket) => socket,
+        let mut bridge = match MqttBridge::new(&broker_address, "sparkfun-avc", COMMAND_TOPIC) {
+            Ok(bridge) => bridge,
             Err(e) => {
-                error!("Unable to open Unix socket: {}", e);
+                error!("Unable to connect to MQTT broker: {}", e);
                 return;
             }
         };
 
-        match socket.set_read_timeout(Some(Duration::from_millis(1000u64))) {
-            Ok(()) => (),
-            Err(err) => error!("Unable to set read timeout: {}", err),
-        }
-        let mut message_bytes = Vec::<u8>::new();
         loop {
-            let mut buffer: [u8; 20] = [0; 20];
-            loop {
-                match socket.read(&mut buffer) {
-                    Ok(size) => if size > 0 {
-                        for index in 0..size {
-                            message_bytes.push(buffer[index])
-                        }
-                        if message_bytes[message_bytes.len() - 1] == '\n' as u8 {
-                            break;
-                        }
-                    },
-                    Err(e) => {
-                        error!("Error reading from domain socket: {}", e);
-                    }
+            match quit_rx.try_recv() {
+                Ok(_) => {
+                    info!("MQTT bridge thread shutting down");
+                    return;
                 }
+                Err(_) => (),
+            }
+
+            warn_err!(request_telemetry_tx.send(()));
+            if let Ok(state) = telemetry_rx.try_recv() {
+                warn_err!(bridge.publish_telemetry(TELEMETRY_TOPIC, &state));
             }
-            match from_utf8(&message_bytes) {
-                Ok(message) => {
-                    info!("Received message \"{}\" on Unix socket", message);
+
+            match bridge.poll_command() {
+                Ok(Some(message)) => {
+                    info!("Received message \"{}\" on MQTT", message);
                     if message == "start" {
                         warn_err!(command_tx.send(CommandMessage::Start));
                     } else if message == "stop" {
                         warn_err!(command_tx.send(CommandMessage::Stop));
+                    } else if message == "pause" {
+                        warn_err!(command_tx.send(CommandMessage::Pause));
+                    } else if message == "resume" {
+                        warn_err!(command_tx.send(CommandMessage::Resume));
                     } else if message == "calibrate-compass" {
                         warn_err!(command_tx.send(CommandMessage::CalibrateCompass));
                     } else {
-                        warn!("Unknown message \"{}\" on Unix socket", message);
+                        warn!("Unknown message \"{}\" on MQTT", message);
                     }
                 }
-                Err(_) => error!("Unable to interpret bytes from Unix socket as UTF8"),
+                Ok(None) => (),
+                Err(e) => {
+                    error!("Error reading from MQTT broker: {}", e);
+                    return;
+                }
             }
-            message_bytes.clear();
 
-            match quit_rx.try_recv() {
-                Ok(_) => {
+            sleep(Duration::from_millis(1000));
+        }
+    })
+}
+
+/// Whether the command listener connection loop stopped because it was told to quit, or
+/// because the socket was lost and should be reconnected.
+enum ListenerExit {
+    Quit,
+    SocketLost,
+}
+
+fn spawn_command_message_listener(
+    command_socket_path: String,
+    command_tx: Sender<CommandMessage>,
+    quit_rx: Receiver<()>,
+) -> JoinHandle<()> {
+    spawn(move || {
+        let mut backoff_ms = 100u64;
+        loop {
+            match run_command_message_listener(&command_socket_path, &command_tx, &quit_rx) {
+                ListenerExit::Quit => {
                     info!("Command message thread shutting down");
                     return;
                 }
-                Err(_) => (),
+                ListenerExit::SocketLost => {
+                    warn!("Lost Unix command socket, reconnecting in {}ms", backoff_ms);
+                    sleep(Duration::from_millis(backoff_ms));
+                    backoff_ms = (backoff_ms * 2).min(5000);
+                }
             }
         }
     })
 }
 
+/// Connects to the command socket and keeps listening for start/stop messages on it until told
+/// to quit or until the connection is lost, in which case the caller should reconnect.
+fn run_command_message_listener(
+    command_socket_path: &str,
+    command_tx: &Sender<CommandMessage>,
+    quit_rx: &Receiver<()>,
+) -> ListenerExit {
+    let server = Path::new(command_socket_path);
+    let mut socket = match UnixStream::connect(&server) {
+        Ok(socket) => socket,
+        Err(e) => {
+            error!("Unable to open Unix socket: {}", e);
+            return ListenerExit::SocketLost;
+        }
+    };
+
+    match socket.set_read_timeout(Some(Duration::from_millis(1000u64))) {
+        Ok(()) => (),
+        Err(err) => error!("Unable to set read timeout: {}", err),
+    }
+    let mut message_bytes = Vec::<u8>::new();
+    loop {
+        let mut buffer: [u8; 20] = [0; 20];
+        loop {
+            match socket.read(&mut buffer) {
+                Ok(0) => return ListenerExit::SocketLost,
+                Ok(size) => {
+                    for index in 0..size {
+                        message_bytes.push(buffer[index])
+                    }
+                    if message_bytes[message_bytes.len() - 1] == '\n' as u8 {
+                        break;
+                    }
+                },
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+                    match quit_rx.try_recv() {
+                        Ok(_) => return ListenerExit::Quit,
+                        Err(_) => (),
+                    }
+                }
+                Err(e) => {
+                    error!("Error reading from domain socket: {}", e);
+                    return ListenerExit::SocketLost;
+                }
+            }
+        }
+        match from_utf8(&message_bytes) {
+            Ok(message) => {
+                let message = message.trim();
+                info!("Received message \"{}\" on Unix socket", message);
+                if message == "start" {
+                    warn_err!(command_tx.send(CommandMessage::Start));
+                } else if message == "stop" {
+                    warn_err!(command_tx.send(CommandMessage::Stop));
+                } else if message == "pause" {
+                    warn_err!(command_tx.send(CommandMessage::Pause));
+                } else if message == "resume" {
+                    warn_err!(command_tx.send(CommandMessage::Resume));
+                } else if message == "calibrate-compass" {
+                    warn_err!(command_tx.send(CommandMessage::CalibrateCompass));
+                } else if message.starts_with("log-level ") {
+                    let level_name = &message["log-level ".len()..];
+                    match log_control::parse_level(level_name) {
+                        Some(level) => {
+                            info!("Setting log level to {}", level);
+                            log_control::set_file_level(level);
+                        }
+                        None => warn!("Unknown log level \"{}\"", level_name),
+                    }
+                } else if message == "log-tail on" {
+                    info!("Starting log tail on command socket");
+                    log_control::set_tail_sink(socket.try_clone().ok());
+                } else if message == "log-tail off" {
+                    info!("Stopping log tail on command socket");
+                    log_control::set_tail_sink(None);
+                } else {
+                    warn!("Unknown message \"{}\" on Unix socket", message);
+                }
+            }
+            Err(_) => error!("Unable to interpret bytes from Unix socket as UTF8"),
+        }
+        message_bytes.clear();
+
+        match quit_rx.try_recv() {
+            Ok(_) => return ListenerExit::Quit,
+            Err(_) => (),
+        }
+    }
+}
+
 fn handle_opts() -> Option<Matches> {
     let mut opts = Options::new();
     opts.optflag("v", "verbose", "Prints extra logging.");
@@ -253,6 +449,20 @@ fn handle_opts() -> Option<Matches> {
         "Maximum throttle to drive at (defaults to 1.0)",
         "THROTTLE",
     );
+    opts.optopt(
+        "",
+        "mqtt-broker",
+        "Address (host:port) of an MQTT broker to bridge telemetry/commands to, e.g. for \
+         remote monitoring. Disabled if not supplied. Overrides the config file's mqtt_broker.",
+        "ADDRESS",
+    );
+    opts.optopt(
+        "",
+        "config",
+        "Path to a JSON config file with the venue's starting pose, sensor noise priors, \
+         socket paths, MQTT broker, and max throttle. Uses built-in defaults if not supplied.",
+        "FILE",
+    );
     let mut args = std::env::args();
     args.next(); // Skip the program name
     let matches = match opts.parse(args) {
@@ -274,15 +484,10 @@ fn handle_opts() -> Option<Matches> {
         .to_string();
     match File::create(&log_file_name) {
         Ok(file) => {
-            CombinedLogger::init(vec![
-                TermLogger::new(LevelFilter::Warn, Config::default()).unwrap(),
-                WriteLogger::new(level, Config::default(), file),
-            ]).unwrap();
+            log_control::init(level, Some(file)).unwrap();
         }
         Err(_) => {
-            CombinedLogger::init(vec![
-                TermLogger::new(LevelFilter::Warn, Config::default()).unwrap(),
-            ]).unwrap();
+            log_control::init(level, None).unwrap();
             error!("Unable to open log file");
         }
     }
@@ -0,0 +1,134 @@
+//! Maps abstract controls (throttle, steering) to physical actuator channels, the way PX4's
+//! text-defined mixers map control setpoints to actuator outputs. Keeping the per-channel pulse
+//! width parameters (neutral, travel limits, deadband, scale, direction) in a `Mixer` built from
+//! config means retuning a servo or adding a channel doesn't require touching driver code.
+
+use driver::Percentage;
+
+/// Which abstract control a channel's pulse width is derived from.
+#[derive(Clone, Copy, Deserialize)]
+pub enum Control {
+    Throttle,
+    Steering,
+}
+
+/// One physical output channel (an ESC or a steering servo): how to turn a `Percentage` in
+/// [-1.0, 1.0] for `source` into a pulse width in microseconds.
+#[derive(Clone, Deserialize)]
+pub struct MixerChannel {
+    pub source: Control,
+    /// GPIO pin (or other channel identifier) the sink should drive.
+    pub pin: i32,
+    pub neutral_us: f32,
+    pub min_us: f32,
+    pub max_us: f32,
+    /// Inputs within this fraction of zero are treated as exactly zero, so trim drift on an idle
+    /// control doesn't creep the actuator off neutral.
+    pub deadband: Percentage,
+    /// Multiplies the input before it's mapped to a pulse width, e.g. to limit a channel's
+    /// authority without touching the upstream throttle/steering values.
+    pub scale: f32,
+    pub reverse: bool,
+}
+
+impl MixerChannel {
+    /// Maps `value` (in [-1.0, 1.0]) to a pulse width in microseconds, honoring this channel's
+    /// deadband, scale, reverse flag, and travel limits.
+    pub fn pulse_width_us(&self, mut value: Percentage) -> f32 {
+        if value.abs() < self.deadband {
+            value = 0.0;
+        }
+        value *= self.scale;
+        if self.reverse {
+            value = -value;
+        }
+        let half_travel_us = (self.max_us - self.min_us) * 0.5;
+        let pulse_width_us = self.neutral_us + value * half_travel_us;
+        pulse_width_us.max(self.min_us).min(self.max_us)
+    }
+}
+
+/// Maps throttle/steering setpoints to a pulse width for every configured output channel.
+pub struct Mixer {
+    channels: Vec<MixerChannel>,
+}
+
+impl Mixer {
+    pub fn new(channels: Vec<MixerChannel>) -> Mixer {
+        Mixer { channels: channels }
+    }
+
+    /// Returns `(pin, pulse_width_us)` for every configured channel, in configuration order.
+    pub fn mix(&self, throttle: Percentage, steering: Percentage) -> Vec<(i32, f32)> {
+        self.channels.iter().map(|channel| {
+            let value = match channel.source {
+                Control::Throttle => throttle,
+                Control::Steering => steering,
+            };
+            (channel.pin, channel.pulse_width_us(value))
+        }).collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{Control, Mixer, MixerChannel};
+
+    fn throttle_channel() -> MixerChannel {
+        MixerChannel {
+            source: Control::Throttle,
+            pin: 18,
+            neutral_us: 1500.0,
+            min_us: 1000.0,
+            max_us: 2000.0,
+            deadband: 0.0,
+            scale: 1.0,
+            reverse: false,
+        }
+    }
+
+    #[test]
+    fn test_pulse_width_full_scale() {
+        let channel = throttle_channel();
+        assert_eq!(channel.pulse_width_us(1.0), 2000.0);
+        assert_eq!(channel.pulse_width_us(-1.0), 1000.0);
+        assert_eq!(channel.pulse_width_us(0.0), 1500.0);
+    }
+
+    #[test]
+    fn test_pulse_width_deadband() {
+        let mut channel = throttle_channel();
+        channel.deadband = 0.1;
+        assert_eq!(channel.pulse_width_us(0.05), 1500.0);
+    }
+
+    #[test]
+    fn test_pulse_width_reverse() {
+        let mut channel = throttle_channel();
+        channel.reverse = true;
+        assert_eq!(channel.pulse_width_us(1.0), 1000.0);
+    }
+
+    #[test]
+    fn test_pulse_width_clamps_beyond_travel() {
+        let channel = throttle_channel();
+        assert_eq!(channel.pulse_width_us(2.0), 2000.0);
+        assert_eq!(channel.pulse_width_us(-2.0), 1000.0);
+    }
+
+    #[test]
+    fn test_mix_routes_controls_to_channels() {
+        let mut steering_channel = throttle_channel();
+        steering_channel.source = Control::Steering;
+        steering_channel.pin = 4;
+        steering_channel.neutral_us = 1650.0;
+        steering_channel.min_us = 1350.0;
+        steering_channel.max_us = 1950.0;
+
+        let mixer = Mixer::new(vec![throttle_channel(), steering_channel]);
+        let outputs = mixer.mix(0.5, -0.5);
+        assert_eq!(outputs[0], (18, 1750.0));
+        assert_eq!(outputs[1], (4, 1500.0));
+    }
+}
@@ -0,0 +1,215 @@
+/**
+ * A minimal hand-rolled MQTT v3.1.1 client used to bridge vehicle telemetry and commands to a
+ * remote broker, as an alternative to the local `/tmp/command-socket` and `/tmp/driver-socket`
+ * Unix sockets. This implements just enough of the wire protocol (CONNECT, SUBSCRIBE, PUBLISH)
+ * to publish telemetry and receive commands at QoS 0, rather than pulling in an MQTT crate.
+ */
+
+use std::io::{ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use telemetry::TelemetryState;
+
+/// Default topic telemetry is published to.
+pub const TELEMETRY_TOPIC: &'static str = "sparkfun-avc/telemetry";
+/// Default topic commands are received on.
+pub const COMMAND_TOPIC: &'static str = "sparkfun-avc/command";
+
+const CONNECT: u8 = 1 << 4;
+const CONNACK: u8 = 2 << 4;
+const PUBLISH: u8 = 3 << 4;
+const SUBSCRIBE: u8 = 8 << 4;
+
+pub struct MqttBridge {
+    stream: TcpStream,
+    read_buffer: Vec<u8>,
+}
+
+impl MqttBridge {
+    /**
+     * Connects to `broker_address` (e.g. "192.168.1.10:1883"), completes the MQTT CONNECT
+     * handshake, and subscribes to `command_topic`.
+     */
+    pub fn new(broker_address: &str, client_id: &str, command_topic: &str) -> Result<MqttBridge, String> {
+        let stream = match TcpStream::connect(broker_address) {
+            Ok(stream) => stream,
+            Err(e) => return Err(format!("Unable to connect to MQTT broker: {}", e)),
+        };
+        if let Err(e) = stream.set_read_timeout(Some(Duration::from_millis(200))) {
+            return Err(format!("Unable to set read timeout: {}", e));
+        }
+
+        let mut bridge = MqttBridge {
+            stream: stream,
+            read_buffer: Vec::new(),
+        };
+        bridge.send_connect(client_id)?;
+        bridge.send_subscribe(command_topic)?;
+        Ok(bridge)
+    }
+
+    /**
+     * Publishes a `TelemetryState` as a small JSON payload on `topic`.
+     */
+    pub fn publish_telemetry(&mut self, topic: &str, telemetry: &TelemetryState) -> Result<(), String> {
+        let payload = format!(
+            "{{\"x\":{},\"y\":{},\"heading\":{},\"speed\":{},\"stopped\":{}}}",
+            telemetry.location.x,
+            telemetry.location.y,
+            telemetry.heading,
+            telemetry.speed,
+            telemetry.stopped,
+        );
+        self.publish(topic, payload.as_bytes())
+    }
+
+    /**
+     * Polls for an incoming PUBLISH packet and returns its UTF-8 payload, if a full packet has
+     * arrived since the last call. Returns `Ok(None)` on a read timeout, which is the normal case
+     * when no command has been sent.
+     */
+    pub fn poll_command(&mut self) -> Result<Option<String>, String> {
+        let mut buffer = [0u8; 256];
+        match self.stream.read(&mut buffer) {
+            Ok(0) => Err("MQTT broker closed the connection".to_string()),
+            Ok(size) => {
+                self.read_buffer.extend_from_slice(&buffer[0..size]);
+                Ok(self.extract_publish_payload())
+            }
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+                Ok(None)
+            }
+            Err(e) => Err(format!("Error reading from MQTT broker: {}", e)),
+        }
+    }
+
+    fn send_connect(&mut self, client_id: &str) -> Result<(), String> {
+        let mut variable_header_and_payload = Vec::new();
+        // Protocol name and level (MQTT 3.1.1)
+        variable_header_and_payload.extend_from_slice(&[0x00, 0x04]);
+        variable_header_and_payload.extend_from_slice(b"MQTT");
+        variable_header_and_payload.push(0x04);
+        // Connect flags: clean session
+        variable_header_and_payload.push(0x02);
+        // Keep alive, seconds
+        variable_header_and_payload.extend_from_slice(&[0x00, 0x3c]);
+        // Client identifier
+        MqttBridge::encode_string(&mut variable_header_and_payload, client_id);
+
+        self.send_packet(CONNECT, &variable_header_and_payload)?;
+
+        // Read the CONNACK; the broker must accept before we publish or subscribe.
+        let mut buffer = [0u8; 4];
+        match self.stream.read(&mut buffer) {
+            Ok(_) => {
+                if buffer[0] & 0xf0 != CONNACK {
+                    return Err("Broker did not acknowledge CONNECT".to_string());
+                }
+                Ok(())
+            }
+            Err(e) => Err(format!("Unable to read CONNACK: {}", e)),
+        }
+    }
+
+    fn send_subscribe(&mut self, topic: &str) -> Result<(), String> {
+        let mut variable_header_and_payload = Vec::new();
+        // Packet identifier
+        variable_header_and_payload.extend_from_slice(&[0x00, 0x01]);
+        MqttBridge::encode_string(&mut variable_header_and_payload, topic);
+        // Requested QoS 0
+        variable_header_and_payload.push(0x00);
+
+        self.send_packet(SUBSCRIBE | 0x02, &variable_header_and_payload)
+    }
+
+    fn publish(&mut self, topic: &str, payload: &[u8]) -> Result<(), String> {
+        let mut variable_header_and_payload = Vec::new();
+        MqttBridge::encode_string(&mut variable_header_and_payload, topic);
+        variable_header_and_payload.extend_from_slice(payload);
+
+        self.send_packet(PUBLISH, &variable_header_and_payload)
+    }
+
+    fn send_packet(&mut self, packet_type: u8, variable_header_and_payload: &[u8]) -> Result<(), String> {
+        let mut packet = Vec::new();
+        packet.push(packet_type);
+        MqttBridge::encode_remaining_length(&mut packet, variable_header_and_payload.len());
+        packet.extend_from_slice(variable_header_and_payload);
+
+        match self.stream.write_all(&packet) {
+            Ok(()) => Ok(()),
+            Err(e) => Err(format!("Unable to write MQTT packet: {}", e)),
+        }
+    }
+
+    fn encode_string(buffer: &mut Vec<u8>, value: &str) {
+        let bytes = value.as_bytes();
+        buffer.push((bytes.len() >> 8) as u8);
+        buffer.push((bytes.len() & 0xff) as u8);
+        buffer.extend_from_slice(bytes);
+    }
+
+    fn encode_remaining_length(buffer: &mut Vec<u8>, length: usize) {
+        let mut remaining = length;
+        loop {
+            let mut encoded_byte = (remaining % 128) as u8;
+            remaining /= 128;
+            if remaining > 0 {
+                encoded_byte |= 0x80;
+            }
+            buffer.push(encoded_byte);
+            if remaining == 0 {
+                break;
+            }
+        }
+    }
+
+    /**
+     * Looks for one complete PUBLISH packet at the front of `read_buffer`; if found, removes it
+     * from the buffer and returns its payload as a string.
+     */
+    fn extract_publish_payload(&mut self) -> Option<String> {
+        if self.read_buffer.is_empty() {
+            return None;
+        }
+        let packet_type = self.read_buffer[0] & 0xf0;
+        let (remaining_length, length_field_size) = match MqttBridge::decode_remaining_length(&self.read_buffer[1..]) {
+            Some(value) => value,
+            None => return None,
+        };
+        let header_size = 1 + length_field_size;
+        let total_size = header_size + remaining_length;
+        if self.read_buffer.len() < total_size {
+            return None;
+        }
+
+        let packet: Vec<u8> = self.read_buffer.drain(0..total_size).collect();
+        if packet_type != PUBLISH {
+            return None;
+        }
+
+        let topic_length = ((packet[header_size] as usize) << 8) | packet[header_size + 1] as usize;
+        let payload_start = header_size + 2 + topic_length;
+        match String::from_utf8(packet[payload_start..].to_vec()) {
+            Ok(payload) => Some(payload),
+            Err(_) => None,
+        }
+    }
+
+    fn decode_remaining_length(buffer: &[u8]) -> Option<(usize, usize)> {
+        let mut multiplier = 1;
+        let mut value = 0usize;
+        for (index, byte) in buffer.iter().enumerate() {
+            value += (*byte as usize & 0x7f) * multiplier;
+            if byte & 0x80 == 0 {
+                return Some((value, index + 1));
+            }
+            multiplier *= 128;
+            if index >= 3 {
+                return None;
+            }
+        }
+        None
+    }
+}
@@ -1,9 +1,22 @@
 /**
  * Reads NMEA messages from the GPS.
+ *
+ * The `no_std` feature is a work in progress, not a working bare-metal build: it currently only
+ * swaps `SatelliteVec`'s storage (below) from a heap-allocated `Vec` to a fixed-capacity
+ * `heapless::Vec`, and drops `GsvCollector`/`AisCollector`/`CurrentFix`, which need a `HashMap`.
+ * The rest of this module -- `NmeaMessage`'s own fields, its parse/encode error paths, `encode()`
+ * -- still use `String`/`format!`/`std::error::Error` unconditionally, so `--features no_std
+ * --no-default-features` does not actually compile without `std` yet. Getting there needs those
+ * paths moved onto a no-alloc error enum and fixed-capacity string types, plus `#![no_std]` and
+ * an explicit `alloc` story for what still needs it.
  */
+#[cfg(not(feature = "no_std"))]
+use std::collections::HashMap;
 use std::error::Error;
 use std::mem::transmute;
 use std::num::ParseFloatError;
+#[cfg(feature = "no_std")]
+extern crate heapless;
 
 use telemetry::Degrees;
 use telemetry::MetersPerSecond;
@@ -12,14 +25,70 @@ pub type Gravity = f32;
 pub type MicroTesla = f32;
 pub type Pascal = u32;
 pub type Celsius = f32;
+/// A latitude or longitude in billionths of a degree (1e-9°), losslessly representing an NMEA
+/// `ddmm.mmmm`/`dddmm.mmmm` field without the rounding error of converting through `f64`.
+pub type NanoDegrees = i64;
+
+/**
+ * The GNSS constellation (or combination of constellations) that emitted a sentence, taken from
+ * the 2-character NMEA talker ID prefix.
+ */
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum Talker {
+    Gps,
+    Glonass,
+    Galileo,
+    Beidou,
+    Qzss,
+    /// GN: a combined solution using more than one constellation.
+    Combined,
+}
+
+impl Talker {
+    /**
+     * Parses the 2-character talker id (e.g. "GP", "GL") immediately after the leading `$`. Some
+     * receivers emit "BD" instead of the standard "GB" for BeiDou, so both are accepted.
+     */
+    fn parse(talker: &str) -> Result<Talker, String> {
+        match talker {
+            "GP" => Ok(Talker::Gps),
+            "GL" => Ok(Talker::Glonass),
+            "GA" => Ok(Talker::Galileo),
+            "GB" | "BD" => Ok(Talker::Beidou),
+            "GQ" => Ok(Talker::Qzss),
+            "GN" => Ok(Talker::Combined),
+            _ => Err(format!("Unknown talker id: {}", talker)),
+        }
+    }
+
+    /**
+     * Returns the 2-character talker id for this constellation, the inverse of `parse`.
+     */
+    fn code(&self) -> &'static str {
+        match *self {
+            Talker::Gps => "GP",
+            Talker::Glonass => "GL",
+            Talker::Galileo => "GA",
+            Talker::Beidou => "GB",
+            Talker::Qzss => "GQ",
+            Talker::Combined => "GN",
+        }
+    }
+}
 
 /**
  * GGA: Global positioning system fix data.
  */
 #[derive(PartialEq)]
 pub struct GgaMessage {
+    pub constellation: Talker,
+    /// hhmmss.sss UTC time of the fix, used to detect epoch boundaries.
+    pub utc_time: String,
     pub latitude_degrees: f64,
     pub longitude_degrees: f64,
+    pub latitude_nanodegrees: NanoDegrees,
+    pub longitude_nanodegrees: NanoDegrees,
+    pub altitude_m: f32,
     pub hdop: f32,
 }
 
@@ -28,6 +97,7 @@ pub struct GgaMessage {
  */
 #[derive(PartialEq)]
 pub struct VtgMessage {
+    pub constellation: Talker,
     pub course: Degrees,
     pub speed: MetersPerSecond,
 }
@@ -37,8 +107,15 @@ pub struct VtgMessage {
  */
 #[derive(PartialEq)]
 pub struct RmcMessage {
+    pub constellation: Talker,
+    /// hhmmss.sss UTC time of the fix, used to detect epoch boundaries.
+    pub utc_time: String,
+    /// ddmmyy UTC date of the fix.
+    pub utc_date: String,
     pub latitude_degrees: f64,
     pub longitude_degrees: f64,
+    pub latitude_nanodegrees: NanoDegrees,
+    pub longitude_nanodegrees: NanoDegrees,
     pub speed: MetersPerSecond,
     pub course: Degrees,
     pub magnetic_variation: Degrees,
@@ -52,7 +129,7 @@ pub enum FixMode {
     Manual,
     Automatic,
 }
-#[derive(PartialEq)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum FixType {
     NotAvailable,
     TwoD,
@@ -60,6 +137,7 @@ pub enum FixType {
 }
 #[derive(PartialEq)]
 pub struct GsaMessage {
+    pub constellation: Talker,
     pub mode: FixMode,
     pub fix_type: FixType,
     pub satellites_used: i32,
@@ -78,12 +156,40 @@ pub struct SatelliteInformation {
     azimuth: Degrees,
     snr_db: i32,
 }
+
+/**
+ * The satellite list carried by a GSV sentence. On a normal build this is a heap-allocated `Vec`;
+ * under the `no_std` feature it's a fixed-capacity `heapless::Vec` instead, capped at 32
+ * satellites, comfortably above what a single GSV burst for one constellation reports. This is
+ * the first step toward a bare-metal build, not a complete one yet -- see the module-level doc
+ * comment above for what's still missing. Adopting this feature requires adding `heapless` as an
+ * optional dependency in Cargo.toml alongside a `no_std = ["heapless"]` feature entry.
+ */
+#[cfg(not(feature = "no_std"))]
+pub type SatelliteVec = Vec<SatelliteInformation>;
+#[cfg(feature = "no_std")]
+pub type SatelliteVec = heapless::Vec<SatelliteInformation, heapless::consts::U32>;
+
+/**
+ * Appends `info` to `satellites`. On `no_std`, a push past the fixed capacity is silently dropped
+ * rather than reported, since a GSV burst can't report more satellites than fit anyway.
+ */
+fn push_satellite(satellites: &mut SatelliteVec, info: SatelliteInformation) {
+    #[cfg(not(feature = "no_std"))]
+    satellites.push(info);
+    #[cfg(feature = "no_std")]
+    {
+        let _ = satellites.push(info);
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub struct GsvMessage {
+    pub constellation: Talker,
     pub message_count: i32,
     pub message_sequence_number: i32,
     pub satellites_in_view: i32,
-    pub satellites: Vec<SatelliteInformation>,
+    pub satellites: SatelliteVec,
 }
 
 /**
@@ -91,8 +197,11 @@ pub struct GsvMessage {
  */
 #[derive(PartialEq)]
 pub struct GllMessage {
+    pub constellation: Talker,
     pub latitude_degrees: f64,
     pub longitude_degrees: f64,
+    pub latitude_nanodegrees: NanoDegrees,
+    pub longitude_nanodegrees: NanoDegrees,
 }
 
 /**
@@ -122,6 +231,32 @@ pub struct BinaryMessage {
     temperature: Celsius,
 }
 
+/**
+ * Errors produced while parsing an NMEA sentence.
+ */
+#[derive(PartialEq, Debug)]
+pub enum NmeaError {
+    /// The sentence's trailing `*hh` checksum didn't match the XOR of its body.
+    ChecksumMismatch { expected: String, found: String },
+    /// Any other parse failure, e.g. a missing field or an unknown sentence type.
+    Parse(String),
+}
+
+/**
+ * One `!AIVDM`/`!AIVDO` sentence: a raw, still-armored fragment of an AIS binary message. Long AIS
+ * messages (e.g. type 5 static data) span several of these, tied together by `sequence_id` and
+ * ordered by `fragment_number`; see `AisCollector` for reassembly and decoding.
+ */
+#[derive(PartialEq, Debug)]
+pub struct AisFragment {
+    pub fragment_count: i32,
+    pub fragment_number: i32,
+    pub sequence_id: Option<i32>,
+    pub channel: char,
+    pub payload: String,
+    pub fill_bits: i32,
+}
+
 #[allow(dead_code)]
 #[derive(PartialEq)]
 pub enum NmeaMessage {
@@ -133,6 +268,7 @@ pub enum NmeaMessage {
     Vtg(VtgMessage),
     Rmc(RmcMessage),
     Sti(StiMessage),
+    Ais(AisFragment),
 }
 
 macro_rules! bail_err {
@@ -162,86 +298,151 @@ macro_rules! array_to_type {
     };
 }
 
+/**
+ * Computes the XOR checksum of `body` (the sentence without its leading `$`/`!` or trailing
+ * `*hh`), formatted as the two uppercase hex digits an NMEA or PMTK sentence expects.
+ */
+fn checksum(body: &str) -> String {
+    let value = body.bytes().fold(0u8, |accumulator, byte| accumulator ^ byte);
+    format!("{:02X}", value)
+}
+
 // convert! needs unsafe in tests, but not in regular code
 #[allow(unused_unsafe)]
 impl NmeaMessage {
-    pub fn parse(message: &str) -> Result<NmeaMessage, String> {
-        // These if statements are sorted in the rough likelihood of appearance
-        if message.starts_with("$GPGGA") {
-            match NmeaMessage::parse_gga(message) {
-                Ok(gga) => Ok(NmeaMessage::Gga(gga)),
-                Err(e) => Err(e),
-            }
-        } else if message.starts_with("$GPVTG") {
-            match NmeaMessage::parse_vtg(message) {
-                Ok(vtg) => Ok(NmeaMessage::Vtg(vtg)),
-                Err(e) => Err(e),
-            }
-        } else if message.starts_with("$PSTI") {
-            match NmeaMessage::parse_sti(message) {
+    pub fn parse(message: &str) -> Result<NmeaMessage, NmeaError> {
+        if let Err(e) = NmeaMessage::verify_checksum(message) {
+            return Err(e);
+        }
+        NmeaMessage::parse_unchecked(message)
+    }
+
+    /**
+     * Parses `message` exactly like `parse`, but without verifying its `*hh` checksum first. This
+     * is the old, permissive behavior, for callers that want to tolerate a corrupted checksum
+     * (e.g. replaying a log known to be intact) rather than reject the sentence outright.
+     */
+    #[allow(dead_code)]
+    pub fn parse_unchecked(message: &str) -> Result<NmeaMessage, NmeaError> {
+        if message.starts_with("$PSTI") {
+            return match NmeaMessage::parse_sti(message) {
                 Ok(sti) => Ok(NmeaMessage::Sti(sti)),
-                Err(e) => Err(e),
-            }
-        } else if message.starts_with("$GPRMC") {
-            match NmeaMessage::parse_rmc(message) {
-                Ok(rmc) => Ok(NmeaMessage::Rmc(rmc)),
-                Err(e) => Err(e),
-            }
-        } else if message.starts_with("$GPGSA") {
-            match NmeaMessage::parse_gsa(message) {
-                Ok(gsa) => Ok(NmeaMessage::Gsa(gsa)),
-                Err(e) => Err(e),
-            }
-        } else if message.starts_with("$GPGSV") {
-            match NmeaMessage::parse_gsv(message) {
-                Ok(gsv) => Ok(NmeaMessage::Gsv(gsv)),
-                Err(e) => Err(e),
-            }
-        } else if message.starts_with("$GPGLL") {
-            match NmeaMessage::parse_gll(message) {
-                Ok(gll) => Ok(NmeaMessage::Gll(gll)),
-                Err(e) => Err(e),
-            }
+                Err(e) => Err(NmeaError::Parse(e)),
+            };
+        }
+        if message.starts_with("!AIVDM") || message.starts_with("!AIVDO") {
+            return match NmeaMessage::parse_ais(message) {
+                Ok(ais) => Ok(NmeaMessage::Ais(ais)),
+                Err(e) => Err(NmeaError::Parse(e)),
+            };
+        }
+        if !message.starts_with('$') || message.len() < 6 {
+            return Err(NmeaError::Parse("Unknown NMEA message type".to_string()));
+        }
+        // Talkers are always 2 characters, immediately after the leading $; the sentence type is
+        // the following 3 characters, e.g. "$GPGGA" -> talker "GP", type "GGA". This dispatches on
+        // the sentence type alone so that any constellation's talker is accepted.
+        let talker = match Talker::parse(&message[1..3]) {
+            Ok(talker) => talker,
+            Err(e) => return Err(NmeaError::Parse(e)),
+        };
+        let sentence_type = &message[3..6];
+
+        // These are sorted in the rough likelihood of appearance
+        let result = if sentence_type == "GGA" {
+            NmeaMessage::parse_gga(message, talker).map(NmeaMessage::Gga)
+        } else if sentence_type == "VTG" {
+            NmeaMessage::parse_vtg(message, talker).map(NmeaMessage::Vtg)
+        } else if sentence_type == "RMC" {
+            NmeaMessage::parse_rmc(message, talker).map(NmeaMessage::Rmc)
+        } else if sentence_type == "GSA" {
+            NmeaMessage::parse_gsa(message, talker).map(NmeaMessage::Gsa)
+        } else if sentence_type == "GSV" {
+            NmeaMessage::parse_gsv(message, talker).map(NmeaMessage::Gsv)
+        } else if sentence_type == "GLL" {
+            NmeaMessage::parse_gll(message, talker).map(NmeaMessage::Gll)
         } else {
             Err("Unknown NMEA message type".to_string())
+        };
+        result.map_err(NmeaError::Parse)
+    }
+
+    /**
+     * Verifies the trailing `*hh` checksum: the XOR of every byte strictly between the leading
+     * `$`/`!` and the `*` must match the two hex digits that follow. This runs before type
+     * dispatch so that corrupted sentences are rejected before their fields are ever trusted.
+     */
+    fn verify_checksum(message: &str) -> Result<(), NmeaError> {
+        let start = match message.find(|c| c == '$' || c == '!') {
+            Some(index) => index,
+            None => return Err(NmeaError::Parse("No $ or ! start delimiter".to_string())),
+        };
+        let star = match message.find('*') {
+            Some(index) => index,
+            None => return Err(NmeaError::Parse("No * checksum delimiter".to_string())),
+        };
+        if star < start {
+            return Err(NmeaError::Parse("Checksum delimiter before start".to_string()));
+        }
+
+        let expected = checksum(&message[start + 1..star]);
+
+        let found: String = message[star + 1..]
+            .chars()
+            .take(2)
+            .collect::<String>()
+            .to_uppercase();
+        if found.len() != 2 {
+            return Err(NmeaError::Parse("Truncated checksum".to_string()));
+        }
+
+        if expected == found {
+            Ok(())
+        } else {
+            Err(NmeaError::ChecksumMismatch {
+                expected: expected,
+                found: found,
+            })
         }
     }
 
     /**
      * Time, position and fix related data for a GPS receiver.
      */
-    fn parse_gga(message: &str) -> Result<GgaMessage, String> {
+    fn parse_gga(message: &str, constellation: Talker) -> Result<GgaMessage, String> {
         // $GPGGA,hhmmss.sss,ddmm.mmmm,a,dddmm.mmmm,a,x,xx,x.x,x.x,M,,,,xxxx*hh<CR><LF>
         let mut iterator = message.split(',');
 
         iterator.next(); // Skip the message type
-        iterator.next(); // Skip the UTC time
+        let utc_time = bail_none!(iterator.next()).to_string();
 
-        let latitude_degrees = {
+        let (latitude_degrees, latitude_nanodegrees) = {
             let string = bail_none!(iterator.next());
             let d = bail_err!(NmeaMessage::parse_degrees_minutes(string));
+            let ndeg = bail_err!(NmeaMessage::parse_degrees_minutes_nanodegrees(string));
 
             let north_indicator = bail_none!(iterator.next());
             let north = north_indicator == "N";
             if north {
-                d
+                (d, ndeg)
             } else {
                 debug_assert!(north_indicator == "S");
-                -d
+                (-d, -ndeg)
             }
         };
 
-        let longitude_degrees = {
+        let (longitude_degrees, longitude_nanodegrees) = {
             let string = bail_none!(iterator.next());
             let d = bail_err!(NmeaMessage::parse_degrees_minutes(string));
+            let ndeg = bail_err!(NmeaMessage::parse_degrees_minutes_nanodegrees(string));
 
             let east_indicator = bail_none!(iterator.next());
             let east = east_indicator == "E";
             if east {
-                d
+                (d, ndeg)
             } else {
                 debug_assert!(east_indicator == "W");
-                -d
+                (-d, -ndeg)
             }
         };
 
@@ -253,11 +454,19 @@ impl NmeaMessage {
 
         let hdop_str = bail_none!(iterator.next());
         let hdop: f32 = bail_err!(hdop_str.parse());
-        // Ignore altitude, DGPS station id, and checksum
+
+        let altitude_str = bail_none!(iterator.next());
+        let altitude_m: f32 = bail_err!(altitude_str.parse());
+        // Ignore altitude units, geoid separation, DGPS station id, and checksum
 
         Ok(GgaMessage {
+            constellation: constellation,
+            utc_time: utc_time,
             latitude_degrees: latitude_degrees,
             longitude_degrees: longitude_degrees,
+            latitude_nanodegrees: latitude_nanodegrees,
+            longitude_nanodegrees: longitude_nanodegrees,
+            altitude_m: altitude_m,
             hdop: hdop,
         })
     }
@@ -265,7 +474,7 @@ impl NmeaMessage {
     /**
      * The actual course and speed relative to the ground.
      */
-    fn parse_vtg(message: &str) -> Result<VtgMessage, String> {
+    fn parse_vtg(message: &str, constellation: Talker) -> Result<VtgMessage, String> {
         // $GPVTG,x.x,T,x.x,M,x.x,N,x.x,K,a*hh<CR><LF>
         let mut iterator = message.split(',');
 
@@ -291,6 +500,7 @@ impl NmeaMessage {
         }
 
         Ok(VtgMessage {
+            constellation: constellation,
             course: course_d,
             speed: speed_km_h * 1000.0 / (60.0 * 60.0),
         })
@@ -299,43 +509,45 @@ impl NmeaMessage {
     /**
      * Time, date, position, course and speed data.
      */
-    fn parse_rmc(message: &str) -> Result<RmcMessage, String> {
+    fn parse_rmc(message: &str, constellation: Talker) -> Result<RmcMessage, String> {
         // $GPRMC,111636.932,A,2447.0949,N,12100.5223,E,000.0,000.0,030407,003.9,W,A*12<CR><LF>
         let mut iterator = message.split(',');
 
         iterator.next(); // Skip the message type
-        iterator.next(); // Skip the UTC time
+        let utc_time = bail_none!(iterator.next()).to_string();
 
         let status = bail_none!(iterator.next());
         if status == "V" {
             return Err("Navigation receiver warning".to_string());
         }
 
-        let latitude_degrees = {
+        let (latitude_degrees, latitude_nanodegrees) = {
             let string = bail_none!(iterator.next());
             let d = bail_err!(NmeaMessage::parse_degrees_minutes(string));
+            let ndeg = bail_err!(NmeaMessage::parse_degrees_minutes_nanodegrees(string));
 
             let north_indicator = bail_none!(iterator.next());
             let north = north_indicator == "N";
             if north {
-                d
+                (d, ndeg)
             } else {
                 debug_assert!(north_indicator == "S");
-                -d
+                (-d, -ndeg)
             }
         };
 
-        let longitude_degrees = {
+        let (longitude_degrees, longitude_nanodegrees) = {
             let string = bail_none!(iterator.next());
             let d = bail_err!(NmeaMessage::parse_degrees_minutes(string));
+            let ndeg = bail_err!(NmeaMessage::parse_degrees_minutes_nanodegrees(string));
 
             let east_indicator = bail_none!(iterator.next());
             let east = east_indicator == "E";
             if east {
-                d
+                (d, ndeg)
             } else {
                 debug_assert!(east_indicator == "W");
-                -d
+                (-d, -ndeg)
             }
         };
 
@@ -346,7 +558,7 @@ impl NmeaMessage {
         let course_d_str = bail_none!(iterator.next());
         let course: Degrees = bail_err!(course_d_str.parse());
 
-        iterator.next(); // Skip UTC date
+        let utc_date = bail_none!(iterator.next()).to_string();
 
         let magnetic_variation = {
             let magnetic_d_str = bail_none!(iterator.next());
@@ -366,8 +578,13 @@ impl NmeaMessage {
         }
 
         Ok(RmcMessage {
+            constellation: constellation,
+            utc_time: utc_time,
+            utc_date: utc_date,
             latitude_degrees: latitude_degrees,
             longitude_degrees: longitude_degrees,
+            latitude_nanodegrees: latitude_nanodegrees,
+            longitude_nanodegrees: longitude_nanodegrees,
             speed: speed,
             course: course,
             magnetic_variation: magnetic_variation,
@@ -378,7 +595,7 @@ impl NmeaMessage {
      * GSA: GPS receiver operating mode, satellites used in the navigation solution reported by the
      * GGA or GNS sentence and DOP values.
      */
-    fn parse_gsa(message: &str) -> Result<GsaMessage, String> {
+    fn parse_gsa(message: &str, constellation: Talker) -> Result<GsaMessage, String> {
         // $GPGSA,A,x,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,xx,x.x,x.x,x.x*hh<CR><LF>
         let mut iterator = message.split(',');
 
@@ -425,6 +642,7 @@ impl NmeaMessage {
         let vdop: f32 = bail_err!(vdop_and_checksum_str[0..star_index].parse());
 
         Ok(GsaMessage {
+            constellation: constellation,
             mode: fix_mode,
             fix_type: fix_type,
             satellites_used: satellites_used,
@@ -437,7 +655,7 @@ impl NmeaMessage {
     /**
      * GSV: Number of satellites in view, IDs, elevation, azimuth and SNR.
      */
-    fn parse_gsv(message: &str) -> Result<GsvMessage, String> {
+    fn parse_gsv(message: &str, constellation: Talker) -> Result<GsvMessage, String> {
         // $GPGSV,3,1,12,05,54,069,45,12,44,061,44,21,07,184,46,22,78,289,47*72<CR><LF>
         let mut iterator = message.split(',');
 
@@ -452,7 +670,7 @@ impl NmeaMessage {
         let satellites_in_view_str = bail_none!(iterator.next());
         let satellites_in_view: i32 = bail_err!(satellites_in_view_str.parse());
 
-        let mut satellites: Vec<SatelliteInformation> = Vec::with_capacity(6);
+        let mut satellites: SatelliteVec = SatelliteVec::new();
         let mut done = false;
 
         loop {
@@ -477,18 +695,22 @@ impl NmeaMessage {
                     }
                 }
             };
-            satellites.push(SatelliteInformation {
-                id: id,
-                elevation: elevation,
-                azimuth: azimuth,
-                snr_db: snr,
-            });
+            push_satellite(
+                &mut satellites,
+                SatelliteInformation {
+                    id: id,
+                    elevation: elevation,
+                    azimuth: azimuth,
+                    snr_db: snr,
+                },
+            );
             if done {
                 break;
             }
         }
 
         Ok(GsvMessage {
+            constellation: constellation,
             message_count: message_count,
             message_sequence_number: message_sequence_number,
             satellites_in_view: satellites_in_view,
@@ -499,37 +721,39 @@ impl NmeaMessage {
     /**
      * GLL: Latitude/longitude.
      */
-    fn parse_gll(message: &str) -> Result<GllMessage, String> {
+    fn parse_gll(message: &str, constellation: Talker) -> Result<GllMessage, String> {
         // $GPGLL,ddmm.mmmm,a,dddmm.mmmm,a,hhmmss.sss,A,a*hh<CR><LF>
         let mut iterator = message.split(',');
 
         iterator.next(); // Skip the message type
 
-        let latitude_degrees = {
+        let (latitude_degrees, latitude_nanodegrees) = {
             let string = bail_none!(iterator.next());
             let d = bail_err!(NmeaMessage::parse_degrees_minutes(string));
+            let ndeg = bail_err!(NmeaMessage::parse_degrees_minutes_nanodegrees(string));
 
             let north_indicator = bail_none!(iterator.next());
             let north = north_indicator == "N";
             if north {
-                d
+                (d, ndeg)
             } else {
                 debug_assert!(north_indicator == "S");
-                -d
+                (-d, -ndeg)
             }
         };
 
-        let longitude_degrees = {
+        let (longitude_degrees, longitude_nanodegrees) = {
             let string = bail_none!(iterator.next());
             let d = bail_err!(NmeaMessage::parse_degrees_minutes(string));
+            let ndeg = bail_err!(NmeaMessage::parse_degrees_minutes_nanodegrees(string));
 
             let east_indicator = bail_none!(iterator.next());
             let east = east_indicator == "E";
             if east {
-                d
+                (d, ndeg)
             } else {
                 debug_assert!(east_indicator == "W");
-                -d
+                (-d, -ndeg)
             }
         };
 
@@ -546,8 +770,11 @@ impl NmeaMessage {
         }
 
         Ok(GllMessage {
+            constellation: constellation,
             latitude_degrees: latitude_degrees,
             longitude_degrees: longitude_degrees,
+            latitude_nanodegrees: latitude_nanodegrees,
+            longitude_nanodegrees: longitude_nanodegrees,
         })
     }
 
@@ -596,6 +823,52 @@ impl NmeaMessage {
         })
     }
 
+    /**
+     * AIVDM/AIVDO: one (possibly partial) fragment of an AIS binary message. Unlike the other
+     * sentence types, this only strips the framing fields; the still-armored `payload` is decoded
+     * separately by `AisCollector` once every fragment of the message has arrived.
+     */
+    fn parse_ais(message: &str) -> Result<AisFragment, String> {
+        // !AIVDM,2,1,9,A,55P5TL01VIaAL@7WKO@mBplU@<PDhPlU8Ht00000016,0*7B<CR><LF>
+        let mut iterator = message.split(',');
+
+        iterator.next(); // Skip the message type
+
+        let fragment_count_str = bail_none!(iterator.next());
+        let fragment_count: i32 = bail_err!(fragment_count_str.parse());
+
+        let fragment_number_str = bail_none!(iterator.next());
+        let fragment_number: i32 = bail_err!(fragment_number_str.parse());
+
+        let sequence_id_str = bail_none!(iterator.next());
+        let sequence_id = if sequence_id_str.is_empty() {
+            None
+        } else {
+            Some(bail_err!(sequence_id_str.parse()))
+        };
+
+        let channel_str = bail_none!(iterator.next());
+        let channel = channel_str.chars().next().unwrap_or('0');
+
+        let payload = bail_none!(iterator.next()).to_string();
+
+        let fill_bits_and_checksum = bail_none!(iterator.next());
+        let star_index = match fill_bits_and_checksum.chars().position(|x| x == '*') {
+            Some(index) => index,
+            None => return Err("Invalid fill bit count".to_string()),
+        };
+        let fill_bits: i32 = bail_err!(fill_bits_and_checksum[0..star_index].parse());
+
+        Ok(AisFragment {
+            fragment_count: fragment_count,
+            fragment_number: fragment_number,
+            sequence_id: sequence_id,
+            channel: channel,
+            payload: payload,
+            fill_bits: fill_bits,
+        })
+    }
+
     #[allow(dead_code)]
     fn parse_binary(message: &[u8; 34]) -> Result<NmeaMessage, String> {
         // The payload length from the GPS is always 34 bytes
@@ -637,14 +910,857 @@ impl NmeaMessage {
         };
         Ok(degrees as f64 + minutes / 60.0f64)
     }
+
+    /**
+     * Converts an NMEA `ddmm.mmmm`/`dddmm.mmmm` field to nanodegrees (1e-9°): splits whole
+     * degrees from decimal minutes the same way `parse_degrees_minutes` does, but computes in
+     * `f64` and rounds to the nearest nanodegree at the end instead of returning a `f64` degrees
+     * value, so callers don't have to round themselves or drag `f64` rounding error through
+     * downstream math. Minutes must be < 60.
+     */
+    fn parse_degrees_minutes_nanodegrees(degrees_minutes: &str) -> Result<NanoDegrees, String> {
+        const NANODEGREES_PER_DEGREE: f64 = 1_000_000_000.0;
+        let decimal_point_index = match degrees_minutes.chars().position(|x| x == '.') {
+            Some(index) => index,
+            None => return Err("Missing decimal point".to_string()),
+        };
+        if decimal_point_index < 2 {
+            return Err("Field too short to hold whole-number minutes".to_string());
+        }
+        // There are always two digits for whole number minutes
+        let degrees: i64 = bail_err!(degrees_minutes[0..decimal_point_index - 2].parse());
+        let minutes: f64 = bail_err!(degrees_minutes[decimal_point_index - 2..].parse());
+        if minutes >= 60.0 {
+            return Err(format!("Minutes out of range: {}", minutes));
+        }
+        let degree_nanodegrees = degrees * NANODEGREES_PER_DEGREE as i64;
+        let minute_nanodegrees = (minutes / 60.0 * NANODEGREES_PER_DEGREE).round() as i64;
+        Ok(degree_nanodegrees + minute_nanodegrees)
+    }
+
+    /**
+     * Serializes this message back into a valid, checksummed NMEA sentence, the inverse of
+     * `parse`. Useful for logging or replaying captured telemetry. `Binary` messages aren't NMEA
+     * sentences at all, and `Ais` fragments use `!` rather than `$` framing, so this returns an
+     * empty string for both.
+     */
+    #[allow(dead_code)]
+    pub fn encode(&self) -> String {
+        let body = match *self {
+            NmeaMessage::Gga(ref gga) => {
+                format!("{}GGA,{}", gga.constellation.code(), NmeaMessage::encode_gga(gga))
+            }
+            NmeaMessage::Vtg(ref vtg) => {
+                format!("{}VTG,{}", vtg.constellation.code(), NmeaMessage::encode_vtg(vtg))
+            }
+            NmeaMessage::Rmc(ref rmc) => {
+                format!("{}RMC,{}", rmc.constellation.code(), NmeaMessage::encode_rmc(rmc))
+            }
+            NmeaMessage::Gsa(ref gsa) => {
+                format!("{}GSA,{}", gsa.constellation.code(), NmeaMessage::encode_gsa(gsa))
+            }
+            NmeaMessage::Gsv(ref gsv) => {
+                format!("{}GSV,{}", gsv.constellation.code(), NmeaMessage::encode_gsv(gsv))
+            }
+            NmeaMessage::Gll(ref gll) => {
+                format!("{}GLL,{}", gll.constellation.code(), NmeaMessage::encode_gll(gll))
+            }
+            NmeaMessage::Sti(ref sti) => NmeaMessage::encode_sti(sti),
+            NmeaMessage::Binary(_) | NmeaMessage::Ais(_) => return String::new(),
+        };
+        format!("${}*{}\r\n", body, checksum(&body))
+    }
+
+    fn format_latitude(degrees: f64) -> String {
+        let hemisphere = if degrees >= 0.0 { 'N' } else { 'S' };
+        let absolute = degrees.abs();
+        let whole_degrees = absolute as i32;
+        let minutes = (absolute - whole_degrees as f64) * 60.0;
+        format!("{:02}{:07.4},{}", whole_degrees, minutes, hemisphere)
+    }
+
+    fn format_longitude(degrees: f64) -> String {
+        let hemisphere = if degrees >= 0.0 { 'E' } else { 'W' };
+        let absolute = degrees.abs();
+        let whole_degrees = absolute as i32;
+        let minutes = (absolute - whole_degrees as f64) * 60.0;
+        format!("{:03}{:07.4},{}", whole_degrees, minutes, hemisphere)
+    }
+
+    fn encode_gga(gga: &GgaMessage) -> String {
+        let latitude = NmeaMessage::format_latitude(gga.latitude_degrees);
+        let longitude = NmeaMessage::format_longitude(gga.longitude_degrees);
+        // The satellite count isn't kept on GgaMessage, so this always reports a 1 (GPS) fix
+        // with 00 satellites used; only the fields we actually parsed round-trip faithfully.
+        format!(
+            "{},{},{},1,00,{:.1},{:.1},M,,,,0000",
+            gga.utc_time, latitude, longitude, gga.hdop, gga.altitude_m
+        )
+    }
+
+    fn encode_vtg(vtg: &VtgMessage) -> String {
+        let speed_knots = vtg.speed / 0.5144;
+        let speed_km_h = vtg.speed * 3.6;
+        format!("{:.1},T,,M,{:.1},N,{:.1},K,A", vtg.course, speed_knots, speed_km_h)
+    }
+
+    fn encode_rmc(rmc: &RmcMessage) -> String {
+        let latitude = NmeaMessage::format_latitude(rmc.latitude_degrees);
+        let longitude = NmeaMessage::format_longitude(rmc.longitude_degrees);
+        let speed_knots = rmc.speed / 0.5144;
+        let (magnitude, east_west) = if rmc.magnetic_variation < 0.0 {
+            (-rmc.magnetic_variation, "E")
+        } else {
+            (rmc.magnetic_variation, "W")
+        };
+        format!(
+            "{},A,{},{},{:.1},{:.1},{},{:.1},{},A",
+            rmc.utc_time,
+            latitude,
+            longitude,
+            speed_knots,
+            rmc.course,
+            rmc.utc_date,
+            magnitude,
+            east_west
+        )
+    }
+
+    fn encode_gsa(gsa: &GsaMessage) -> String {
+        let mode = match gsa.mode {
+            FixMode::Automatic => "A",
+            FixMode::Manual => "M",
+        };
+        let fix_type = match gsa.fix_type {
+            FixType::NotAvailable => "1",
+            FixType::TwoD => "2",
+            FixType::ThreeD => "3",
+        };
+        // The individual satellite ids used in the fix aren't kept on GsaMessage, so this just
+        // fills the 12 id slots with placeholders to preserve the satellite count.
+        let mut satellite_ids = String::new();
+        for i in 0..12 {
+            satellite_ids.push(',');
+            if i < gsa.satellites_used {
+                satellite_ids.push_str(&format!("{:02}", i + 1));
+            }
+        }
+        format!(
+            "{},{}{},{:.1},{:.1},{:.1}",
+            mode, fix_type, satellite_ids, gsa.pdop, gsa.hdop, gsa.vdop
+        )
+    }
+
+    fn encode_gsv(gsv: &GsvMessage) -> String {
+        let mut satellites = String::new();
+        for satellite in &gsv.satellites {
+            satellites.push_str(&format!(
+                ",{:02},{:02.0},{:03.0},{:02}",
+                satellite.id, satellite.elevation, satellite.azimuth, satellite.snr_db
+            ));
+        }
+        format!(
+            "{},{},{}{}",
+            gsv.message_count, gsv.message_sequence_number, gsv.satellites_in_view, satellites
+        )
+    }
+
+    fn encode_gll(gll: &GllMessage) -> String {
+        let latitude = NmeaMessage::format_latitude(gll.latitude_degrees);
+        let longitude = NmeaMessage::format_longitude(gll.longitude_degrees);
+        format!("{},{},000000.000,A,A", latitude, longitude)
+    }
+
+    fn encode_sti(sti: &StiMessage) -> String {
+        format!(
+            "PSTI,004,001,1,{:.1},{:.1},{:.1},{},{:.1}",
+            sti.pitch, sti.roll, sti.yaw, sti.pressure, sti.temperature
+        )
+    }
+}
+
+/**
+ * A fused position, velocity, and dilution-of-precision solution for a single GNSS epoch,
+ * assembled from whichever GGA/RMC/VTG/GSA sentences reported each piece.
+ */
+#[derive(PartialEq, Debug)]
+pub struct PvtSolution {
+    pub latitude_degrees: f64,
+    pub longitude_degrees: f64,
+    pub altitude_m: f32,
+    pub v_north: MetersPerSecond,
+    pub v_east: MetersPerSecond,
+    pub v_down: MetersPerSecond,
+    pub course: Degrees,
+    pub speed: MetersPerSecond,
+    pub pdop: f32,
+    pub hdop: f32,
+    pub vdop: f32,
+    pub satellites_used: i32,
+    pub fix_type: FixType,
+}
+
+/**
+ * Fuses one epoch's worth of sentences into a single PvtSolution. A GPS emits one sentence of
+ * each type per epoch, so this just remembers the latest of each and, whenever the UTC time in a
+ * GGA or RMC sentence rolls over to a new value, emits a solution built from everything
+ * accumulated during the epoch that just ended.
+ */
+pub struct NmeaAggregator {
+    current_utc_time: Option<String>,
+    latitude_degrees: Option<f64>,
+    longitude_degrees: Option<f64>,
+    altitude_m: Option<f32>,
+    previous_altitude_m: Option<f32>,
+    course: Option<Degrees>,
+    speed: Option<MetersPerSecond>,
+    pdop: Option<f32>,
+    hdop: Option<f32>,
+    vdop: Option<f32>,
+    satellites_used: Option<i32>,
+    fix_type: Option<FixType>,
+}
+
+impl NmeaAggregator {
+    pub fn new() -> NmeaAggregator {
+        NmeaAggregator {
+            current_utc_time: None,
+            latitude_degrees: None,
+            longitude_degrees: None,
+            altitude_m: None,
+            previous_altitude_m: None,
+            course: None,
+            speed: None,
+            pdop: None,
+            hdop: None,
+            vdop: None,
+            satellites_used: None,
+            fix_type: None,
+        }
+    }
+
+    /**
+     * Folds a parsed message into the running epoch. Returns a PvtSolution when this message
+     * marks the start of a new epoch, i.e. the UTC time in a GGA or RMC sentence just changed.
+     */
+    pub fn ingest(&mut self, message: &NmeaMessage) -> Option<PvtSolution> {
+        match *message {
+            NmeaMessage::Gga(ref gga) => {
+                let solution = self.roll_epoch(&gga.utc_time);
+                self.previous_altitude_m = self.altitude_m;
+                self.latitude_degrees = Some(gga.latitude_degrees);
+                self.longitude_degrees = Some(gga.longitude_degrees);
+                self.altitude_m = Some(gga.altitude_m);
+                self.hdop = Some(gga.hdop);
+                solution
+            }
+            NmeaMessage::Rmc(ref rmc) => {
+                let solution = self.roll_epoch(&rmc.utc_time);
+                self.latitude_degrees = Some(rmc.latitude_degrees);
+                self.longitude_degrees = Some(rmc.longitude_degrees);
+                self.course = Some(rmc.course);
+                self.speed = Some(rmc.speed);
+                solution
+            }
+            NmeaMessage::Vtg(ref vtg) => {
+                self.course = Some(vtg.course);
+                self.speed = Some(vtg.speed);
+                None
+            }
+            NmeaMessage::Gsa(ref gsa) => {
+                self.pdop = Some(gsa.pdop);
+                self.hdop = Some(gsa.hdop);
+                self.vdop = Some(gsa.vdop);
+                self.satellites_used = Some(gsa.satellites_used);
+                self.fix_type = Some(gsa.fix_type);
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /**
+     * Checks whether `utc_time` marks a new epoch relative to the last one seen; if so, builds
+     * a solution from everything accumulated so far before starting the next epoch.
+     */
+    fn roll_epoch(&mut self, utc_time: &str) -> Option<PvtSolution> {
+        let is_new_epoch = match self.current_utc_time {
+            Some(ref current) => current != utc_time,
+            None => false,
+        };
+        self.current_utc_time = Some(utc_time.to_string());
+        if is_new_epoch {
+            self.build_solution()
+        } else {
+            None
+        }
+    }
+
+    fn build_solution(&self) -> Option<PvtSolution> {
+        let latitude_degrees = match self.latitude_degrees {
+            Some(l) => l,
+            None => return None,
+        };
+        let longitude_degrees = match self.longitude_degrees {
+            Some(l) => l,
+            None => return None,
+        };
+        let altitude_m = self.altitude_m.unwrap_or(0.0);
+        let course = self.course.unwrap_or(0.0);
+        let speed = self.speed.unwrap_or(0.0);
+        let v_north = speed * course.to_radians().cos();
+        let v_east = speed * course.to_radians().sin();
+        let v_down = match self.previous_altitude_m {
+            Some(previous) => -(altitude_m - previous),
+            None => 0.0,
+        };
+        Some(PvtSolution {
+            latitude_degrees: latitude_degrees,
+            longitude_degrees: longitude_degrees,
+            altitude_m: altitude_m,
+            v_north: v_north,
+            v_east: v_east,
+            v_down: v_down,
+            course: course,
+            speed: speed,
+            pdop: self.pdop.unwrap_or(0.0),
+            hdop: self.hdop.unwrap_or(0.0),
+            vdop: self.vdop.unwrap_or(0.0),
+            satellites_used: self.satellites_used.unwrap_or(0),
+            fix_type: self.fix_type.unwrap_or(FixType::NotAvailable),
+        })
+    }
+}
+
+/**
+ * The complete set of satellites in view for one constellation, reassembled from the 2-4 GSV
+ * sentences that make up a single burst.
+ */
+#[derive(PartialEq, Debug)]
+pub struct SatelliteSky {
+    pub satellites_in_view: i32,
+    pub satellites: SatelliteVec,
+}
+
+/**
+ * A GSV burst in progress: the sentences received so far for one talker, waiting on the rest of
+ * the `message_count` sentences to arrive in order.
+ */
+struct GsvBurst {
+    message_count: i32,
+    next_sequence_number: i32,
+    satellites_in_view: i32,
+    satellites: SatelliteVec,
+}
+
+/**
+ * Reassembles the 2-4 GSV sentences of a burst into a single SatelliteSky. GSV satellite data is
+ * split across multiple sentences per constellation, each tagged with `message_count` and
+ * `message_sequence_number`, so this buffers sentences per talker until the last one in the
+ * sequence arrives and then concatenates their satellite lists. A burst that's interrupted by an
+ * out-of-order sequence number or a `message_count` that changes mid-burst is discarded; the
+ * sentence that broke it starts a new burst only if its sequence number is 1, and is otherwise
+ * dropped as well.
+ *
+ * This buffers one burst per talker in a `HashMap`, so unlike `GsvMessage` itself it always needs
+ * an allocator and isn't available under the `no_std` feature.
+ */
+#[cfg(not(feature = "no_std"))]
+pub struct GsvCollector {
+    bursts: HashMap<Talker, GsvBurst>,
+}
+
+#[cfg(not(feature = "no_std"))]
+impl GsvCollector {
+    pub fn new() -> GsvCollector {
+        GsvCollector {
+            bursts: HashMap::new(),
+        }
+    }
+
+    /**
+     * Folds one GSV sentence into the in-progress burst for its talker. Returns the completed
+     * SatelliteSky once the sentence with `message_sequence_number == message_count` arrives.
+     */
+    pub fn ingest(&mut self, gsv: GsvMessage) -> Option<SatelliteSky> {
+        let continues_burst = match self.bursts.get(&gsv.constellation) {
+            Some(burst) => {
+                burst.message_count == gsv.message_count
+                    && gsv.message_sequence_number == burst.next_sequence_number
+            }
+            None => false,
+        };
+
+        if gsv.message_sequence_number == 1 {
+            // Start of a burst: whatever was buffered for this talker before is stale, whether
+            // it's a leftover from an interrupted burst or not, so replace it outright.
+            self.bursts.insert(
+                gsv.constellation,
+                GsvBurst {
+                    message_count: gsv.message_count,
+                    next_sequence_number: 1,
+                    satellites_in_view: gsv.satellites_in_view,
+                    satellites: SatelliteVec::new(),
+                },
+            );
+        } else if !continues_burst {
+            // This sentence doesn't pick up where the buffered burst for this talker left off,
+            // so whatever was buffered can't be completed.
+            self.bursts.remove(&gsv.constellation);
+            return None;
+        }
+
+        let burst = self.bursts.get_mut(&gsv.constellation).unwrap();
+        for satellite in gsv.satellites {
+            push_satellite(&mut burst.satellites, satellite);
+        }
+        burst.next_sequence_number += 1;
+
+        if gsv.message_sequence_number >= gsv.message_count {
+            let burst = self.bursts.remove(&gsv.constellation).unwrap();
+            Some(SatelliteSky {
+                satellites_in_view: burst.satellites_in_view,
+                satellites: burst.satellites,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/**
+ * Reverses the 6-bit ASCII armoring of one AIS payload character into its 6-bit value: the
+ * character's ASCII code minus 48, with another 8 subtracted if that exceeds 40 (the payload
+ * alphabet skips the ASCII range occupied by flow-control characters).
+ */
+fn unarmor_ais_char(character: u8) -> u8 {
+    let value = character - 48;
+    if value > 40 {
+        value - 8
+    } else {
+        value
+    }
+}
+
+/**
+ * Unarmors an AIS payload into its underlying bitstream, one bool per bit, MSB first within each
+ * 6-bit character.
+ */
+fn unarmor_ais_payload(payload: &str) -> Vec<bool> {
+    let mut bits = Vec::with_capacity(payload.len() * 6);
+    for character in payload.bytes() {
+        let value = unarmor_ais_char(character);
+        for shift in (0..6).rev() {
+            bits.push((value >> shift) & 1 == 1);
+        }
+    }
+    bits
+}
+
+/**
+ * Reads `length` bits starting at `start` as an unsigned big-endian integer. Bits past the end of
+ * the stream (e.g. a field that runs into this fragment's fill bits) read as zero.
+ */
+fn read_ais_unsigned(bits: &[bool], start: usize, length: usize) -> u64 {
+    let mut value = 0u64;
+    for i in 0..length {
+        value <<= 1;
+        if bits.get(start + i) == Some(&true) {
+            value |= 1;
+        }
+    }
+    value
+}
+
+/**
+ * Reads `length` bits starting at `start` as a two's-complement signed integer, the way AIS
+ * encodes rate of turn, longitude and latitude.
+ */
+fn read_ais_signed(bits: &[bool], start: usize, length: usize) -> i64 {
+    let value = read_ais_unsigned(bits, start, length);
+    let sign_bit = 1u64 << (length - 1);
+    if value & sign_bit != 0 {
+        value as i64 - (1i64 << length)
+    } else {
+        value as i64
+    }
+}
+
+/**
+ * Reads `char_count` 6-bit AIS characters starting at `start` as a string, then trims the
+ * trailing `@`/space padding AIS uses to fill a fixed-width field. The 6-bit alphabet maps 0-31 to
+ * `@`-`_` and 32-63 directly onto ASCII 32-63.
+ */
+fn read_ais_string(bits: &[bool], start: usize, char_count: usize) -> String {
+    let mut string = String::with_capacity(char_count);
+    for i in 0..char_count {
+        let code = read_ais_unsigned(bits, start + i * 6, 6) as u8;
+        let ascii = if code < 32 { code + 64 } else { code };
+        string.push(ascii as char);
+    }
+    string.trim_end_matches(|c| c == '@' || c == ' ').to_string()
+}
+
+/**
+ * AIS message types 1-3: a Class A vessel's position, course and speed over ground.
+ */
+#[derive(PartialEq, Debug)]
+pub struct AisPositionReport {
+    pub message_type: i32,
+    pub mmsi: u32,
+    pub speed_over_ground_knots: f32,
+    pub latitude_degrees: f64,
+    pub longitude_degrees: f64,
+    pub course_over_ground: Degrees,
+    /// `None` when the vessel isn't reporting a gyro heading (encoded as 511).
+    pub true_heading: Option<i32>,
+}
+
+/**
+ * AIS message type 5: a vessel's static and voyage-related data, broadcast far less often than
+ * its position reports.
+ */
+#[derive(PartialEq, Debug)]
+pub struct AisStaticData {
+    pub mmsi: u32,
+    pub vessel_name: String,
+    pub ship_type: i32,
+}
+
+/**
+ * A fully reassembled and decoded AIS message. Only the fields this crate needs are extracted;
+ * see `AisMessage::decode` for the bit offsets of each one.
+ */
+#[derive(PartialEq, Debug)]
+pub enum AisMessage {
+    PositionReport(AisPositionReport),
+    StaticData(AisStaticData),
+}
+
+impl AisMessage {
+    /**
+     * Decodes an unarmored AIS bitstream. Only message types 1-3 (position report) and 5 (static
+     * data) are supported; anything else is reported as an error rather than silently dropped.
+     */
+    fn decode(bits: &[bool]) -> Result<AisMessage, String> {
+        let message_type = read_ais_unsigned(bits, 0, 6) as i32;
+        match message_type {
+            1 | 2 | 3 => Ok(AisMessage::PositionReport(AisMessage::decode_position_report(
+                bits,
+                message_type,
+            ))),
+            5 => Ok(AisMessage::StaticData(AisMessage::decode_static_data(bits))),
+            _ => Err(format!("Unsupported AIS message type: {}", message_type)),
+        }
+    }
+
+    fn decode_position_report(bits: &[bool], message_type: i32) -> AisPositionReport {
+        let mmsi = read_ais_unsigned(bits, 8, 30) as u32;
+        let speed_over_ground_knots = read_ais_unsigned(bits, 50, 10) as f32 / 10.0;
+        // Longitude/latitude are encoded in 1/10000 of a minute; dividing by 600,000 converts
+        // straight to degrees (60 minutes/degree * 10,000).
+        let longitude_degrees = read_ais_signed(bits, 61, 28) as f64 / 600_000.0;
+        let latitude_degrees = read_ais_signed(bits, 89, 27) as f64 / 600_000.0;
+        let course_over_ground = read_ais_unsigned(bits, 116, 12) as f32 / 10.0;
+        let heading = read_ais_unsigned(bits, 128, 9) as i32;
+        AisPositionReport {
+            message_type: message_type,
+            mmsi: mmsi,
+            speed_over_ground_knots: speed_over_ground_knots,
+            latitude_degrees: latitude_degrees,
+            longitude_degrees: longitude_degrees,
+            course_over_ground: course_over_ground,
+            true_heading: if heading == 511 { None } else { Some(heading) },
+        }
+    }
+
+    fn decode_static_data(bits: &[bool]) -> AisStaticData {
+        let mmsi = read_ais_unsigned(bits, 8, 30) as u32;
+        let vessel_name = read_ais_string(bits, 112, 20);
+        let ship_type = read_ais_unsigned(bits, 232, 8) as i32;
+        AisStaticData {
+            mmsi: mmsi,
+            vessel_name: vessel_name,
+            ship_type: ship_type,
+        }
+    }
+}
+
+/**
+ * An AIS message in progress: the payload of the fragments received so far for one sequential
+ * message id, waiting on the rest of `fragment_count` fragments to arrive in order.
+ */
+struct AisBurst {
+    fragment_count: i32,
+    next_fragment_number: i32,
+    payload: String,
+}
+
+/**
+ * Reassembles the 1-9 fragments of a multi-part AIS message (keyed on its sequential message id)
+ * into a single payload and decodes it once the last fragment arrives. A single-fragment message
+ * decodes immediately. Like `GsvCollector`, this buffers state in a `HashMap` and so needs an
+ * allocator; it isn't available under the `no_std` feature.
+ */
+#[cfg(not(feature = "no_std"))]
+pub struct AisCollector {
+    bursts: HashMap<i32, AisBurst>,
+}
+
+#[cfg(not(feature = "no_std"))]
+impl AisCollector {
+    pub fn new() -> AisCollector {
+        AisCollector {
+            bursts: HashMap::new(),
+        }
+    }
+
+    /**
+     * Folds one AIS fragment into the in-progress message for its sequential id. Returns the
+     * decoded message once every fragment has arrived, or the parse/decode error if the completed
+     * payload turns out to be malformed or an unsupported message type.
+     */
+    pub fn ingest(&mut self, fragment: AisFragment) -> Option<Result<AisMessage, String>> {
+        if fragment.fragment_count <= 1 {
+            let bits = unarmor_ais_payload(&fragment.payload);
+            return Some(AisMessage::decode(&bits));
+        }
+
+        let key = fragment.sequence_id.unwrap_or(0);
+        let continues_burst = match self.bursts.get(&key) {
+            Some(burst) => {
+                burst.fragment_count == fragment.fragment_count
+                    && fragment.fragment_number == burst.next_fragment_number
+            }
+            None => false,
+        };
+
+        if fragment.fragment_number == 1 {
+            // Start of a message: whatever was buffered for this id before is stale, whether it's
+            // a leftover from an interrupted message or not, so replace it outright.
+            self.bursts.insert(
+                key,
+                AisBurst {
+                    fragment_count: fragment.fragment_count,
+                    next_fragment_number: 1,
+                    payload: String::new(),
+                },
+            );
+        } else if !continues_burst {
+            // This fragment doesn't pick up where the buffered message for this id left off, so
+            // whatever was buffered can't be completed.
+            self.bursts.remove(&key);
+            return None;
+        }
+
+        let burst = self.bursts.get_mut(&key).unwrap();
+        burst.payload.push_str(&fragment.payload);
+        burst.next_fragment_number += 1;
+
+        if fragment.fragment_number >= fragment.fragment_count {
+            let burst = self.bursts.remove(&key).unwrap();
+            let bits = unarmor_ais_payload(&burst.payload);
+            Some(AisMessage::decode(&bits))
+        } else {
+            None
+        }
+    }
+}
+
+/**
+ * A snapshot of everything known about the receiver's current position, built up by `Nmea` as
+ * sentences arrive. Each field simply holds the most recent value reported for it; unlike
+ * `PvtSolution`, there's no notion of an epoch boundary here, so fields from different sentences
+ * may lag each other by a few hundred milliseconds.
+ */
+#[derive(PartialEq, Debug)]
+#[cfg(not(feature = "no_std"))]
+pub struct CurrentFix {
+    pub utc_time: Option<String>,
+    pub utc_date: Option<String>,
+    pub latitude_degrees: Option<f64>,
+    pub longitude_degrees: Option<f64>,
+    pub altitude_m: Option<f32>,
+    pub speed: Option<MetersPerSecond>,
+    pub course: Option<Degrees>,
+    pub fix_type: Option<FixType>,
+    pub pdop: Option<f32>,
+    pub hdop: Option<f32>,
+    pub vdop: Option<f32>,
+    /// The most recently completed GSV scan for each constellation that has reported one.
+    pub satellites: HashMap<Talker, SatelliteVec>,
+}
+
+#[cfg(not(feature = "no_std"))]
+impl CurrentFix {
+    fn new() -> CurrentFix {
+        CurrentFix {
+            utc_time: None,
+            utc_date: None,
+            latitude_degrees: None,
+            longitude_degrees: None,
+            altitude_m: None,
+            speed: None,
+            course: None,
+            fix_type: None,
+            pdop: None,
+            hdop: None,
+            vdop: None,
+            satellites: HashMap::new(),
+        }
+    }
+}
+
+/**
+ * Parses raw NMEA sentences and folds each one into a single evolving `CurrentFix`, so a caller
+ * doesn't have to manually stitch together altitude from GGA, course/speed from VTG/RMC, DOP from
+ * GSA, and satellites from GSV itself. GSV pages are buffered by a `GsvCollector` internally, so
+ * `fix.satellites` for a constellation only changes once a full scan has arrived, never mid-page.
+ * Like `GsvCollector`, this needs an allocator and isn't available under the `no_std` feature.
+ */
+#[cfg(not(feature = "no_std"))]
+pub struct Nmea {
+    pub fix: CurrentFix,
+    gsv_collector: GsvCollector,
+}
+
+#[cfg(not(feature = "no_std"))]
+impl Nmea {
+    pub fn new() -> Nmea {
+        Nmea {
+            fix: CurrentFix::new(),
+            gsv_collector: GsvCollector::new(),
+        }
+    }
+
+    /**
+     * Parses one raw sentence and merges it into `fix`. Returns the parse error, if any, without
+     * otherwise changing `fix`.
+     */
+    pub fn parse_into(&mut self, sentence: &str) -> Result<(), NmeaError> {
+        let message = match NmeaMessage::parse(sentence) {
+            Ok(message) => message,
+            Err(e) => return Err(e),
+        };
+        match message {
+            NmeaMessage::Gga(gga) => {
+                self.fix.utc_time = Some(gga.utc_time);
+                self.fix.latitude_degrees = Some(gga.latitude_degrees);
+                self.fix.longitude_degrees = Some(gga.longitude_degrees);
+                self.fix.altitude_m = Some(gga.altitude_m);
+                self.fix.hdop = Some(gga.hdop);
+            }
+            NmeaMessage::Rmc(rmc) => {
+                self.fix.utc_time = Some(rmc.utc_time);
+                self.fix.utc_date = Some(rmc.utc_date);
+                self.fix.latitude_degrees = Some(rmc.latitude_degrees);
+                self.fix.longitude_degrees = Some(rmc.longitude_degrees);
+                self.fix.speed = Some(rmc.speed);
+                self.fix.course = Some(rmc.course);
+            }
+            NmeaMessage::Vtg(vtg) => {
+                self.fix.speed = Some(vtg.speed);
+                self.fix.course = Some(vtg.course);
+            }
+            NmeaMessage::Gsa(gsa) => {
+                self.fix.fix_type = Some(gsa.fix_type);
+                self.fix.pdop = Some(gsa.pdop);
+                self.fix.hdop = Some(gsa.hdop);
+                self.fix.vdop = Some(gsa.vdop);
+            }
+            NmeaMessage::Gsv(gsv) => {
+                let constellation = gsv.constellation;
+                if let Some(sky) = self.gsv_collector.ingest(gsv) {
+                    self.fix.satellites.insert(constellation, sky.satellites);
+                }
+            }
+            NmeaMessage::Gll(gll) => {
+                self.fix.latitude_degrees = Some(gll.latitude_degrees);
+                self.fix.longitude_degrees = Some(gll.longitude_degrees);
+            }
+            NmeaMessage::Sti(_) | NmeaMessage::Binary(_) | NmeaMessage::Ais(_) => (),
+        }
+        Ok(())
+    }
+}
+
+/**
+ * The per-sentence output rate multipliers for `PMTK314`: 0 disables a sentence, 1 emits it every
+ * fix, and N emits it every Nth fix.
+ */
+#[allow(dead_code)]
+pub struct NmeaOutputRates {
+    pub gll: i32,
+    pub rmc: i32,
+    pub vtg: i32,
+    pub gga: i32,
+    pub gsa: i32,
+    pub gsv: i32,
+}
+
+/**
+ * Builds the proprietary `$PMTK` control sentences used to configure these GPS receivers:
+ * setting the fix/update rate, choosing which NMEA sentences are enabled, and issuing
+ * hot/warm/cold restarts. Unlike `NmeaMessage::parse`, there's nothing to dispatch on here, so
+ * this is just a namespace for a handful of free functions that each build one command string.
+ */
+#[allow(dead_code)]
+pub struct PmtkCommand;
+
+impl PmtkCommand {
+    /**
+     * PMTK220: sets the position fix/update interval, in milliseconds.
+     */
+    pub fn set_fix_interval(interval_ms: i32) -> String {
+        PmtkCommand::encode(&format!("PMTK220,{}", interval_ms))
+    }
+
+    /**
+     * PMTK314: enables or disables each output sentence at the given rate. The trailing fields
+     * are other SiRF/MTK sentence types this crate doesn't parse, so they're always left
+     * disabled.
+     */
+    pub fn set_nmea_output(rates: &NmeaOutputRates) -> String {
+        let body = format!(
+            "PMTK314,{},{},{},{},{},{},0,0,0,0,0,0,0,0,0,0,0,0,0",
+            rates.gll, rates.rmc, rates.vtg, rates.gga, rates.gsa, rates.gsv
+        );
+        PmtkCommand::encode(&body)
+    }
+
+    /**
+     * PMTK101: hot restart, using ephemeris and the last known position/time.
+     */
+    pub fn hot_restart() -> String {
+        PmtkCommand::encode("PMTK101")
+    }
+
+    /**
+     * PMTK102: warm restart, discarding ephemeris but keeping other stored data.
+     */
+    pub fn warm_restart() -> String {
+        PmtkCommand::encode("PMTK102")
+    }
+
+    /**
+     * PMTK104: cold restart, discarding all stored data and starting from scratch.
+     */
+    pub fn cold_restart() -> String {
+        PmtkCommand::encode("PMTK104")
+    }
+
+    fn encode(body: &str) -> String {
+        format!("${}*{}\r\n", body, checksum(body))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::NmeaMessage::Binary;
     use super::{
-        BinaryMessage, FixMode, FixType, GgaMessage, GllMessage, GsaMessage, GsvMessage,
-        NmeaMessage, RmcMessage, SatelliteInformation, StiMessage, VtgMessage,
+        AisCollector, AisMessage, BinaryMessage, FixMode, FixType, GgaMessage, GllMessage,
+        GsaMessage, GsvCollector, GsvMessage, Nmea, NmeaAggregator, NmeaError, NmeaMessage, NmeaOutputRates,
+        PmtkCommand, RmcMessage, SatelliteInformation, StiMessage, Talker, VtgMessage,
     };
     use std::fs::File;
     use std::io::{BufRead, BufReader};
@@ -659,11 +1775,16 @@ mod tests {
     fn test_parse_gga() {
         let message = "$GPGGA,033403.456,0102.3456,N,0102.3456,W,1,11,0.8,108.2,M,,,,0000*01\r\n";
         let expected = GgaMessage {
+            constellation: Talker::Gps,
+            utc_time: "033403.456".to_string(),
             latitude_degrees: 1.0390933333333334f64,
             longitude_degrees: -1.0390933333333334f64,
+            latitude_nanodegrees: 1039093333,
+            longitude_nanodegrees: -1039093333,
+            altitude_m: 108.2,
             hdop: 0.8f32,
         };
-        match NmeaMessage::parse_gga(message) {
+        match NmeaMessage::parse_gga(message, Talker::Gps) {
             Ok(gga) => assert!(expected == gga),
             _ => assert!(false),
         };
@@ -674,10 +1795,11 @@ mod tests {
         // 36 km/h = 10 m/s
         let message = "$GPVTG,123.4,T,356.1,M,000.0,N,0036.0,K,A*32\r\n";
         let expected = VtgMessage {
+            constellation: Talker::Gps,
             course: 123.4,
             speed: 10.0,
         };
-        match NmeaMessage::parse_vtg(message) {
+        match NmeaMessage::parse_vtg(message, Talker::Gps) {
             Ok(vtg) => assert!(expected == vtg),
             _ => assert!(false),
         }
@@ -688,13 +1810,18 @@ mod tests {
         let message =
             "$GPRMC,111636.932,A,2447.0949,N,12100.5223,E,000.0,000.0,030407,003.9,W,A*12\r\n";
         let expected = RmcMessage {
+            constellation: Talker::Gps,
+            utc_time: "111636.932".to_string(),
+            utc_date: "030407".to_string(),
             latitude_degrees: 24.784915,
             longitude_degrees: 121.008705,
+            latitude_nanodegrees: 24784915000,
+            longitude_nanodegrees: 121008705000,
             speed: 0.0,
             course: 0.0,
             magnetic_variation: 3.9,
         };
-        match NmeaMessage::parse_rmc(message) {
+        match NmeaMessage::parse_rmc(message, Talker::Gps) {
             Ok(rmc) => assert!(expected == rmc),
             _ => assert!(false),
         }
@@ -704,6 +1831,7 @@ mod tests {
     fn test_parse_gsa() {
         let message = "$GPGSA,A,3,05,12,21,22,30,09,18,06,14,01,31,,1.2,0.8,0.6*36\r\n";
         let expected = GsaMessage {
+            constellation: Talker::Gps,
             mode: FixMode::Automatic,
             fix_type: FixType::ThreeD,
             satellites_used: 11,
@@ -711,7 +1839,7 @@ mod tests {
             hdop: 0.8,
             vdop: 0.6,
         };
-        match NmeaMessage::parse_gsa(message) {
+        match NmeaMessage::parse_gsa(message, Talker::Gps) {
             Ok(gsa) => assert!(expected == gsa),
             _ => assert!(false),
         }
@@ -721,6 +1849,7 @@ mod tests {
     fn test_parse_gsv() {
         let message = "$GPGSV,3,1,12,05,54,069,45,12,44,061,44,21,07,184,46,22,78,289,47*72\r\n";
         let expected = GsvMessage {
+            constellation: Talker::Gps,
             message_count: 3,
             message_sequence_number: 1,
             satellites_in_view: 12,
@@ -751,13 +1880,14 @@ mod tests {
                 },
             ],
         };
-        match NmeaMessage::parse_gsv(message) {
+        match NmeaMessage::parse_gsv(message, Talker::Gps) {
             Ok(gsv) => assert!(expected == gsv),
             _ => assert!(false),
         }
 
         let message_2 = "$GPGSV,3,2,12,30,65,118,45,09,12,047,37,18,62,157,47,06,08,144,45*7C\r\n";
         let expected_2 = GsvMessage {
+            constellation: Talker::Gps,
             message_count: 3,
             message_sequence_number: 2,
             satellites_in_view: 12,
@@ -788,7 +1918,7 @@ mod tests {
                 },
             ],
         };
-        match NmeaMessage::parse_gsv(message_2) {
+        match NmeaMessage::parse_gsv(message_2, Talker::Gps) {
             Ok(gsv) => {
                 println!("\n{:?}\n{:?}", expected_2, gsv);
                 assert!(expected_2 == gsv)
@@ -801,10 +1931,13 @@ mod tests {
     fn test_parse_gll() {
         let message = "$GPGLL,2447.0944,N,12100.5213,E,112609.932,A,A*57\r\n";
         let expected = GllMessage {
+            constellation: Talker::Gps,
             latitude_degrees: 24.784906666666668,
             longitude_degrees: 121.00868833333334,
+            latitude_nanodegrees: 24784906667,
+            longitude_nanodegrees: 121008688333,
         };
-        match NmeaMessage::parse_gll(message) {
+        match NmeaMessage::parse_gll(message, Talker::Gps) {
             Ok(gll) => assert!(expected == gll),
             _ => assert!(false),
         };
@@ -831,11 +1964,11 @@ mod tests {
 
     #[test]
     fn test_parse() {
-        let gga = "$GPGGA,033403.456,0102.3456,N,0102.3456,W,1,11,0.8,108.2,M,,,,0000*01\r\n";
-        let vtg = "$GPVTG,123.4,T,356.1,M,000.0,N,0036.0,K,A*32\r\n";
+        let gga = "$GPGGA,033403.456,0102.3456,N,0102.3456,W,1,11,0.8,108.2,M,,,,0000*2E\r\n";
+        let vtg = "$GPVTG,123.4,T,356.1,M,000.0,N,0036.0,K,A*13\r\n";
         let rmc =
             "$GPRMC,111636.932,A,2447.0949,N,12100.5223,E,000.0,000.0,030407,003.9,W,A*12\r\n";
-        let gsa = "$GPGSA,A,3,05,12,21,22,30,09,18,06,14,01,31,,1.2,0.8,0.6*36\r\n";
+        let gsa = "$GPGSA,A,3,05,12,21,22,30,09,18,06,14,01,31,,1.2,0.8,0.6*39\r\n";
         let gsv = "$GPGSV,3,1,12,05,54,069,45,12,44,061,44,21,07,184,46,22,78,289,47*72\r\n";
         let gll = "$GPGLL,2447.0944,N,12100.5213,E,112609.932,A,A*57\r\n";
         let sti = "$PSTI,004,001,1,34.7,121.6,-48.2,99912,29.4*08\r\n";
@@ -870,6 +2003,114 @@ mod tests {
         };
     }
 
+    #[test]
+    fn test_parse_multi_constellation() {
+        let glonass_gsv = "$GLGSV,1,1,03,70,28,157,34,71,63,058,37,72,35,221,36*52\r\n";
+        match NmeaMessage::parse(glonass_gsv).unwrap() {
+            NmeaMessage::Gsv(gsv) => assert!(gsv.constellation == Talker::Glonass),
+            _ => assert!(false),
+        };
+
+        let galileo_rmc =
+            "$GARMC,111636.932,A,2447.0949,N,12100.5223,E,000.0,000.0,030407,003.9,W,A*03\r\n";
+        match NmeaMessage::parse(galileo_rmc).unwrap() {
+            NmeaMessage::Rmc(rmc) => assert!(rmc.constellation == Talker::Galileo),
+            _ => assert!(false),
+        };
+
+        let beidou_gsa = "$GBGSA,A,3,05,12,21,22,30,09,18,06,14,01,31,,1.2,0.8,0.6*2B\r\n";
+        match NmeaMessage::parse(beidou_gsa).unwrap() {
+            NmeaMessage::Gsa(gsa) => assert!(gsa.constellation == Talker::Beidou),
+            _ => assert!(false),
+        };
+
+        let combined_gga =
+            "$GNGGA,033403.456,0102.3456,N,0102.3456,W,1,11,0.8,108.2,M,,,,0000*30\r\n";
+        match NmeaMessage::parse(combined_gga).unwrap() {
+            NmeaMessage::Gga(gga) => assert!(gga.constellation == Talker::Combined),
+            _ => assert!(false),
+        };
+
+        let qzss_gll = "$GQGLL,2447.0944,N,12100.5213,E,112609.932,A,A*56\r\n";
+        match NmeaMessage::parse(qzss_gll).unwrap() {
+            NmeaMessage::Gll(gll) => assert!(gll.constellation == Talker::Qzss),
+            _ => assert!(false),
+        };
+
+        assert!(NmeaMessage::parse("$XXGGA,\r\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_beidou_bd_alias() {
+        // Some receivers use "BD" instead of the standard "GB" talker id for BeiDou.
+        let beidou_gsa = "$BDGSA,A,3,05,12,21,22,30,09,18,06,14,01,31,,1.2,0.8,0.6*28\r\n";
+        match NmeaMessage::parse(beidou_gsa).unwrap() {
+            NmeaMessage::Gsa(gsa) => assert!(gsa.constellation == Talker::Beidou),
+            _ => assert!(false),
+        };
+    }
+
+    #[test]
+    fn test_parse_checksum_mismatch() {
+        let corrupted = "$GPGGA,033403.456,0102.3456,N,0102.3456,W,1,11,0.8,108.2,M,,,,0000*FF\r\n";
+        match NmeaMessage::parse(corrupted) {
+            Err(NmeaError::ChecksumMismatch { expected, found }) => {
+                assert!(expected == "2E");
+                assert!(found == "FF");
+            }
+            _ => assert!(false),
+        }
+
+        assert!(NmeaMessage::parse("$GPGGA,033403.456\r\n").is_err());
+
+        let valid = "$GPGGA,033403.456,0102.3456,N,0102.3456,W,1,11,0.8,108.2,M,,,,0000*2E\r\n";
+        assert!(NmeaMessage::parse(valid).is_ok());
+    }
+
+    #[test]
+    fn test_parse_degrees_minutes_nanodegrees() {
+        let cases = [
+            ("4530.3001", 45505001667i64),
+            ("0102.3456", 1039093333i64),
+            ("12100.5223", 121008705000i64),
+        ];
+        for &(field, expected) in cases.iter() {
+            match NmeaMessage::parse_degrees_minutes_nanodegrees(field) {
+                Ok(ndeg) => assert!(ndeg == expected, "{} -> {} (expected {})", field, ndeg, expected),
+                Err(e) => panic!("{} failed to parse: {}", field, e),
+            }
+        }
+
+        // Minutes must be strictly less than 60.
+        assert!(NmeaMessage::parse_degrees_minutes_nanodegrees("0160.0000").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "no_std")]
+    fn test_parse_gsv_under_no_std() {
+        // GsvMessage::satellites is a heapless::Vec under this feature, and parse_gsv builds it
+        // the same way regardless; this only exercises the no_std storage path.
+        let message = "$GPGSV,1,1,03,05,54,069,45,12,44,061,44,21,07,184,46*4F\r\n";
+        let gsv = NmeaMessage::parse_gsv(message, Talker::Gps).unwrap();
+        assert!(gsv.satellites.len() == 3);
+        assert!(gsv.satellites[0].id == 5);
+    }
+
+    #[test]
+    fn test_parse_unchecked_ignores_checksum() {
+        let corrupted = "$GPGGA,033403.456,0102.3456,N,0102.3456,W,1,11,0.8,108.2,M,,,,0000*FF\r\n";
+        match NmeaMessage::parse_unchecked(corrupted) {
+            Ok(NmeaMessage::Gga(_gga)) => (),
+            _ => assert!(false),
+        }
+
+        let valid = "$GPGGA,033403.456,0102.3456,N,0102.3456,W,1,11,0.8,108.2,M,,,,0000*2E\r\n";
+        match NmeaMessage::parse_unchecked(valid) {
+            Ok(NmeaMessage::Gga(_gga)) => (),
+            _ => assert!(false),
+        }
+    }
+
     #[test]
     fn test_tty() {
         // This will fail on everything but the Pi, so let's just ignore it if we're not running on
@@ -903,7 +2144,7 @@ mod tests {
         match NmeaMessage::parse(&message) {
             Ok(_m) => (),
             Err(e) => panic!(format!(
-                "Unable to parse NmeaMessage\n{}\nbecause {}",
+                "Unable to parse NmeaMessage\n{}\nbecause {:?}",
                 message, e
             )),
         }
@@ -944,4 +2185,300 @@ mod tests {
     fn test_convert() {
         assert!((convert![f32, 0xBD4FE154u32] - -0.050752).abs() < 0.001);
     }
+
+    #[test]
+    fn test_aggregator_emits_on_epoch_rollover() {
+        let gga_1 = "$GPGGA,033403.456,0102.3456,N,0102.3456,W,1,11,0.8,108.2,M,,,,0000*2E\r\n";
+        let gsa = "$GPGSA,A,3,05,12,21,22,30,09,18,06,14,01,31,,1.2,0.8,0.6*39\r\n";
+        let rmc_1 =
+            "$GPRMC,033403.456,A,2447.0949,N,12100.5223,E,010.0,090.0,030407,003.9,W,A*10\r\n";
+        let gga_2 = "$GPGGA,033404.456,0102.3456,N,0102.3456,W,1,11,0.8,110.2,M,,,,0000*20\r\n";
+
+        let mut aggregator = NmeaAggregator::new();
+
+        assert!(aggregator
+            .ingest(&NmeaMessage::parse(gga_1).unwrap())
+            .is_none());
+        assert!(aggregator
+            .ingest(&NmeaMessage::parse(gsa).unwrap())
+            .is_none());
+        assert!(aggregator
+            .ingest(&NmeaMessage::parse(rmc_1).unwrap())
+            .is_none());
+
+        // The UTC time in gga_2 is a new epoch, so this should flush the solution built from
+        // gga_1, gsa, and rmc_1.
+        match aggregator.ingest(&NmeaMessage::parse(gga_2).unwrap()) {
+            Some(solution) => {
+                assert!(solution.latitude_degrees == 24.784915);
+                assert!(solution.altitude_m == 108.2);
+                // RMC reports speed in knots: 10 knots = 5.144 m/s.
+                assert!(solution.speed == 10.0 * 0.5144);
+                assert!(solution.course == 90.0);
+                assert!((solution.v_east - 10.0 * 0.5144).abs() < 0.001);
+                assert!((solution.v_north - 0.0).abs() < 0.001);
+                assert!(solution.pdop == 1.2);
+                assert!(solution.hdop == 0.8);
+                assert!(solution.vdop == 0.6);
+                assert!(solution.satellites_used == 11);
+                assert!(solution.fix_type == FixType::ThreeD);
+            }
+            None => assert!(false),
+        }
+    }
+
+    #[test]
+    fn test_gsv_collector_reassembles_burst() {
+        let message_1 = "$GPGSV,3,1,12,05,54,069,45,12,44,061,44,21,07,184,46,22,78,289,47*72\r\n";
+        let message_2 = "$GPGSV,3,2,12,30,65,118,45,09,12,047,37,18,62,157,47,06,08,144,45*7C\r\n";
+        let message_3 = "$GPGSV,3,3,12,04,21,202,39,15,34,276,41,19,08,043,33,24,72,198,44*76\r\n";
+
+        let mut collector = GsvCollector::new();
+
+        let gsv_1 = NmeaMessage::parse_gsv(message_1, Talker::Gps).unwrap();
+        assert!(collector.ingest(gsv_1).is_none());
+
+        let gsv_2 = NmeaMessage::parse_gsv(message_2, Talker::Gps).unwrap();
+        assert!(collector.ingest(gsv_2).is_none());
+
+        let gsv_3 = NmeaMessage::parse_gsv(message_3, Talker::Gps).unwrap();
+        match collector.ingest(gsv_3) {
+            Some(sky) => {
+                assert!(sky.satellites_in_view == 12);
+                assert!(sky.satellites.len() == 12);
+                assert!(sky.satellites[0].id == 5);
+                assert!(sky.satellites[11].id == 24);
+            }
+            None => assert!(false),
+        }
+    }
+
+    #[test]
+    fn test_gsv_collector_discards_interrupted_burst() {
+        let message_1 = "$GPGSV,3,1,12,05,54,069,45,12,44,061,44,21,07,184,46,22,78,289,47*72\r\n";
+        // A new burst for the same talker starts before the first one finished; the first burst
+        // should be discarded rather than silently merged with the new one.
+        let restarted = "$GPGSV,2,1,6,05,54,069,45,12,44,061,44,21,07,184,46,22,78,289,47*46\r\n";
+        let message_2_of_restarted = "$GPGSV,2,2,6,30,65,118,45,09,12,047,37*4B\r\n";
+
+        let mut collector = GsvCollector::new();
+
+        let gsv_1 = NmeaMessage::parse_gsv(message_1, Talker::Gps).unwrap();
+        assert!(collector.ingest(gsv_1).is_none());
+
+        let gsv_restarted = NmeaMessage::parse_gsv(restarted, Talker::Gps).unwrap();
+        assert!(collector.ingest(gsv_restarted).is_none());
+
+        let gsv_2_of_restarted = NmeaMessage::parse_gsv(message_2_of_restarted, Talker::Gps).unwrap();
+        match collector.ingest(gsv_2_of_restarted) {
+            Some(sky) => {
+                assert!(sky.satellites_in_view == 6);
+                assert!(sky.satellites.len() == 6);
+            }
+            None => assert!(false),
+        }
+    }
+
+    #[test]
+    fn test_encode_gga_round_trips() {
+        let message = "$GPGGA,033403.456,0102.3456,N,0102.3456,W,1,11,0.8,108.2,M,,,,0000*2E\r\n";
+        let gga = NmeaMessage::parse_gga(message, Talker::Gps).unwrap();
+        let encoded = NmeaMessage::Gga(gga).encode();
+        match NmeaMessage::parse(&encoded).unwrap() {
+            NmeaMessage::Gga(round_tripped) => {
+                assert!(round_tripped.constellation == Talker::Gps);
+                assert!((round_tripped.latitude_degrees - 1.0390933333333334).abs() < 0.0001);
+                assert!((round_tripped.longitude_degrees - -1.0390933333333334).abs() < 0.0001);
+                assert!(round_tripped.altitude_m == 108.2);
+                assert!(round_tripped.hdop == 0.8);
+            }
+            _ => assert!(false),
+        }
+    }
+
+    #[test]
+    fn test_encode_rmc_round_trips() {
+        let message =
+            "$GPRMC,111636.932,A,2447.0949,N,12100.5223,E,000.0,000.0,030407,003.9,W,A*12\r\n";
+        let rmc = NmeaMessage::parse_rmc(message, Talker::Gps).unwrap();
+        let encoded = NmeaMessage::Rmc(rmc).encode();
+        match NmeaMessage::parse(&encoded).unwrap() {
+            NmeaMessage::Rmc(round_tripped) => {
+                assert!((round_tripped.latitude_degrees - 24.784915).abs() < 0.0001);
+                assert!((round_tripped.course - 0.0).abs() < 0.0001);
+                assert!((round_tripped.magnetic_variation - 3.9).abs() < 0.0001);
+                assert!(round_tripped.utc_date == "030407");
+            }
+            _ => assert!(false),
+        }
+    }
+
+    #[test]
+    fn test_encode_gsv_round_trips() {
+        let message = "$GPGSV,3,1,12,05,54,069,45,12,44,061,44,21,07,184,46,22,78,289,47*72\r\n";
+        let expected = NmeaMessage::parse_gsv(message, Talker::Gps).unwrap();
+        let gsv = NmeaMessage::parse_gsv(message, Talker::Gps).unwrap();
+        let encoded = NmeaMessage::Gsv(gsv).encode();
+        match NmeaMessage::parse(&encoded).unwrap() {
+            NmeaMessage::Gsv(round_tripped) => assert!(expected == round_tripped),
+            _ => assert!(false),
+        }
+    }
+
+    #[test]
+    fn test_nmea_merges_sentences_into_current_fix() {
+        let gga = "$GPGGA,033403.456,0102.3456,N,0102.3456,W,1,11,0.8,108.2,M,,,,0000*2E\r\n";
+        let gsa = "$GPGSA,A,3,05,12,21,22,30,09,18,06,14,01,31,,1.2,0.8,0.6*39\r\n";
+        let rmc =
+            "$GPRMC,111636.932,A,2447.0949,N,12100.5223,E,000.0,000.0,030407,003.9,W,A*12\r\n";
+        let gsv_1 = "$GPGSV,3,1,12,05,54,069,45,12,44,061,44,21,07,184,46,22,78,289,47*72\r\n";
+        let gsv_2 = "$GPGSV,3,2,12,30,65,118,45,09,12,047,37,18,62,157,47,06,08,144,45*7C\r\n";
+        let gsv_3 = "$GPGSV,3,3,12,04,21,202,39,15,34,276,41,19,08,043,33,24,72,198,44*76\r\n";
+
+        let mut nmea = Nmea::new();
+        assert!(nmea.parse_into(gga).is_ok());
+        assert!(nmea.parse_into(gsa).is_ok());
+        assert!(nmea.parse_into(rmc).is_ok());
+        assert!(nmea.parse_into(gsv_1).is_ok());
+        assert!(nmea.parse_into(gsv_2).is_ok());
+
+        // The third and final GSV page hasn't arrived yet, so no scan should be recorded.
+        assert!(nmea.fix.satellites.get(&Talker::Gps).is_none());
+
+        assert!(nmea.parse_into(gsv_3).is_ok());
+
+        assert!(nmea.fix.altitude_m == Some(108.2));
+        assert!(nmea.fix.utc_date == Some("030407".to_string()));
+        assert!(nmea.fix.fix_type == Some(FixType::ThreeD));
+        assert!(nmea.fix.pdop == Some(1.2));
+        assert!(nmea.fix.satellites.get(&Talker::Gps).unwrap().len() == 12);
+    }
+
+    #[test]
+    fn test_pmtk_commands_have_valid_checksums() {
+        assert!(PmtkCommand::set_fix_interval(200) == "$PMTK220,200*2C\r\n");
+        assert!(PmtkCommand::hot_restart() == "$PMTK101*32\r\n");
+        assert!(PmtkCommand::warm_restart() == "$PMTK102*31\r\n");
+        assert!(PmtkCommand::cold_restart() == "$PMTK104*37\r\n");
+
+        let rates = NmeaOutputRates {
+            gll: 0,
+            rmc: 1,
+            vtg: 0,
+            gga: 1,
+            gsa: 1,
+            gsv: 1,
+        };
+        let command = PmtkCommand::set_nmea_output(&rates);
+        assert!(command.starts_with("$PMTK314,0,1,0,1,1,1,"));
+        assert!(command.ends_with("\r\n"));
+    }
+
+    #[test]
+    fn test_parse_ais_fragment() {
+        let message = "!AIVDM,2,1,9,A,55P5TL01VIaAL@7WKO@mBplU@<PDhPlU8Ht00000016,0*7B\r\n";
+        match NmeaMessage::parse(message).unwrap() {
+            NmeaMessage::Ais(ais) => {
+                assert!(ais.fragment_count == 2);
+                assert!(ais.fragment_number == 1);
+                assert!(ais.sequence_id == Some(9));
+                assert!(ais.channel == 'A');
+                assert!(ais.payload == "55P5TL01VIaAL@7WKO@mBplU@<PDhPlU8Ht00000016");
+                assert!(ais.fill_bits == 0);
+            }
+            _ => assert!(false),
+        }
+
+        // No sequential message id, as for a single-fragment message.
+        let single = "!AIVDM,1,1,,A,15M67FC000G?ufbE`FepT@3n00Sa,0*5F\r\n";
+        match NmeaMessage::parse(single).unwrap() {
+            NmeaMessage::Ais(ais) => assert!(ais.sequence_id.is_none()),
+            _ => assert!(false),
+        }
+    }
+
+    #[test]
+    fn test_ais_collector_decodes_single_fragment_position_report() {
+        let message = "!AIVDM,1,1,,A,15M67FC000G?ufbE`FepT@3n00Sa,0*5F\r\n";
+        let fragment = match NmeaMessage::parse(message).unwrap() {
+            NmeaMessage::Ais(ais) => ais,
+            _ => panic!("Expected an Ais fragment"),
+        };
+
+        let mut collector = AisCollector::new();
+        match collector.ingest(fragment) {
+            Some(Ok(AisMessage::PositionReport(report))) => {
+                assert!(report.message_type == 1);
+                assert!(report.mmsi == 366053209);
+                assert!(report.speed_over_ground_knots == 0.0);
+                assert!((report.latitude_degrees - 37.80211833333333).abs() < 0.00001);
+                assert!((report.longitude_degrees - -122.34161833333333).abs() < 0.00001);
+                assert!((report.course_over_ground - 219.3).abs() < 0.01);
+                assert!(report.true_heading == Some(1));
+            }
+            other => panic!("Expected a decoded position report, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_ais_collector_reassembles_multi_fragment_static_data() {
+        let part_a = "!AIVDM,2,1,7,A,55M:Ih000001@E=@<4i@E=B1<,0*2B\r\n";
+        let part_b = "!AIVDM,2,2,7,A,PU0000000000016,0*23\r\n";
+
+        let mut collector = AisCollector::new();
+
+        let fragment_a = match NmeaMessage::parse(part_a).unwrap() {
+            NmeaMessage::Ais(ais) => ais,
+            _ => panic!("Expected an Ais fragment"),
+        };
+        assert!(collector.ingest(fragment_a).is_none());
+
+        let fragment_b = match NmeaMessage::parse(part_b).unwrap() {
+            NmeaMessage::Ais(ais) => ais,
+            _ => panic!("Expected an Ais fragment"),
+        };
+        match collector.ingest(fragment_b) {
+            Some(Ok(AisMessage::StaticData(data))) => {
+                assert!(data.mmsi == 366123456);
+                assert!(data.vessel_name == "TEST SHIP");
+                assert!(data.ship_type == 70);
+            }
+            other => panic!("Expected decoded static data, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_ais_collector_discards_interrupted_message() {
+        let part_a = "!AIVDM,2,1,7,A,55M:Ih000001@E=@<4i@E=B1<,0*2B\r\n";
+        // A new message reusing the same sequential id starts before the first one finished; the
+        // first message should be discarded rather than silently merged with the new one.
+        let restarted = "!AIVDM,2,1,7,A,51b4N?@00000u@P<4hhu@PE:1,0*07\r\n";
+        let restarted_part_2 = "!AIVDM,2,2,7,A,<PU00000000000N,0*56\r\n";
+
+        let mut collector = AisCollector::new();
+
+        let fragment_a = match NmeaMessage::parse(part_a).unwrap() {
+            NmeaMessage::Ais(ais) => ais,
+            _ => panic!("Expected an Ais fragment"),
+        };
+        assert!(collector.ingest(fragment_a).is_none());
+
+        let fragment_restarted = match NmeaMessage::parse(restarted).unwrap() {
+            NmeaMessage::Ais(ais) => ais,
+            _ => panic!("Expected an Ais fragment"),
+        };
+        assert!(collector.ingest(fragment_restarted).is_none());
+
+        let fragment_restarted_2 = match NmeaMessage::parse(restarted_part_2).unwrap() {
+            NmeaMessage::Ais(ais) => ais,
+            _ => panic!("Expected an Ais fragment"),
+        };
+        match collector.ingest(fragment_restarted_2) {
+            Some(Ok(AisMessage::StaticData(data))) => {
+                assert!(data.mmsi == 111222333);
+                assert!(data.vessel_name == "OTHER SHIP");
+            }
+            other => panic!("Expected the restarted message's static data, got {:?}", other),
+        }
+    }
 }
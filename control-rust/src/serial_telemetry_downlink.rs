@@ -0,0 +1,199 @@
+/// Packs a snapshot of the vehicle's telemetry into a small framed binary packet and writes it
+/// to a serial port, so a laptop-side tool can watch a run live. Modeled on PX4's FrSky/HoTT
+/// telemetry drivers: fixed-size payload, one sync byte, one checksum byte.
+use std::io::Write;
+use std::mem::transmute;
+
+use telemetry::{Degrees, Meter, MetersPerSecond};
+use telemetry_message::{CompassMessage, GpsMessage};
+
+const SYNC_BYTE: u8 = 0xAA;
+/// Fields: estimated x, estimated y, estimated heading, estimated speed, GPS heading, GPS speed,
+/// compass heading, throttle, steering; all f32.
+const PAYLOAD_LEN: usize = 9 * 4;
+
+/// A snapshot of everything a downlink packet reports.
+pub struct TelemetrySnapshot {
+    pub estimated_x_m: Meter,
+    pub estimated_y_m: Meter,
+    pub estimated_heading_d: Degrees,
+    pub estimated_speed_m_s: MetersPerSecond,
+    pub gps_heading_d: Degrees,
+    pub gps_speed_m_s: MetersPerSecond,
+    pub compass_heading_d: Degrees,
+    pub throttle: f32,
+    pub steering: f32,
+}
+
+impl TelemetrySnapshot {
+    pub fn new(
+        estimated_x_m: Meter,
+        estimated_y_m: Meter,
+        estimated_heading_d: Degrees,
+        estimated_speed_m_s: MetersPerSecond,
+        gps_message: &GpsMessage,
+        compass_message: &CompassMessage,
+        throttle: f32,
+        steering: f32,
+    ) -> TelemetrySnapshot {
+        TelemetrySnapshot {
+            estimated_x_m: estimated_x_m,
+            estimated_y_m: estimated_y_m,
+            estimated_heading_d: estimated_heading_d,
+            estimated_speed_m_s: estimated_speed_m_s,
+            gps_heading_d: gps_message.heading,
+            gps_speed_m_s: gps_message.speed,
+            compass_heading_d: compass_message.heading,
+            throttle: throttle,
+            steering: steering,
+        }
+    }
+}
+
+/// A sink that a `TelemetrySnapshot` can be periodically written to. A separate trait from
+/// `Logger` since a snapshot isn't a log message, but the two are meant to coexist: a vehicle run
+/// can have both a `StdoutLogger` for text and a `SerialTelemetryDownlink` for live state.
+pub trait TelemetryDownlink {
+    fn send(&mut self, snapshot: &TelemetrySnapshot) -> ();
+}
+
+/// Writes framed telemetry packets to a serial port.
+pub struct SerialTelemetryDownlink {
+    serial: Box<Write>,
+}
+
+impl SerialTelemetryDownlink {
+    pub fn new(serial: Box<Write>) -> SerialTelemetryDownlink {
+        SerialTelemetryDownlink { serial: serial }
+    }
+}
+
+impl TelemetryDownlink for SerialTelemetryDownlink {
+    fn send(&mut self, snapshot: &TelemetrySnapshot) -> () {
+        let packet = encode(snapshot);
+        match self.serial.write(&packet) {
+            Ok(_) => (),
+            Err(err) => error!("Unable to write telemetry downlink packet: {}", err),
+        }
+    }
+}
+
+/// Frames a snapshot as `[SYNC_BYTE, payload..., checksum]`, where `checksum` is the XOR of every
+/// payload byte.
+fn encode(snapshot: &TelemetrySnapshot) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(1 + PAYLOAD_LEN + 1);
+    packet.push(SYNC_BYTE);
+    for &value in &[
+        snapshot.estimated_x_m,
+        snapshot.estimated_y_m,
+        snapshot.estimated_heading_d,
+        snapshot.estimated_speed_m_s,
+        snapshot.gps_heading_d,
+        snapshot.gps_speed_m_s,
+        snapshot.compass_heading_d,
+        snapshot.throttle,
+        snapshot.steering,
+    ] {
+        let bits: u32 = unsafe { transmute(value) };
+        let bytes: [u8; 4] = unsafe { transmute(bits.to_le()) };
+        packet.extend_from_slice(&bytes);
+    }
+
+    let mut checksum = 0u8;
+    for &byte in &packet[1..] {
+        checksum ^= byte;
+    }
+    packet.push(checksum);
+    packet
+}
+
+/// Decodes a single framed packet (as written by `encode`) back into its field values, returning
+/// `None` if `packet` isn't the expected length, doesn't start with `SYNC_BYTE`, or fails its
+/// checksum.
+pub fn decode(packet: &[u8]) -> Option<[f32; 9]> {
+    if packet.len() != 1 + PAYLOAD_LEN + 1 || packet[0] != SYNC_BYTE {
+        return None;
+    }
+    let payload = &packet[1..1 + PAYLOAD_LEN];
+    let mut checksum = 0u8;
+    for &byte in payload {
+        checksum ^= byte;
+    }
+    if checksum != packet[1 + PAYLOAD_LEN] {
+        return None;
+    }
+
+    let mut values = [0f32; 9];
+    for (index, value) in values.iter_mut().enumerate() {
+        let offset = index * 4;
+        let mut bytes = [0u8; 4];
+        bytes.clone_from_slice(&payload[offset..offset + 4]);
+        let bits: u32 = u32::from_le(unsafe { transmute(bytes) });
+        *value = unsafe { transmute(bits) };
+    }
+    Some(values)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use telemetry_message::{CompassMessage, GpsMessage};
+    use telemetry::Point;
+    use super::{decode, encode, TelemetrySnapshot};
+
+    fn snapshot() -> TelemetrySnapshot {
+        let gps_message = GpsMessage {
+            point: Point { x: 0.0, y: 0.0 },
+            heading: 45.0,
+            speed: 3.0,
+            std_dev_x: 2.0,
+            std_dev_y: 2.0,
+        };
+        let compass_message = CompassMessage {
+            heading: 47.0,
+            std_dev: 5.0,
+            magnetic_x: 0.0,
+            magnetic_y: 0.0,
+            magnetic_z: 0.0,
+        };
+        TelemetrySnapshot::new(
+            12.5,
+            -3.25,
+            44.0,
+            2.75,
+            &gps_message,
+            &compass_message,
+            0.5,
+            -0.25,
+        )
+    }
+
+    #[test]
+    fn test_round_trips_a_snapshot() {
+        let packet = encode(&snapshot());
+        let values = decode(&packet).expect("Expected a valid packet to decode");
+        assert_eq!(values, [12.5, -3.25, 44.0, 2.75, 45.0, 3.0, 47.0, 0.5, -0.25]);
+    }
+
+    #[test]
+    fn test_rejects_bad_checksum() {
+        let mut packet = encode(&snapshot());
+        let last = packet.len() - 1;
+        packet[last] ^= 0xff;
+        assert!(decode(&packet).is_none());
+    }
+
+    #[test]
+    fn test_rejects_wrong_length() {
+        let mut packet = encode(&snapshot());
+        packet.pop();
+        assert!(decode(&packet).is_none());
+    }
+
+    #[test]
+    fn test_rejects_bad_sync_byte() {
+        let mut packet = encode(&snapshot());
+        packet[0] = 0x00;
+        assert!(decode(&packet).is_none());
+    }
+}
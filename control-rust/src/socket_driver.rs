@@ -1,30 +1,62 @@
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
 use unix_socket::UnixStream;
 
 use driver::{Driver, Percentage};
 
-/// Sends drive commands to a Unix domain socket.
+const INITIAL_BACKOFF_MS: u64 = 100;
+const MAX_BACKOFF_MS: u64 = 5000;
+
+/// Sends drive commands to a Unix domain socket. If the socket write fails, reconnects with
+/// exponential backoff rather than giving up; the throttle/steering commanded while
+/// disconnected are simply superseded once the next `drive` call gets through.
 pub struct SocketDriver {
     throttle: Percentage,
     steering: Percentage,
     max_throttle: Percentage,
-    socket: UnixStream,
+    socket_path: PathBuf,
+    socket: Option<UnixStream>,
+    backoff_ms: u64,
 }
 
 impl SocketDriver {
-    pub fn new(max_throttle: Percentage) -> SocketDriver {
-        let server = Path::new("/tmp/driver-socket");
+    pub fn new(max_throttle: Percentage, socket_path: &str) -> SocketDriver {
+        let server = Path::new(socket_path);
         let socket = match UnixStream::connect(server) {
-            Ok(socket) => socket,
-            Err(e) => panic!("Unable to open Unix socket for driver: {}", e),
+            Ok(socket) => Some(socket),
+            Err(e) => {
+                error!("Unable to open Unix socket for driver: {}", e);
+                None
+            }
         };
 
         SocketDriver {
             throttle: 0.0,
             steering: 0.0,
             max_throttle: max_throttle,
-            socket: socket,}
+            socket_path: server.to_path_buf(),
+            socket: socket,
+            backoff_ms: INITIAL_BACKOFF_MS,
+        }
+    }
+
+    /// Waits out the current backoff delay, then tries to reconnect, doubling the delay on
+    /// failure up to `MAX_BACKOFF_MS`.
+    fn reconnect(&mut self) {
+        thread::sleep(Duration::from_millis(self.backoff_ms));
+        match UnixStream::connect(&self.socket_path) {
+            Ok(socket) => {
+                info!("Reconnected to driver socket");
+                self.socket = Some(socket);
+                self.backoff_ms = INITIAL_BACKOFF_MS;
+            }
+            Err(e) => {
+                warn!("Unable to reconnect to driver socket: {}", e);
+                self.backoff_ms = (self.backoff_ms * 2).min(MAX_BACKOFF_MS);
+            }
+        }
     }
 }
 
@@ -32,9 +64,22 @@ impl Driver for SocketDriver {
     fn drive(&mut self, throttle: Percentage, steering: Percentage) {
         self.throttle = self.max_throttle.max(throttle);
         self.steering = steering;
-        match self.socket.write(format!("{} {}\n", self.throttle, self.steering).as_bytes()) {
-            Ok(_) => (),
-            Err(err) => error!("Unable to send drive command: {}", err),
+
+        let message = format!("{} {}\n", self.throttle, self.steering);
+        let write_failed = match self.socket {
+            Some(ref mut socket) => match socket.write(message.as_bytes()) {
+                Ok(_) => false,
+                Err(err) => {
+                    error!("Unable to send drive command: {}", err);
+                    true
+                }
+            },
+            None => true,
+        };
+
+        if write_failed {
+            self.socket = None;
+            self.reconnect();
         }
     }
 
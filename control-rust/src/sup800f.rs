@@ -2,9 +2,27 @@
 use std::io::{BufRead, Error, ErrorKind, Result, Write};
 use std::mem::transmute;
 
+use telemetry_message::{AccelerometerMessage, CompassMessage};
+
+
+/// The SUP800F's message IDs for acknowledging or rejecting a configuration command, e.g. the
+/// "configure message type" command `_change_mode` sends.
+const MESSAGE_ID_ACK: u8 = 0x83;
+const MESSAGE_ID_NACK: u8 = 0x84;
+/// How many frames to read while waiting for a mode-change ack before giving up; sensor frames
+/// in flight when the command is sent arrive first and have to be skipped over.
+const MAX_ACK_ATTEMPTS: u32 = 10;
+
+/// Whether the SUP800F accepted the last configuration command.
+#[derive(Debug, PartialEq)]
+pub enum ModeAck {
+    Ack,
+    Nack,
+}
+
 
 /// Returns a single message.
-fn get_message(serial: &mut BufRead) -> Result<Vec<u8>> {
+pub fn get_message(serial: &mut BufRead) -> Result<Vec<u8>> {
     let mut byte = [0u8; 1];
     let mut length_buffer = [0u8; 2];
     // Keep consuming bytes until we see the header message
@@ -64,30 +82,99 @@ fn get_message(serial: &mut BufRead) -> Result<Vec<u8>> {
 }
 
 
-/// Switches to the NMEA message mode.
-fn switch_to_nmea_mode(serial: &mut Write) -> Result<()> {
-    _change_mode(serial, 1)
+/// Switches to the NMEA message mode and waits for the module to acknowledge it.
+pub fn switch_to_nmea_mode(reader: &mut BufRead, writer: &mut Write) -> Result<ModeAck> {
+    _change_mode(reader, writer, 1)
 }
 
 
-/// Switches to the binary message mode.
-fn switch_to_binary_mode(serial: &mut Write) -> Result<()> {
-    _change_mode(serial, 2)
+/// Switches to the binary message mode and waits for the module to acknowledge it.
+pub fn switch_to_binary_mode(reader: &mut BufRead, writer: &mut Write) -> Result<ModeAck> {
+    _change_mode(reader, writer, 2)
 }
 
 
 /// Change reporting mode between NMEA messages or binary (temperature, accelerometer and
-/// magnetometer) mode.
-fn _change_mode(serial: &mut Write, mode: u8) -> Result<()> {
+/// magnetometer) mode, then read frames on `reader` until the ack/nack for this command arrives,
+/// skipping over any sensor or NMEA frames that were already in flight.
+fn _change_mode(reader: &mut BufRead, writer: &mut Write, mode: u8) -> Result<ModeAck> {
     // message id, 9 = configure message type
     let payload: Vec<u8> = vec![9, mode, 0];
     let message = _format_message(&payload);
-    match serial.write(&message.into_boxed_slice()) {
+    match writer.write(&message.into_boxed_slice()) {
         Ok(_) => (),
         Err(err) => return Err(err),
     }
-    // TODO: See if the mode changed successfully
-    Ok(())
+
+    for _ in 0..MAX_ACK_ATTEMPTS {
+        let frame = get_message(reader)?;
+        // Header (4 bytes) followed by the message ID byte
+        if frame.len() < 5 {
+            continue;
+        }
+        match frame[4] {
+            MESSAGE_ID_ACK => return Ok(ModeAck::Ack),
+            MESSAGE_ID_NACK => return Ok(ModeAck::Nack),
+            _ => continue,
+        }
+    }
+    Err(Error::new(ErrorKind::TimedOut, "No ack received for mode-change command"))
+}
+
+
+/// Parses a binary-mode sensor frame from the SUP800F: `message` is the full frame, including
+/// the 4-byte header, the message ID byte, six big-endian IEEE-754 f32 readings (accelerometer
+/// X/Y/Z then magnetometer X/Y/Z), and the trailing XOR checksum byte. Returns `None` if the
+/// frame is too short or its checksum doesn't match.
+fn parse_binary_sensor(message: &[u8]) -> Option<(AccelerometerMessage, CompassMessage)> {
+    const HEADER_LEN: usize = 4;
+    const MESSAGE_ID_LEN: usize = 1;
+    const FLOATS_LEN: usize = 6 * 4;
+    let sensor_start = HEADER_LEN + MESSAGE_ID_LEN;
+    let checksum_index = sensor_start + FLOATS_LEN;
+    if message.len() <= checksum_index {
+        return None;
+    }
+
+    let checksummed = &message[HEADER_LEN..checksum_index];
+    let expected_checksum = checksummed.iter().fold(0u8, |part, byte| part ^ byte);
+    if message[checksum_index] != expected_checksum {
+        return None;
+    }
+
+    let floats = &message[sensor_start..checksum_index];
+    let acceleration_x = read_be_f32(&floats[0..4]);
+    let acceleration_y = read_be_f32(&floats[4..8]);
+    let acceleration_z = read_be_f32(&floats[8..12]);
+    let magnetic_x = read_be_f32(&floats[12..16]);
+    let magnetic_y = read_be_f32(&floats[16..20]);
+    let magnetic_z = read_be_f32(&floats[20..24]);
+
+    Some((
+        AccelerometerMessage {
+            x: acceleration_x,
+            y: acceleration_y,
+            z: acceleration_z,
+        },
+        CompassMessage {
+            // Filled in by FilteredTelemetry once it tilt-compensates with the accelerometer.
+            heading: 0.0,
+            std_dev: 1.0,
+            magnetic_x: magnetic_x,
+            magnetic_y: magnetic_y,
+            magnetic_z: magnetic_z,
+        },
+    ))
+}
+
+
+/// Reads 4 big-endian bytes as an IEEE-754 f32.
+fn read_be_f32(bytes: &[u8]) -> f32 {
+    let bits: u32 = ((bytes[0] as u32) << 24)
+        | ((bytes[1] as u32) << 16)
+        | ((bytes[2] as u32) << 8)
+        | (bytes[3] as u32);
+    unsafe { transmute(bits) }
 }
 
 
@@ -109,7 +196,7 @@ fn _format_message(payload: &Vec<u8>) -> Vec<u8> {
 mod tests {
     use std::io::Cursor;
     use std::mem::transmute;
-    use super::{get_message, _format_message};
+    use super::{get_message, parse_binary_sensor, switch_to_nmea_mode, _format_message, ModeAck};
 
     #[test]
     fn test_format_message() {
@@ -160,4 +247,62 @@ mod tests {
         };
         assert!(first_message.len() == length);
     }
+
+    #[test]
+    fn test_parse_binary_sensor() {
+        let message: Vec<u8> = vec![
+            // Header
+            0x0A, 0x0A, 0x00, 0x19,
+            // Message ID
+            0xCF,
+            // Accelerometer X/Y/Z, magnetometer X/Y/Z, big-endian f32s
+            0x3D, 0xCC, 0xCC, 0xCD,
+            0xBE, 0x4C, 0xCC, 0xCD,
+            0x3F, 0x7A, 0xE1, 0x48,
+            0x41, 0x20, 0x00, 0x00,
+            0xC0, 0xA0, 0x00, 0x00,
+            0x42, 0x20, 0x00, 0x00,
+            // Checksum
+            0x43,
+        ];
+
+        let (accelerometer, compass) = match parse_binary_sensor(&message) {
+            Some(parsed) => parsed,
+            None => panic!("Unable to parse binary sensor message"),
+        };
+        assert!((accelerometer.x - 0.1).abs() < 0.0001);
+        assert!((accelerometer.y - -0.2).abs() < 0.0001);
+        assert!((accelerometer.z - 0.98).abs() < 0.0001);
+        assert!((compass.magnetic_x - 10.0).abs() < 0.0001);
+        assert!((compass.magnetic_y - -5.0).abs() < 0.0001);
+        assert!((compass.magnetic_z - 40.0).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_change_mode_ack() {
+        // Header, 1-byte payload length, then the ack message ID
+        let ack_frame: Vec<u8> = vec![0xA0, 0xA1, 0x01, 0x00, 0x83];
+        let mut reader = Cursor::new(ack_frame);
+        let mut writer: Vec<u8> = Vec::new();
+        let ack = switch_to_nmea_mode(&mut reader, &mut writer).unwrap();
+        assert!(ack == ModeAck::Ack);
+        // The mode-change command should have actually been written out
+        assert!(!writer.is_empty());
+    }
+
+    #[test]
+    fn test_parse_binary_sensor_bad_checksum() {
+        let mut message: Vec<u8> = vec![
+            0x0A, 0x0A, 0x00, 0x19,
+            0xCF,
+            0x3D, 0xCC, 0xCC, 0xCD,
+            0xBE, 0x4C, 0xCC, 0xCD,
+            0x3F, 0x7A, 0xE1, 0x48,
+            0x41, 0x20, 0x00, 0x00,
+            0xC0, 0xA0, 0x00, 0x00,
+            0x42, 0x20, 0x00, 0x00,
+            0x00,
+        ];
+        assert!(parse_binary_sensor(&message).is_none());
+    }
 }
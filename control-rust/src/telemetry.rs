@@ -85,20 +85,48 @@ fn equatorial_radius_m() -> f64 {
 
 
 /**
- * Returns the number of meters per degree of latitude. I don't know how to
+ * Returns the WGS84 ellipsoid's eccentricity squared, e^2 = 2f - f^2. I don't know how to
  * define constants in Rust.
  */
+fn eccentricity_squared() -> f64 {
+    let flattening = 1.0 / 298.257223563;
+    2.0 * flattening - flattening * flattening
+}
+
+
+/**
+ * Returns the number of meters per degree of latitude at a given latitude, using the WGS84
+ * ellipsoid's meridional radius of curvature M. This varies from about 110.6 km/degree at the
+ * equator to about 111.7 km/degree at the poles, rather than being constant as on a sphere.
+ */
+pub fn m_per_latitude_d_at(latitude: f64) -> f64 {
+    let phi = latitude.to_radians();
+    let sin_phi = phi.sin();
+    let meridional_radius_m = equatorial_radius_m() * (1.0 - eccentricity_squared())
+        / (1.0 - eccentricity_squared() * sin_phi * sin_phi).powf(1.5);
+    meridional_radius_m * f64::consts::PI_2 / 360.0
+}
+
+
+/**
+ * Returns the number of meters per degree of latitude at the equator. Prefer
+ * `m_per_latitude_d_at` when a latitude is available, since this varies by latitude.
+ */
 pub fn m_per_latitude_d() -> f64 {
-    equatorial_radius_m() * f64::consts::PI_2 / 360.0
+    m_per_latitude_d_at(0.0)
 }
 
 
 /**
- * Returns the number of meters per degree longitude at a given latitude.
+ * Returns the number of meters per degree longitude at a given latitude, using the WGS84
+ * ellipsoid's prime-vertical radius of curvature N rather than assuming a perfect sphere.
  */
 pub fn latitude_d_to_m_per_longitude_d(latitude: f64) -> f64 {
-    let radius_m: f64 = latitude.cosine_d() * equatorial_radius_m();
-    let circumference_m: f64 = f64::consts::PI_2 * radius_m;
+    let phi = latitude.to_radians();
+    let sin_phi = phi.sin();
+    let prime_vertical_radius_m =
+        equatorial_radius_m() / (1.0 - eccentricity_squared() * sin_phi * sin_phi).sqrt();
+    let circumference_m: f64 = f64::consts::PI_2 * prime_vertical_radius_m * phi.cos();
     circumference_m / 360.0
 }
 
@@ -190,22 +218,270 @@ pub fn distance(point_1: &Point, point_2: &Point) -> Meter {
 
 
 /**
- * Latitude and longitude to meters from an arbitrary central location. The Pi only single
- * precision hardware float capability which affords 6~9 digits of precision. If we only used
- * latitude and longitude, we would need double prevision everywhere, which would run slowly on the
- * Pi. As long as we're within a kilometer of the central point, we should have at least centimeter
- * precision, which should work fine.
+ * Signed perpendicular distance of `point` from the line through `segment_start` and
+ * `segment_end`, treating the segment as the intended course. Positive means `point` is to the
+ * right of the segment (as faced travelling from `segment_start` to `segment_end`), negative
+ * means left. If the segment is degenerate (start and end coincide), falls back to the plain
+ * distance from `segment_start`.
+ */
+#[allow(dead_code)]
+pub fn cross_track_distance(segment_start: &Point, segment_end: &Point, point: &Point) -> Meter {
+    let segment_x = segment_end.x - segment_start.x;
+    let segment_y = segment_end.y - segment_start.y;
+    let segment_length_m = (segment_x * segment_x + segment_y * segment_y).sqrt();
+    if segment_length_m < 0.0001 {
+        return distance(segment_start, point);
+    }
+
+    let point_x = point.x - segment_start.x;
+    let point_y = point.y - segment_start.y;
+    (segment_y * point_x - segment_x * point_y) / segment_length_m
+}
+
+
+/**
+ * Computes the geodesic distance and initial bearing between two WGS84 latitude/longitude points
+ * using Vincenty's inverse formula. Unlike `distance`, which works over the locally-projected
+ * `Point`s and degrades beyond the ~1 km window described in `latitude_longitude_to_point`, this
+ * stays accurate on the ellipsoid across the whole event field and at arbitrary reference points.
+ * Coincident points return (0.0, 0.0); if the iteration fails to converge, which can happen for
+ * near-antipodal points, this falls back to the planar `distance` as a last resort.
+ */
+#[allow(dead_code)]
+pub fn geodesic_distance_and_bearing(
+    latitude_1: f64,
+    longitude_1: f64,
+    latitude_2: f64,
+    longitude_2: f64,
+) -> (Meter, Degrees) {
+    // WGS84 ellipsoid parameters
+    let a = 6378137.0f64;
+    let f = 1.0 / 298.257223563f64;
+    let b = (1.0 - f) * a;
+
+    if (latitude_1 - latitude_2).abs() < 1e-12 && (longitude_1 - longitude_2).abs() < 1e-12 {
+        return (0.0, 0.0);
+    }
+
+    let big_l = (longitude_2 - longitude_1).to_radians();
+    let u_1 = ((1.0 - f) * latitude_1.to_radians().tan()).atan();
+    let u_2 = ((1.0 - f) * latitude_2.to_radians().tan()).atan();
+    let (sin_u_1, cos_u_1) = (u_1.sin(), u_1.cos());
+    let (sin_u_2, cos_u_2) = (u_2.sin(), u_2.cos());
+
+    let mut lambda = big_l;
+    let mut sin_lambda;
+    let mut cos_lambda;
+    let mut sin_sigma = 0.0f64;
+    let mut cos_sigma = 1.0f64;
+    let mut sigma = 0.0f64;
+    let mut sin_alpha;
+    let mut cos_sq_alpha = 1.0f64;
+    let mut cos_2sigma_m = 0.0f64;
+    let mut converged = false;
+
+    for _ in 0..200 {
+        sin_lambda = lambda.sin();
+        cos_lambda = lambda.cos();
+        sin_sigma = ((cos_u_2 * sin_lambda).powi(2)
+            + (cos_u_1 * sin_u_2 - sin_u_1 * cos_u_2 * cos_lambda).powi(2))
+        .sqrt();
+        if sin_sigma == 0.0 {
+            // Coincident points
+            return (0.0, 0.0);
+        }
+        cos_sigma = sin_u_1 * sin_u_2 + cos_u_1 * cos_u_2 * cos_lambda;
+        sigma = sin_sigma.atan2(cos_sigma);
+        sin_alpha = cos_u_1 * cos_u_2 * sin_lambda / sin_sigma;
+        cos_sq_alpha = 1.0 - sin_alpha * sin_alpha;
+        cos_2sigma_m = if cos_sq_alpha == 0.0 {
+            // On the equatorial line
+            0.0
+        } else {
+            cos_sigma - 2.0 * sin_u_1 * sin_u_2 / cos_sq_alpha
+        };
+        let c = f / 16.0 * cos_sq_alpha * (4.0 + f * (4.0 - 3.0 * cos_sq_alpha));
+        let previous_lambda = lambda;
+        lambda = big_l
+            + (1.0 - c)
+                * f
+                * sin_alpha
+                * (sigma
+                    + c * sin_sigma
+                        * (cos_2sigma_m + c * cos_sigma * (-1.0 + 2.0 * cos_2sigma_m * cos_2sigma_m)));
+        if (lambda - previous_lambda).abs() < 1e-12 {
+            converged = true;
+            break;
+        }
+    }
+
+    if !converged {
+        // Near-antipodal points can fail to converge; fall back to the planar approximation
+        // rather than returning a nonsense result.
+        return (
+            distance(
+                &latitude_longitude_to_point(latitude_1, longitude_1),
+                &latitude_longitude_to_point(latitude_2, longitude_2),
+            ),
+            0.0,
+        );
+    }
+
+    let u_sq = cos_sq_alpha * (a * a - b * b) / (b * b);
+    let big_a = 1.0 + u_sq / 16384.0 * (4096.0 + u_sq * (-768.0 + u_sq * (320.0 - 175.0 * u_sq)));
+    let big_b = u_sq / 1024.0 * (256.0 + u_sq * (-128.0 + u_sq * (74.0 - 47.0 * u_sq)));
+    let delta_sigma = big_b
+        * sin_sigma
+        * (cos_2sigma_m
+            + big_b / 4.0
+                * (cos_sigma * (-1.0 + 2.0 * cos_2sigma_m * cos_2sigma_m)
+                    - big_b / 6.0
+                        * cos_2sigma_m
+                        * (-3.0 + 4.0 * sin_sigma * sin_sigma)
+                        * (-3.0 + 4.0 * cos_2sigma_m * cos_2sigma_m)));
+    let distance_m = (b * big_a * (sigma - delta_sigma)) as Meter;
+
+    let final_sin_lambda = lambda.sin();
+    let final_cos_lambda = lambda.cos();
+    let bearing_d = (final_sin_lambda * cos_u_2)
+        .atan2(cos_u_1 * sin_u_2 - sin_u_1 * cos_u_2 * final_cos_lambda)
+        .to_degrees() as Degrees;
+
+    (distance_m, wrap_degrees(bearing_d))
+}
+
+
+/**
+ * Computes the destination point reached by travelling distance_m meters on initial bearing
+ * heading_d from (latitude, longitude), using the spherical great-circle formula. This
+ * complements `relative_degrees`/`rotate_degrees_clockwise` for dead reckoning between GPS
+ * fixes and for generating offset waypoints. A spherical model is cheap enough for the Pi;
+ * unlike `geodesic_distance_and_bearing`, ellipsoidal accuracy isn't needed for this use.
+ * Zero distance returns the input unchanged. The returned latitude is clamped to [-90, 90] and
+ * the returned longitude is normalized to [-180, 180), so travelling across a pole still
+ * produces a valid result.
+ */
+#[allow(dead_code)]
+pub fn point_at(latitude: f64, longitude: f64, heading_d: Degrees, distance_m: Meter) -> (f64, f64) {
+    if distance_m == 0.0 {
+        return (latitude, longitude);
+    }
+
+    let radius_m = 6378137.0f64;
+    let angular_distance = distance_m as f64 / radius_m;
+    let bearing = (heading_d as f64).to_radians();
+
+    let phi_1 = latitude.to_radians();
+    let lambda_1 = longitude.to_radians();
+
+    let sin_phi_2 = phi_1.sin() * angular_distance.cos()
+        + phi_1.cos() * angular_distance.sin() * bearing.cos();
+    // Clamp before asin(); floating-point error can otherwise push this just outside [-1, 1].
+    let sin_phi_2 = sin_phi_2.max(-1.0).min(1.0);
+    let phi_2 = sin_phi_2.asin();
+    let lambda_2 = lambda_1
+        + (bearing.sin() * angular_distance.sin() * phi_1.cos())
+            .atan2(angular_distance.cos() - phi_1.sin() * sin_phi_2);
+
+    let latitude_2 = phi_2.to_degrees().max(-90.0).min(90.0);
+    let mut longitude_2 = lambda_2.to_degrees();
+    while longitude_2 < -180.0 {
+        longitude_2 += 360.0;
+    }
+    while longitude_2 >= 180.0 {
+        longitude_2 -= 360.0;
+    }
+
+    (latitude_2, longitude_2)
+}
+
+
+/**
+ * A local tangent-plane projection centered on an arbitrary reference latitude/longitude, so
+ * that GPS fixes can be converted to meter-scale `Point`s and back. The reference must stay f64
+ * (single precision loses the 7th decimal), while the resulting `Point` stays f32, same as
+ * `latitude_longitude_to_point` below. As long as positions stay within a kilometer of the
+ * reference, this should have at least centimeter precision.
+ */
+pub struct MapProjection {
+    pub ref_lat: f64,
+    pub ref_lon: f64,
+    last_scale_lat: Option<f64>,
+    cached_longitude_scale_m: f64,
+}
+
+impl MapProjection {
+    /**
+     * Creates a projection centered on the given reference latitude/longitude.
+     */
+    pub fn new(ref_lat: f64, ref_lon: f64) -> MapProjection {
+        MapProjection {
+            ref_lat: ref_lat,
+            ref_lon: ref_lon,
+            last_scale_lat: None,
+            cached_longitude_scale_m: latitude_d_to_m_per_longitude_d(ref_lat),
+        }
+    }
+
+    /**
+     * Returns meters per degree of longitude at `self.ref_lat`, cached because the cosine behind
+     * it is pricier than the Pi's weak FPU would like and `ref_lat` rarely changes between calls
+     * in the common case where the car stays within a kilometer of one spot: the scale is only
+     * recomputed once the reference latitude has moved more than ~0.01 degrees (about 1 km)
+     * since the last call. The result is floored well above zero so a reference near the poles
+     * can't collapse the scale to nothing.
+     */
+    fn longitude_scale_m(&mut self) -> f64 {
+        let recompute = match self.last_scale_lat {
+            Some(last_lat) => (self.ref_lat - last_lat).abs() >= 0.01,
+            None => true,
+        };
+        if recompute {
+            let scale = latitude_d_to_m_per_longitude_d(self.ref_lat);
+            let floor = 0.01 * latitude_d_to_m_per_longitude_d(0.0);
+            self.cached_longitude_scale_m = if scale < floor { floor } else { scale };
+            self.last_scale_lat = Some(self.ref_lat);
+        }
+        self.cached_longitude_scale_m
+    }
+
+    /**
+     * Projects a latitude/longitude into meters relative to the reference point.
+     */
+    pub fn project(&mut self, latitude: f64, longitude: f64) -> Point {
+        let latitude_diff = latitude - self.ref_lat;
+        let longitude_diff = longitude - self.ref_lon;
+        Point {
+            x: (self.longitude_scale_m() * longitude_diff) as f32,
+            y: (m_per_latitude_d_at(self.ref_lat) * latitude_diff) as f32,
+        }
+    }
+
+    /**
+     * Converts a projected `Point` back into latitude/longitude.
+     */
+    pub fn reproject(&mut self, point: &Point) -> (f64, f64) {
+        let latitude =
+            self.ref_lat + point.y as f64 / m_per_latitude_d_at(self.ref_lat);
+        let longitude = self.ref_lon + point.x as f64 / self.longitude_scale_m();
+        (latitude, longitude)
+    }
+}
+
+
+/**
+ * Latitude and longitude to meters from the Boulder reference point, via `MapProjection`. The
+ * Pi only single precision hardware float capability which affords 6~9 digits of precision. If
+ * we only used latitude and longitude, we would need double prevision everywhere, which would
+ * run slowly on the Pi. As long as we're within a kilometer of the central point, we should have
+ * at least centimeter precision, which should work fine.
  */
 pub fn latitude_longitude_to_point(latitude: f64, longitude: f64) -> Point {
     let central_latitude = 40.0941804f64;
     let central_longitude = -105.1872092f64;
-    let latitude_diff = latitude - central_latitude;
-    let longitude_diff = longitude - central_longitude;
-    Point {
-        // Hopefully LLVM will optimize this call out
-        x: (latitude_d_to_m_per_longitude_d(central_latitude) * longitude_diff) as f32,
-        y: (m_per_latitude_d() * latitude_diff) as f32,
-    }
+    // Hopefully LLVM will optimize this call out
+    let mut projection = MapProjection::new(central_latitude, central_longitude);
+    projection.project(latitude, longitude)
 }
 
 
@@ -287,11 +563,16 @@ mod tests {
     use super::{
         Point,
         Degrees,
+        cross_track_distance,
         difference_d,
         distance,
         equatorial_radius_m,
+        geodesic_distance_and_bearing,
         is_turn_left,
         latitude_d_to_m_per_longitude_d,
+        m_per_latitude_d_at,
+        MapProjection,
+        point_at,
         relative_degrees,
         rotate_degrees_clockwise,
         wrap_degrees,
@@ -381,14 +662,30 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]  // We're using a less accurate spherical method right now
     fn test_latitude_d_to_m_per_longitude_d_oblong() {
         // Known values, from http://www.csgnetwork.com/degreelenllavcalc.html
         // M_PER_D_LATITUDE = 111319.458,
-        assert_approx_eq(
-            // Boulder
-            latitude_d_to_m_per_longitude_d(40.08),
-            85294.08886768305);
+        // That calculator uses Snyder's polynomial approximation rather than the exact
+        // ellipsoidal formula above, so they only agree to within a few centimeters per degree.
+        let m_per_longitude_d = latitude_d_to_m_per_longitude_d(40.08);  // Boulder
+        assert!(
+            (m_per_longitude_d - 85294.08886768305).abs() < 0.1,
+            "m_per_longitude_d was {}", m_per_longitude_d);
+    }
+
+    #[test]
+    fn test_m_per_latitude_d_at() {
+        // Unlike longitude, meters per degree of latitude is smallest at the equator and
+        // largest at the poles.
+        assert!(m_per_latitude_d_at(0.0) < m_per_latitude_d_at(40.08));
+        assert!(m_per_latitude_d_at(40.08) < m_per_latitude_d_at(90.0));
+
+        // Should be symmetrical
+        for degrees in (0i32..85) {
+            assert_approx_eq(
+                m_per_latitude_d_at(degrees as f64),
+                m_per_latitude_d_at(-degrees as f64));
+        }
     }
 
     #[test]
@@ -496,4 +793,140 @@ mod tests {
                 &Point { x: -1.0, y: -3.0 }),
             5.0);
     }
+
+    #[test]
+    fn test_cross_track_distance() {
+        // On the segment: no cross-track error
+        assert_approx_eq(
+            cross_track_distance(
+                &Point { x: 0.0, y: 0.0 },
+                &Point { x: 0.0, y: 10.0 },
+                &Point { x: 0.0, y: 5.0 }),
+            0.0);
+
+        // Heading north (0, 0) -> (0, 10), a point to the east is to the right
+        assert_approx_eq(
+            cross_track_distance(
+                &Point { x: 0.0, y: 0.0 },
+                &Point { x: 0.0, y: 10.0 },
+                &Point { x: 3.0, y: 5.0 }),
+            3.0);
+
+        // ...and a point to the west is to the left
+        assert_approx_eq(
+            cross_track_distance(
+                &Point { x: 0.0, y: 0.0 },
+                &Point { x: 0.0, y: 10.0 },
+                &Point { x: -3.0, y: 5.0 }),
+            -3.0);
+
+        // Heading east, a point to the north is to the left
+        assert_approx_eq(
+            cross_track_distance(
+                &Point { x: 0.0, y: 0.0 },
+                &Point { x: 10.0, y: 0.0 },
+                &Point { x: 5.0, y: 3.0 }),
+            -3.0);
+
+        // Degenerate segment falls back to plain distance from the start
+        assert_approx_eq(
+            cross_track_distance(
+                &Point { x: 1.0, y: 1.0 },
+                &Point { x: 1.0, y: 1.0 },
+                &Point { x: 4.0, y: 5.0 }),
+            5.0);
+    }
+
+    #[test]
+    fn test_geodesic_distance_and_bearing_coincident() {
+        let (distance_m, bearing_d) = geodesic_distance_and_bearing(40.0, -105.0, 40.0, -105.0);
+        assert_approx_eq(distance_m, 0.0);
+        assert_approx_eq(bearing_d, 0.0);
+    }
+
+    #[test]
+    fn test_geodesic_distance_and_bearing_flinders_peak_to_buninyong() {
+        // A classic test case for Vincenty's formula: known distance and initial bearing between
+        // Flinders Peak and Buninyong, Australia.
+        // http://www.ngs.noaa.gov/PUBS_LIB/inverse.pdf
+        let (distance_m, bearing_d) = geodesic_distance_and_bearing(
+            -37.95103341666667,
+            144.42486788888888,
+            -37.65282113888889,
+            143.92649552777777,
+        );
+        assert!((distance_m - 54972.271).abs() < 0.01, "distance was {}", distance_m);
+        assert!((bearing_d - 306.86817).abs() < 0.01, "bearing was {}", bearing_d);
+    }
+
+    #[test]
+    fn test_map_projection_round_trip_at_reference() {
+        let mut projection = MapProjection::new(40.0941804, -105.1872092);
+        let point = projection.project(40.0941804, -105.1872092);
+        assert_approx_eq(point.x, 0.0);
+        assert_approx_eq(point.y, 0.0);
+
+        let (latitude, longitude) = projection.reproject(&point);
+        assert!((latitude - 40.0941804).abs() < 0.0000001);
+        assert!((longitude - -105.1872092).abs() < 0.0000001);
+    }
+
+    #[test]
+    fn test_map_projection_round_trip_within_1km() {
+        let mut projection = MapProjection::new(40.0941804, -105.1872092);
+        // Roughly 900 m north-east of the reference point (about 650 m north, 615 m east)
+        let latitude = 40.1;
+        let longitude = -105.18;
+        let point = projection.project(latitude, longitude);
+        let (round_tripped_latitude, round_tripped_longitude) = projection.reproject(&point);
+
+        // Centimeter-scale tolerance in degrees of latitude/longitude
+        assert!((round_tripped_latitude - latitude).abs() < 0.0000001);
+        assert!((round_tripped_longitude - longitude).abs() < 0.0000001);
+    }
+
+    #[test]
+    fn test_map_projection_caches_longitude_scale_until_reference_moves_a_kilometer() {
+        let mut projection = MapProjection::new(40.0, -105.0);
+        let initial_scale = projection.longitude_scale_m();
+
+        // A small nudge, well under the ~0.01 degree / 1 km threshold, shouldn't recompute.
+        projection.ref_lat = 40.001;
+        assert_approx_eq(projection.longitude_scale_m(), initial_scale);
+
+        // A nudge past the threshold should recompute to the new latitude's scale.
+        projection.ref_lat = 41.0;
+        let new_scale = projection.longitude_scale_m();
+        assert!((new_scale - latitude_d_to_m_per_longitude_d(41.0)).abs() < 0.00001);
+    }
+
+    #[test]
+    fn test_point_at_zero_distance_returns_input() {
+        let (latitude, longitude) = point_at(40.0, -105.0, 123.0, 0.0);
+        assert_approx_eq(latitude, 40.0);
+        assert_approx_eq(longitude, -105.0);
+    }
+
+    #[test]
+    fn test_point_at_due_north() {
+        // 1000 m due north should increase latitude by about 1000 / m_per_latitude_d, ~0.009 deg.
+        let (latitude, longitude) = point_at(40.0, -105.0, 0.0, 1000.0);
+        assert!((latitude - 40.00898).abs() < 0.0001, "latitude was {}", latitude);
+        assert_approx_eq(longitude, -105.0);
+    }
+
+    #[test]
+    fn test_point_at_due_east() {
+        let (latitude, longitude) = point_at(40.0, -105.0, 90.0, 1000.0);
+        assert!((latitude - 40.0).abs() < 0.0001, "latitude was {}", latitude);
+        assert!((longitude - -104.98827).abs() < 0.0001, "longitude was {}", longitude);
+    }
+
+    #[test]
+    fn test_point_at_across_pole() {
+        // Travelling due north past the pole should clamp to a valid latitude, not NaN.
+        let (latitude, longitude) = point_at(89.9, 0.0, 0.0, 50000.0);
+        assert!(latitude >= -90.0 && latitude <= 90.0, "latitude was {}", latitude);
+        assert!(longitude >= -180.0 && longitude < 180.0, "longitude was {}", longitude);
+    }
 }
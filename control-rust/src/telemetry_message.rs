@@ -10,15 +10,42 @@ pub struct GpsMessage {
 pub struct CompassMessage {
     pub heading: Degrees,
     pub std_dev: Degrees,
+    /// Raw magnetometer readings as the sensor reports them (not normalized), used to
+    /// tilt-compensate the heading above.
+    pub magnetic_x: f32,
+    pub magnetic_y: f32,
+    pub magnetic_z: f32,
+}
+pub struct AccelerometerMessage {
+    /// Normalized gravity components (g), used to tilt-compensate the compass heading.
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+pub struct ImuMessage {
+    /// Gyroscope-reported rate of turn, positive clockwise.
+    pub yaw_rate_d_s: Degrees,
+    /// Accelerometer-reported forward acceleration, with gravity already removed.
+    pub accel_m_s2: f32,
 }
 pub enum CommandMessage {
     CalibrateCompass,
     Start,
     Stop,
+    Pause,
+    Resume,
 }
 
 #[allow(dead_code)]
 pub enum TelemetryMessage {
     Gps(GpsMessage),
     Compass(CompassMessage),
+    Accelerometer(AccelerometerMessage),
+    Imu(ImuMessage),
+    /// Sent once when `Control` enters `CalibrateCompass`: starts collecting raw magnetometer
+    /// samples for the hard/soft-iron ellipsoid fit instead of just forwarding the latest one.
+    StartCompassCalibration,
+    /// Sent once `Control`'s calibration spin finishes: fits the collected samples and, if the
+    /// fit succeeds, starts applying the correction to future readings.
+    FinishCompassCalibration,
 }
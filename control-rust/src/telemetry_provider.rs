@@ -1,65 +1,64 @@
 /// Reads messages from the SUP800F module and forwards the data.
-use sup800f::{get_message, switch_to_binary_mode, switch_to_nmea_mode};
+use sup800f::{get_message, parse_binary_sensor, switch_to_binary_mode, switch_to_nmea_mode, ModeAck};
 
 use std::error::Error;
 use std::fs::File;
+use std::io::{self, BufRead, BufReader};
 use std::path::Path;
 use std::sync::mpsc::{Receiver, Sender};
 use std::thread;
 
-use telemetry::{
-    Degrees,
-    MetersPerSecond,
-    Point,
-    hdop_to_std_dev,
-    latitude_longitude_to_point,
-    wrap_degrees};
-use telemetry_message::{CompassMessage, GpsMessage, TelemetryMessage};
+use nmea::{MicroTesla, NmeaAggregator, NmeaMessage, PvtSolution};
+use telemetry::{Degrees, MetersPerSecond, hdop_to_std_dev, latitude_longitude_to_point, wrap_degrees};
+use telemetry_message::{AccelerometerMessage, CompassMessage, GpsMessage, TelemetryMessage};
 use termios::{Speed, Termio};
-use nmea::{MicroTesla, NmeaMessage};
+
+
+/// Which message format the SUP800F is currently configured to emit.
+enum Mode {
+    Nmea,
+    Binary,
+}
 
 
 pub struct TelemetryProvider {
-    speed: MetersPerSecond,
-    heading: Degrees,
-    magnetometer_std_dev: f32,
-    point: Point,
-    hdop: f32,
     telemetry_message_tx: Sender<TelemetryMessage>,
-    magnetometer_offsets: [f32; 2],
+    // How many binary accelerometer/magnetometer frames to read before switching back to NMEA
+    // mode for the next GPS fix; higher favors compass update rate, lower favors GPS update rate.
+    binary_frames_per_gps_fix: i32,
 }
 
 
 impl TelemetryProvider {
-    pub fn new(telemetry_message_tx: Sender<TelemetryMessage>) -> TelemetryProvider {
+    pub fn new(
+        telemetry_message_tx: Sender<TelemetryMessage>,
+        binary_frames_per_gps_fix: i32,
+    ) -> TelemetryProvider {
         TelemetryProvider {
-            speed: 0.0,
-            heading: 315.0,  // Starting line of the Sparkfun AVC
-            magnetometer_std_dev: 0.0,
-            point: latitude_longitude_to_point(40.090583, -105.185664),
-            hdop: 2.0,
             telemetry_message_tx: telemetry_message_tx,
-            magnetometer_offsets: [-4.43, -0.43],  // From observation
+            binary_frames_per_gps_fix: binary_frames_per_gps_fix,
         }
     }
 
-    /// Processes and forwards messages from the SUP800F module
+    /// Processes and forwards messages from the SUP800F module, alternating between NMEA mode
+    /// (for GGA/RMC/VTG/GSA position fixes) and binary mode (for accelerometer/magnetometer
+    /// frames) so both kinds of readings keep arriving.
     pub fn run(&mut self, quit_rx: Receiver<()>) {
-        let mut tty = match File::open(&Path::new("/dev/ttyAMA0")) {
+        let tty = match File::open(&Path::new("/dev/ttyAMA0")) {
             Ok(f) => f,
             Err(m) => panic!("Unable to open /dev/ttyAMA0: {}", m.description())
         };
         match tty.set_speed(Speed::B1152000) {
             Ok(_) => (),
-            Err(_) => {
-                error!("Unable to set TTY speed");
+            Err(e) => {
+                error!("Unable to set TTY speed: {}", e);
                 return;
             }
         }
         match tty.drop_input_output() {
             Ok(_) => (),
-            Err(_) => {
-                error!("Unable to drop TTY input and output");
+            Err(e) => {
+                error!("Unable to drop TTY input and output: {}", e);
                 return;
             }
         }
@@ -79,8 +78,22 @@ impl TelemetryProvider {
             return;
         }
 
-        let mut message = String::new();
-        let mut binary_message_count = 0;
+        // get_message needs a BufRead to find frame boundaries, but the mode-change commands
+        // need a plain Write, so the TTY is split into a buffered reader and a cloned writer.
+        let mut writer = match tty.try_clone() {
+            Ok(clone) => clone,
+            Err(e) => {
+                error!("Unable to clone GPS serial handle: {}", e);
+                return;
+            }
+        };
+        let mut reader = BufReader::new(tty);
+
+        let mut mode = Mode::Nmea;
+        self.request_mode(&mut reader, &mut writer, &mode);
+
+        let mut aggregator = NmeaAggregator::new();
+        let mut binary_frame_count = 0;
         loop {
             match quit_rx.try_recv() {
                 Ok(_) => {
@@ -90,100 +103,93 @@ impl TelemetryProvider {
                 Err(_) => (),
             };
 
-            // Blocking read
-            let message = match get_message(&mut tty) {
-                Ok(message) => message,
-                Err(e) => {
-                    error!("Unable to read line from GPS: {}", e);
-                    break;
-                }
-            };
-
-            match NmeaMessage::parse(&message) {
-                Ok(nmea) => match nmea {
-                    NmeaMessage::Binary(binary) => {
-                        let adjusted_x = binary.x_magnetic_field - self.magnetometer_offsets[0];
-                        let adjusted_y = binary.y_magnetic_field - self.magnetometer_offsets[1];
-                        self.heading = adjusted_x.atan2(adjusted_y);
-                        // TODO: Compute this
-                        self.magnetometer_std_dev = 1.0;
-                        if !self.send_compass() {
-                            break;
-                        }
-                        binary_message_count += 1;
-                    },
-                    NmeaMessage::Gga(gga) => {
-                        self.point = latitude_longitude_to_point(
-                           gga.latitude_degrees,
-                           gga.longitude_degrees);
-                        self.hdop = gga.hdop;
-                        if !self.send_gps() {
+            match mode {
+                Mode::Nmea => {
+                    let line = match get_line(&mut reader) {
+                        Ok(line) => line,
+                        Err(e) => {
+                            error!("Unable to read line from GPS: {}", e);
                             break;
                         }
-                        binary_message_count = -1;
-                    },
-                    NmeaMessage::Gll(gll) => {
-                        self.point = latitude_longitude_to_point(
-                           gll.latitude_degrees,
-                           gll.longitude_degrees);
-                        if !self.send_gps() {
+                    };
+                    match NmeaMessage::parse(line.trim()) {
+                        Ok(message) => {
+                            if let Some(solution) = aggregator.ingest(&message) {
+                                if !self.send_gps(&solution) {
+                                    break;
+                                }
+                                binary_frame_count = 0;
+                                mode = Mode::Binary;
+                                self.request_mode(&mut reader, &mut writer, &mode);
+                            }
+                        },
+                        Err(_) => (),
+                    }
+                },
+                Mode::Binary => {
+                    let frame = match get_message(&mut reader) {
+                        Ok(frame) => frame,
+                        Err(e) => {
+                            error!("Unable to read frame from GPS: {}", e);
                             break;
                         }
-                        binary_message_count = -1;
-                    },
-                    NmeaMessage::Gsa(gsa) => self.hdop = gsa.hdop,
-                    NmeaMessage::Gsv(_) => (),  // TODO Gsv is satellites in view?
-                    NmeaMessage::Vtg(vtg) => {
-                        self.heading = vtg.course;
-                        self.speed = vtg.speed;
-                    },
-                    NmeaMessage::Rmc(rmc) => {
-                        self.point = latitude_longitude_to_point(
-                           rmc.latitude_degrees,
-                           rmc.longitude_degrees);
-                        self.heading = rmc.course;
-                        self.speed = rmc.speed;
-                        if !self.send_gps() {
+                    };
+                    if let Some((accelerometer, compass)) = parse_binary_sensor(&frame) {
+                        if !self.send_accelerometer(accelerometer) || !self.send_compass(compass) {
                             break;
                         }
-                        binary_message_count = -1;
-                    },
-                    NmeaMessage::Sti(_) => (),  // I don't think there's anything useful here
-                    NmeaMessage::Ack(_) => (),  // TODO
+                        binary_frame_count += 1;
+                    }
+                    if binary_frame_count >= self.binary_frames_per_gps_fix {
+                        mode = Mode::Nmea;
+                        self.request_mode(&mut reader, &mut writer, &mode);
+                    }
                 },
-                Err(_) => (),
             }
+        }
+    }
 
-            // I don't expect binary_message_count to unexpectedly get above 3, but just in case
-            if binary_message_count >= 3 {
-                switch_to_nmea_mode(&mut tty);
-            } else if binary_message_count == -1 {
-                switch_to_binary_mode(&mut tty);
-            }
+    /// Asks the SUP800F to switch to `mode`, logging if it rejects the command or never
+    /// responds; `run` has already set its own `mode` to the requested one and keeps reading in
+    /// that mode either way.
+    fn request_mode(&self, reader: &mut BufReader<File>, writer: &mut File, mode: &Mode) {
+        let result = match *mode {
+            Mode::Nmea => switch_to_nmea_mode(reader, writer),
+            Mode::Binary => switch_to_binary_mode(reader, writer),
+        };
+        match result {
+            Ok(ModeAck::Ack) => (),
+            Ok(ModeAck::Nack) => warn!("SUP800F rejected mode-change request"),
+            Err(e) => error!("Unable to switch SUP800F mode: {}", e),
         }
     }
 
-    fn send_gps(&self) -> bool {
+    fn send_gps(&self, solution: &PvtSolution) -> bool {
+        let point = latitude_longitude_to_point(solution.latitude_degrees, solution.longitude_degrees);
         let status = self.telemetry_message_tx.send(
             TelemetryMessage::Gps(
                 GpsMessage {
-                    point: self.point,
-                    heading: self.heading,
-                    speed: self.speed,
-                    std_dev_x: hdop_to_std_dev(self.hdop),
-                    std_dev_y: hdop_to_std_dev(self.hdop),}));
+                    point: point,
+                    heading: solution.course,
+                    speed: solution.speed,
+                    std_dev_x: hdop_to_std_dev(solution.hdop),
+                    std_dev_y: hdop_to_std_dev(solution.hdop),}));
         match status {
             Ok(_) => true,
             Err(_) => false,
         }
     }
 
-    fn send_compass(&self) -> bool {
-        let status = self.telemetry_message_tx.send(
-            TelemetryMessage::Compass(
-                CompassMessage {
-                    heading: self.heading,
-                    std_dev: self.magnetometer_std_dev,}));
+    fn send_compass(&self, compass: CompassMessage) -> bool {
+        let status = self.telemetry_message_tx.send(TelemetryMessage::Compass(compass));
+        match status {
+            Ok(_) => true,
+            Err(_) => false,
+        }
+    }
+
+    fn send_accelerometer(&self, accelerometer: AccelerometerMessage) -> bool {
+        let status = self.telemetry_message_tx.send(TelemetryMessage::Accelerometer(accelerometer));
         match status {
             Ok(_) => true,
             Err(_) => false,
@@ -192,6 +198,17 @@ impl TelemetryProvider {
 }
 
 
+/// Reads a single newline-terminated NMEA sentence.
+fn get_line(reader: &mut BufRead) -> io::Result<String> {
+    let mut line = String::new();
+    match reader.read_line(&mut line) {
+        Ok(0) => Err(io::Error::new(io::ErrorKind::Other, "GPS connection closed")),
+        Ok(_) => Ok(line),
+        Err(e) => Err(e),
+    }
+}
+
+
 fn tilt_compensated_compass(
     magnetic_x: MicroTesla,
     magnetic_y: MicroTesla,
@@ -2,210 +2,359 @@
  * FFI bindings for the Termios library. There is a Termios crate but I had trouble getting it to
  * work. I couldn't get the example code to work and I'm not sure how you would call the different
  * functions anyway.
+ *
+ * Built on `libc::termios` and `libc`'s own `tc*`/`cf*` bindings rather than a hand-rolled struct
+ * and `extern` block, so the struct layout and the meaning of the `Speed` values are always right
+ * for whatever platform this is compiled for (the old hand-rolled `CTermios` only matched Linux:
+ * macOS/BSD have no `c_line` field, a different `c_cc` length, and literal-bit-rate baud values
+ * instead of Linux's baud *indices*). The `Termio` trait itself is unchanged.
  */
 
 extern crate enum_primitive;
 extern crate libc;
 
-use std::mem::transmute;
-use std::os::unix::prelude::AsRawFd;
+use std::io::{Error, ErrorKind};
+use std::os::unix::prelude::{AsRawFd, RawFd};
 use num::FromPrimitive;
 
 
 pub trait Termio {
-    fn set_speed(&self, speed: Speed) -> Result<i32, i32>;
-    fn get_speed(&self) -> Result<Speed, i32>;
-    fn drain(&self) -> Result<i32, i32>;
-    fn drop_input(&self) -> Result<i32, i32>;
-    fn drop_output(&self) -> Result<i32, i32>;
-    fn drop_input_output(&self) -> Result<i32, i32>;
-    fn input_buffer_count(&self) -> Result<i32, i32>;
+    fn set_speed(&self, speed: Speed) -> Result<i32, Error>;
+    fn get_speed(&self) -> Result<Speed, Error>;
+    fn drain(&self) -> Result<i32, Error>;
+    fn drop_input(&self) -> Result<i32, Error>;
+    fn drop_output(&self) -> Result<i32, Error>;
+    fn drop_input_output(&self) -> Result<i32, Error>;
+    fn input_buffer_count(&self) -> Result<i32, Error>;
+    /// Puts the line discipline into raw mode (no echo, no canonicalization, no signal
+    /// generation), mirroring `cfmakeraw`.
+    fn set_raw(&self) -> Result<i32, Error>;
+    fn set_parity(&self, parity: Parity) -> Result<i32, Error>;
+    fn set_stop_bits(&self, stop_bits: StopBits) -> Result<i32, Error>;
+    fn set_char_size(&self, char_size: CharSize) -> Result<i32, Error>;
+    fn set_flow_control(&self, flow_control: FlowControl) -> Result<i32, Error>;
+    /// Sends a BREAK condition, e.g. to reset a stuck microcontroller on the other end of the
+    /// link. `duration_ms` isn't a true millisecond count: a `0` sends the standard 0.25-0.5s
+    /// break, anything else is an implementation-defined "about that many times longer".
+    fn send_break(&self, duration_ms: i32) -> Result<i32, Error>;
+    fn set_flow(&self, action: FlowAction) -> Result<i32, Error>;
     fn errno(&self) -> i32;
 }
 impl<T> Termio for T where T: AsRawFd {
-    fn set_speed(&self, speed: Speed) -> Result<i32, i32> {
+    fn set_speed(&self, speed: Speed) -> Result<i32, Error> {
         let fd = self.as_raw_fd();
-        let mut config = CTermios::new();
-        if unsafe { tcgetattr(fd, &mut config) } < 0 {
-            return Err(self.errno());
+        let mut config = read_config(fd)?;
+        // Not every platform has `cfsetspeed` (it's a glibc/BSD convenience on top of the two
+        // calls below), so set both directions explicitly instead.
+        let result = unsafe { libc::cfsetispeed(&mut config, speed as libc::speed_t) };
+        if result < 0 {
+            let errno = self.errno();
+            return Err(Error::from_raw_os_error(errno));
         }
-        if unsafe { cfsetspeed(&mut config, speed as u32) } < 0 {
-            return Err(self.errno());
+        let result = unsafe { libc::cfsetospeed(&mut config, speed as libc::speed_t) };
+        if result < 0 {
+            let errno = self.errno();
+            return Err(Error::from_raw_os_error(errno));
         }
-        if unsafe { tcsetattr(fd, TcSetattrOptions::TCSANOW as i32, &mut config) } < 0 {
-            return Err(self.errno());
-        }
-        Ok(0)
+        write_config(fd, &mut config)
     }
 
-    fn get_speed(&self) -> Result<Speed, i32> {
+    fn get_speed(&self) -> Result<Speed, Error> {
         let fd = self.as_raw_fd();
-        let mut config = CTermios::new();
-        if unsafe { tcgetattr(fd, transmute(&mut config)) } < 0 {
-            return Err(self.errno());
-        }
-        let getospeed = unsafe {
-            cfgetospeed(&mut config)
-        };
-        match Speed::from_u32(getospeed) {
+        let config = read_config(fd)?;
+        let raw_speed = unsafe { libc::cfgetospeed(&config) };
+        match Speed::from_u32(raw_speed as u32) {
             Some(speed) => Ok(speed),
-            None => Err(1) // TODO: I'm not too sure what to do here
+            None => Err(Error::new(ErrorKind::Other, "Unrecognized speed_t value")),
         }
     }
 
-    fn drain(&self) -> Result<i32, i32> {
+    fn drain(&self) -> Result<i32, Error> {
         let fd = self.as_raw_fd();
-        if unsafe { tcdrain(fd) } < 0 {
-            Err(self.errno())
+        let result = unsafe { libc::tcdrain(fd) };
+        if result < 0 {
+            let errno = self.errno();
+            Err(Error::from_raw_os_error(errno))
         } else {
             Ok(0)
         }
     }
 
-    fn drop_input(&self) -> Result<i32, i32> {
+    fn drop_input(&self) -> Result<i32, Error> {
         let fd = self.as_raw_fd();
-        if unsafe { tcflush(fd, TcFlushOptions::TCIFLUSH as i32) } < 0 {
-            Err(self.errno())
+        let result = unsafe { libc::tcflush(fd, libc::TCIFLUSH) };
+        if result < 0 {
+            let errno = self.errno();
+            Err(Error::from_raw_os_error(errno))
         } else {
             Ok(0)
         }
     }
 
-    fn drop_output(&self) -> Result<i32, i32> {
+    fn drop_output(&self) -> Result<i32, Error> {
         let fd = self.as_raw_fd();
-        if unsafe { tcflush(fd, TcFlushOptions::TCOFLUSH as i32) } < 0 {
-            Err(self.errno())
+        let result = unsafe { libc::tcflush(fd, libc::TCOFLUSH) };
+        if result < 0 {
+            let errno = self.errno();
+            Err(Error::from_raw_os_error(errno))
         } else {
             Ok(0)
         }
     }
 
-    fn drop_input_output(&self) -> Result<i32, i32> {
+    fn drop_input_output(&self) -> Result<i32, Error> {
         let fd = self.as_raw_fd();
-        if unsafe { tcflush(fd, TcFlushOptions::TCIOFLUSH as i32) } < 0 {
-            Err(self.errno())
+        let result = unsafe { libc::tcflush(fd, libc::TCIOFLUSH) };
+        if result < 0 {
+            let errno = self.errno();
+            Err(Error::from_raw_os_error(errno))
         } else {
             Ok(0)
         }
     }
 
-    #[allow(unused_mut)]
-    fn input_buffer_count(&self) -> Result<i32, i32> {
+    fn input_buffer_count(&self) -> Result<i32, Error> {
+        let fd = self.as_raw_fd();
+        let mut size: libc::c_int = 0;
+        let result = unsafe { libc::ioctl(fd, libc::FIONREAD, &mut size) };
+        if result < 0 {
+            let errno = self.errno();
+            Err(Error::from_raw_os_error(errno))
+        } else {
+            Ok(size)
+        }
+    }
+
+    fn set_raw(&self) -> Result<i32, Error> {
+        let fd = self.as_raw_fd();
+        let mut config = read_config(fd)?;
+        unsafe { libc::cfmakeraw(&mut config) };
+        write_config(fd, &mut config)
+    }
+
+    fn set_parity(&self, parity: Parity) -> Result<i32, Error> {
+        let fd = self.as_raw_fd();
+        let mut config = read_config(fd)?;
+        config.c_cflag &= !(libc::PARENB | libc::PARODD);
+        match parity {
+            Parity::None => (),
+            Parity::Even => config.c_cflag |= libc::PARENB,
+            Parity::Odd => config.c_cflag |= libc::PARENB | libc::PARODD,
+        }
+        write_config(fd, &mut config)
+    }
+
+    fn set_stop_bits(&self, stop_bits: StopBits) -> Result<i32, Error> {
         let fd = self.as_raw_fd();
-        let buffer_size = unsafe {
-            // I don't know if this mut annotation is necessary with transmute; will the compiler
-            // optimize the value out?
-            let mut size: i32 = 0;
-            let result = ioctl(fd, IoCtlOptions::FIONREAD as i32, transmute(&size));
-            if result < 0 {
-                result
-            } else {
-                size
-            }
+        let mut config = read_config(fd)?;
+        match stop_bits {
+            StopBits::One => config.c_cflag &= !(libc::CSTOPB),
+            StopBits::Two => config.c_cflag |= libc::CSTOPB,
+        }
+        write_config(fd, &mut config)
+    }
+
+    fn set_char_size(&self, char_size: CharSize) -> Result<i32, Error> {
+        let fd = self.as_raw_fd();
+        let mut config = read_config(fd)?;
+        config.c_cflag &= !(libc::CSIZE);
+        config.c_cflag |= match char_size {
+            CharSize::Five => libc::CS5,
+            CharSize::Six => libc::CS6,
+            CharSize::Seven => libc::CS7,
+            CharSize::Eight => libc::CS8,
         };
-        if buffer_size < 0 {
-            Err(self.errno())
+        write_config(fd, &mut config)
+    }
+
+    fn set_flow_control(&self, flow_control: FlowControl) -> Result<i32, Error> {
+        let fd = self.as_raw_fd();
+        let mut config = read_config(fd)?;
+        config.c_iflag &= !(libc::IXON | libc::IXOFF);
+        config.c_cflag &= !(libc::CRTSCTS);
+        match flow_control {
+            FlowControl::None => (),
+            FlowControl::Software => config.c_iflag |= libc::IXON | libc::IXOFF,
+            FlowControl::Hardware => config.c_cflag |= libc::CRTSCTS,
+        }
+        write_config(fd, &mut config)
+    }
+
+    fn send_break(&self, duration_ms: i32) -> Result<i32, Error> {
+        let fd = self.as_raw_fd();
+        let result = unsafe { libc::tcsendbreak(fd, duration_ms as libc::c_int) };
+        if result < 0 {
+            let errno = self.errno();
+            Err(Error::from_raw_os_error(errno))
         } else {
-            Ok(buffer_size)
+            Ok(0)
         }
     }
 
+    fn set_flow(&self, action: FlowAction) -> Result<i32, Error> {
+        let fd = self.as_raw_fd();
+        let result = unsafe { libc::tcflow(fd, action as libc::c_int) };
+        if result < 0 {
+            let errno = self.errno();
+            Err(Error::from_raw_os_error(errno))
+        } else {
+            Ok(0)
+        }
+    }
+
+    // Reads the errno left by the most recently failed libc call. Callers must capture this
+    // immediately after that call's return value and before anything else that might make a
+    // libc call of its own (including, on some platforms, allocation), since errno is a single
+    // thread-local cell any such call could clobber.
     fn errno(&self) -> i32 {
-        // TODO: Get the errno
-        0
+        raw_errno()
     }
 }
 
+#[cfg(target_os = "linux")]
+fn raw_errno() -> i32 {
+    unsafe { *libc::__errno_location() }
+}
 
-#[allow(non_camel_case_types)]
-type cc_t = u8;
-#[allow(non_camel_case_types)]
-type tcflag_t = u32;
-#[allow(non_camel_case_types)]
-type speed_t = u32;
-
-#[repr(C)]
-struct CTermios
-{
-    c_iflag: tcflag_t,  // input mode flags
-    c_oflag: tcflag_t,  // output mode flags
-    c_cflag: tcflag_t,  // control mode flags
-    c_lflag: tcflag_t,  // local mode flags
-    c_line: cc_t,       // line discipline
-    c_cc: [cc_t; 32],   // control characters
-    c_ispeed: speed_t,  // input speed
-    c_ospeed: speed_t,  // output speed
+#[cfg(not(target_os = "linux"))]
+fn raw_errno() -> i32 {
+    unsafe { *libc::__error() }
 }
-impl CTermios {
-    fn new() -> CTermios {
-        CTermios {
-            c_iflag: 0,
-            c_oflag: 0,
-            c_cflag: 0,
-            c_lflag: 0,
-            c_line: 0,
-            c_cc: [0u8; 32],   // control characters
-            c_ispeed: 0,  // input speed
-            c_ospeed: 0,  // output speed
+
+/// Fetches the current line discipline configuration for `fd`, the common first step of every
+/// `tcgetattr` -> mutate -> `tcsetattr` round trip above.
+fn read_config(fd: i32) -> Result<libc::termios, Error> {
+    let mut config: libc::termios = unsafe { std::mem::zeroed() };
+    let result = unsafe { libc::tcgetattr(fd, &mut config) };
+    if result < 0 {
+        let errno = raw_errno();
+        return Err(Error::from_raw_os_error(errno));
+    }
+    Ok(config)
+}
+
+/// Applies a mutated line discipline configuration to `fd` immediately (`TCSANOW`), the common
+/// last step of every `tcgetattr` -> mutate -> `tcsetattr` round trip above.
+fn write_config(fd: i32, config: &mut libc::termios) -> Result<i32, Error> {
+    let result = unsafe { libc::tcsetattr(fd, libc::TCSANOW, config) };
+    if result < 0 {
+        let errno = raw_errno();
+        return Err(Error::from_raw_os_error(errno));
+    }
+    Ok(0)
+}
+
+/// An owned end of a pseudo-terminal pair opened by `openpty()`. Implements `AsRawFd`, so it
+/// gets the whole `Termio` trait for free via the blanket impl above, and closes its fd on drop
+/// so a test case can't leak descriptors into the next one.
+pub struct PtyFd(RawFd);
+
+impl AsRawFd for PtyFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+impl Drop for PtyFd {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+/// Opens a pseudo-terminal pair so serial-handling code can be exercised in tests without real
+/// hardware attached: bytes written to the master's fd show up as input on the slave's and vice
+/// versa. `speed` and `raw`, when given, are applied to the slave's line discipline (mirroring
+/// how the real serial port gets configured after `open()`) before the pair is returned.
+pub fn openpty(speed: Option<Speed>, raw: bool) -> Result<(PtyFd, PtyFd), Error> {
+    let mut master_fd: libc::c_int = 0;
+    let mut slave_fd: libc::c_int = 0;
+    let result = unsafe {
+        libc::openpty(
+            &mut master_fd,
+            &mut slave_fd,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+        )
+    };
+    if result < 0 {
+        let errno = raw_errno();
+        return Err(Error::from_raw_os_error(errno));
+    }
+
+    // Grant and unlock the slave so reads/writes against it don't fail with EACCES; `openpty`
+    // does this itself on most platforms, but it costs nothing to be sure.
+    if unsafe { libc::grantpt(master_fd) } < 0 {
+        let errno = raw_errno();
+        unsafe {
+            libc::close(master_fd);
+            libc::close(slave_fd);
+        }
+        return Err(Error::from_raw_os_error(errno));
+    }
+    if unsafe { libc::unlockpt(master_fd) } < 0 {
+        let errno = raw_errno();
+        unsafe {
+            libc::close(master_fd);
+            libc::close(slave_fd);
         }
+        return Err(Error::from_raw_os_error(errno));
+    }
+
+    let master = PtyFd(master_fd);
+    let slave = PtyFd(slave_fd);
+    if let Some(speed) = speed {
+        slave.set_speed(speed)?;
     }
+    if raw {
+        slave.set_raw()?;
+    }
+    Ok((master, slave))
 }
 
-#[allow(dead_code)]
-pub enum ControlCharacters {
-    VINTR = 0,
-    VQUIT = 1,
-    VERASE = 2,
-    VKILL = 3,
-    VEOF = 4,
-    VTIME = 5,
-    VMIN = 6,
-    VSWTC = 7,
-    VSTART = 8,
-    VSTOP = 9,
-    VSUSP = 10,
-    VEOL = 11,
-    VREPRINT = 12,
-    VDISCARD = 13,
-    VWERASE = 14,
-    VLNEXT = 15,
-    VEOL2 = 16,
+// Typed wrappers around the flag bits below, so callers can't accidentally mix, say, an iflag
+// bit into `c_cflag`.
+#[derive(Clone, Copy)]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
 }
 
-#[allow(dead_code)]
-pub enum IflagBits {
-    IGNBRK = 0000001,
-    BRKINT = 0000002,
-    IGNPAR = 0000004,
-    PARMRK = 0000010,
-    INPCK = 0000020,
-    ISTRIP = 0000040,
-    INLCR = 0000100,
-    IGNCR = 0000200,
-    ICRNL = 0000400,
-    IUCLC = 0001000,
-    IXON = 0002000,
-    IXANY = 0004000,
-    IXOFF = 0010000,
-    IMAXBEL = 0020000,
-    IUTF8 = 0040000,
+#[derive(Clone, Copy)]
+pub enum StopBits {
+    One,
+    Two,
 }
 
-#[allow(dead_code)]
-pub enum OflagBits {
-    OPOST = 0000001,
-    OLCUC = 0000002,
-    ONLCR = 0000004,
-    OCRNL = 0000010,
-    ONOCR = 0000020,
-    ONLRET = 0000040,
-    OFILL = 0000100,
-    OFDEL = 0000200,
-    VTDLY = 0040000,
-    //VT0 = 0000000,
-    //VT1 = 0040000,
+#[derive(Clone, Copy)]
+pub enum CharSize {
+    Five,
+    Six,
+    Seven,
+    Eight,
 }
 
+#[derive(Clone, Copy)]
+pub enum FlowControl {
+    None,
+    Software,
+    Hardware,
+}
+
+/// Actions for `tcflow`, used to assert/deassert XON/XOFF on a link to a sensor that doesn't
+/// react well to being flooded, rather than only being able to flush buffers outright.
+#[derive(Clone, Copy)]
+pub enum FlowAction {
+    TCOOFF = 0,
+    TCOON = 1,
+    TCIOFF = 2,
+    TCION = 3,
+}
+
+// Linux's baud constants are *indices* into a lookup table, not literal bit rates, and it defines
+// several high-speed rates macOS/BSD don't have.
+#[cfg(target_os = "linux")]
 #[allow(dead_code)]
 enum_from_primitive! {
 pub enum Speed {
@@ -243,63 +392,59 @@ pub enum Speed {
 }
 }
 
+// macOS/BSD's baud constants are the literal bit rate.
+#[cfg(not(target_os = "linux"))]
 #[allow(dead_code)]
-pub enum CflagBits {
-    CS5 = 0000000,
-    CS6 = 0000020,
-    CS7 = 0000040,
-    CS8 = 0000060,
-    CSTOPB = 0000100,
-    CREAD = 0000200,
-    PARENB = 0000400,
-    PARODD = 0001000,
-    HUPCL = 0002000,
-    CLOCAL = 0004000,
+enum_from_primitive! {
+pub enum Speed {
+    B0 = 0,
+    B50 = 50,
+    B75 = 75,
+    B110 = 110,
+    B134 = 134,
+    B150 = 150,
+    B200 = 200,
+    B300 = 300,
+    B600 = 600,
+    B1200 = 1200,
+    B1800 = 1800,
+    B2400 = 2400,
+    B4800 = 4800,
+    B9600 = 9600,
+    B19200 = 19200,
+    B38400 = 38400,
+    B57600 = 57600,
+    B115200 = 115200,
+    B230400 = 230400,
 }
-
-#[allow(dead_code)]
-enum LflagBits {
-    ISIG = 0000001,
-    ICANON = 0000002,
-    ECHO = 0000010,
-    ECHOE = 0000020,
-    ECHOK = 0000040,
-    ECHONL = 0000100,
-    NOFLSH = 0000200,
-    TOSTOP = 0000400,
 }
 
-#[allow(dead_code)]
-enum TcSetattrOptions {
-    TCSANOW = 0,
-    TCSADRAIN = 1,
-    TCSAFLUSH = 2,
-}
+#[cfg(test)]
+mod tests {
+    use super::{openpty, Speed, Termio};
 
-#[allow(dead_code)]
-enum TcFlushOptions {
-    TCIFLUSH = 0,
-    TCOFLUSH = 1,
-    TCIOFLUSH = 2,
-}
+    #[test]
+    fn master_writes_are_readable_on_slave() {
+        let (master, slave) = openpty(Some(Speed::B9600), true).unwrap();
+        assert_eq!(slave.input_buffer_count().unwrap(), 0);
 
-enum IoCtlOptions {
-    FIONREAD = 21531,
-}
+        let written = unsafe {
+            let fd = ::std::os::unix::prelude::AsRawFd::as_raw_fd(&master);
+            ::libc::write(fd, b"$GPGGA\r\n".as_ptr() as *const ::libc::c_void, 8)
+        };
+        assert_eq!(written, 8);
+        assert_eq!(slave.input_buffer_count().unwrap(), 8);
 
-#[allow(dead_code)]
-extern {
-    fn tcgetattr(fd: i32, termios_p: *mut CTermios) -> i32;
-    fn tcsetattr(fd: i32, optional_actions: i32, termios_p: *mut CTermios) -> i32;
-    fn tcsendbreak(fd: i32, duration: i32) -> i32;
-    fn tcdrain(fd: i32) -> i32;
-    fn tcflush(fd: i32, queue_selector: i32) -> i32;
-    fn tcflow(fd: i32, action: i32) -> i32;
-    fn cfmakeraw(termios_p: *mut CTermios) -> ();
-    fn cfgetispeed(termios_p: *mut CTermios) -> speed_t;
-    fn cfgetospeed(termios_p: *mut CTermios) -> speed_t;
-    fn cfsetispeed(termios_p: *mut CTermios, speed: speed_t) -> i32;
-    fn cfsetospeed(termios_p: *mut CTermios, speed: speed_t) -> i32;
-    fn cfsetspeed(termios_p: *mut CTermios, speed: speed_t) -> i32;
-    fn ioctl(fd: i32, request: i32, value: *mut i32) -> i32;
+        slave.drop_input().unwrap();
+        assert_eq!(slave.input_buffer_count().unwrap(), 0);
+    }
+
+    #[test]
+    fn set_speed_round_trips_on_the_slave() {
+        let (_master, slave) = openpty(Some(Speed::B4800), false).unwrap();
+        assert_eq!(slave.get_speed().unwrap() as u32, Speed::B4800 as u32);
+
+        slave.set_speed(Speed::B9600).unwrap();
+        assert_eq!(slave.get_speed().unwrap() as u32, Speed::B9600 as u32);
+    }
 }
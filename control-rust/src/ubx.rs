@@ -0,0 +1,277 @@
+/// Streaming parser for u-blox's UBX binary GPS protocol, as used by the PX4 `ubx.cpp`/`ubx.h`
+/// driver: recognizes the 0xB5 0x62 sync chars, a little-endian class/id/length header, and the
+/// two-byte 8-bit Fletcher checksum, then decodes NAV-PVT frames into `GpsMessage`s.
+use telemetry::{latitude_longitude_to_point, wrap_degrees};
+use telemetry_message::GpsMessage;
+
+const SYNC_CHAR_1: u8 = 0xB5;
+const SYNC_CHAR_2: u8 = 0x62;
+const CLASS_NAV: u8 = 0x01;
+const ID_NAV_PVT: u8 = 0x07;
+const NAV_PVT_PAYLOAD_LEN: usize = 92;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum State {
+    Sync1,
+    Sync2,
+    Class,
+    Id,
+    Length1,
+    Length2,
+    Payload,
+    Checksum1,
+    Checksum2,
+}
+
+/// A resumable, byte-at-a-time UBX frame parser. It's tolerant of partial reads (just feed it
+/// whatever bytes arrived) and of garbage in the stream (a bad sync or checksum just resets it
+/// to looking for the next 0xB5 0x62, rather than giving up).
+pub struct UbxParser {
+    state: State,
+    class: u8,
+    id: u8,
+    length: u16,
+    payload: Vec<u8>,
+    running_checksum_a: u8,
+    running_checksum_b: u8,
+    frame_checksum_a: u8,
+}
+
+impl UbxParser {
+    pub fn new() -> UbxParser {
+        UbxParser {
+            state: State::Sync1,
+            class: 0,
+            id: 0,
+            length: 0,
+            payload: Vec::new(),
+            running_checksum_a: 0,
+            running_checksum_b: 0,
+            frame_checksum_a: 0,
+        }
+    }
+
+    /// Feeds a single byte into the parser. Returns a `GpsMessage` once `byte` completes a
+    /// checksum-valid NAV-PVT frame; otherwise returns `None`, whether because the frame isn't
+    /// finished yet, it was some other UBX message, or it failed the checksum.
+    pub fn consume(&mut self, byte: u8) -> Option<GpsMessage> {
+        match self.state {
+            State::Sync1 => {
+                if byte == SYNC_CHAR_1 {
+                    self.state = State::Sync2;
+                }
+            }
+            State::Sync2 => {
+                self.state = if byte == SYNC_CHAR_2 {
+                    State::Class
+                } else {
+                    State::Sync1
+                };
+            }
+            State::Class => {
+                self.class = byte;
+                self.running_checksum_a = 0;
+                self.running_checksum_b = 0;
+                self.update_checksum(byte);
+                self.state = State::Id;
+            }
+            State::Id => {
+                self.id = byte;
+                self.update_checksum(byte);
+                self.state = State::Length1;
+            }
+            State::Length1 => {
+                self.length = byte as u16;
+                self.update_checksum(byte);
+                self.state = State::Length2;
+            }
+            State::Length2 => {
+                self.length |= (byte as u16) << 8;
+                self.update_checksum(byte);
+                self.payload.clear();
+                self.state = if self.length == 0 {
+                    State::Checksum1
+                } else {
+                    State::Payload
+                };
+            }
+            State::Payload => {
+                self.payload.push(byte);
+                self.update_checksum(byte);
+                if self.payload.len() == self.length as usize {
+                    self.state = State::Checksum1;
+                }
+            }
+            State::Checksum1 => {
+                self.frame_checksum_a = byte;
+                self.state = State::Checksum2;
+            }
+            State::Checksum2 => {
+                self.state = State::Sync1;
+                if self.frame_checksum_a == self.running_checksum_a
+                    && byte == self.running_checksum_b
+                {
+                    return self.decode_nav_pvt();
+                }
+            }
+        }
+        None
+    }
+
+    /// Folds `byte` into the running 8-bit Fletcher checksum over the class, id, length, and
+    /// payload bytes.
+    fn update_checksum(&mut self, byte: u8) {
+        self.running_checksum_a = self.running_checksum_a.wrapping_add(byte);
+        self.running_checksum_b = self.running_checksum_b.wrapping_add(self.running_checksum_a);
+    }
+
+    /// Decodes the just-completed frame as a NAV-PVT solution, or returns `None` if it's a
+    /// different message class/id/length.
+    fn decode_nav_pvt(&self) -> Option<GpsMessage> {
+        if self.class != CLASS_NAV
+            || self.id != ID_NAV_PVT
+            || self.payload.len() != NAV_PVT_PAYLOAD_LEN
+        {
+            return None;
+        }
+
+        let longitude_degrees = read_i32(&self.payload[24..28]) as f64 * 1e-7;
+        let latitude_degrees = read_i32(&self.payload[28..32]) as f64 * 1e-7;
+        let horizontal_accuracy_mm = read_u32(&self.payload[40..44]);
+        let ground_speed_mm_s = read_i32(&self.payload[60..64]);
+        let heading_of_motion_1e5_degrees = read_i32(&self.payload[64..68]);
+
+        Some(GpsMessage {
+            point: latitude_longitude_to_point(latitude_degrees, longitude_degrees),
+            heading: wrap_degrees(heading_of_motion_1e5_degrees as f32 * 1e-5),
+            speed: ground_speed_mm_s as f32 / 1000.0,
+            // std_dev_x/std_dev_y are both horizontal-position std-devs (paired with point.x/y),
+            // so both come from hAcc; NAV-PVT's vAcc is altitude accuracy and doesn't belong here.
+            std_dev_x: horizontal_accuracy_mm as f32 / 1000.0,
+            std_dev_y: horizontal_accuracy_mm as f32 / 1000.0,
+        })
+    }
+}
+
+/// Reads 4 little-endian bytes as a signed 32-bit integer.
+fn read_i32(bytes: &[u8]) -> i32 {
+    read_u32(bytes) as i32
+}
+
+/// Reads 4 little-endian bytes as an unsigned 32-bit integer.
+fn read_u32(bytes: &[u8]) -> u32 {
+    (bytes[0] as u32)
+        | ((bytes[1] as u32) << 8)
+        | ((bytes[2] as u32) << 16)
+        | ((bytes[3] as u32) << 24)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::UbxParser;
+
+    /// Builds a full NAV-PVT frame (header, fixed payload, trailing Fletcher checksum) around the
+    /// given little-endian field bytes, with every other payload byte set to zero.
+    fn nav_pvt_frame(
+        longitude_1e7: i32,
+        latitude_1e7: i32,
+        horizontal_accuracy_mm: u32,
+        vertical_accuracy_mm: u32,
+        ground_speed_mm_s: i32,
+        heading_1e5: i32,
+    ) -> Vec<u8> {
+        let mut payload = vec![0u8; 92];
+        payload[24..28].clone_from_slice(&to_le_bytes(longitude_1e7 as u32));
+        payload[28..32].clone_from_slice(&to_le_bytes(latitude_1e7 as u32));
+        payload[40..44].clone_from_slice(&to_le_bytes(horizontal_accuracy_mm));
+        payload[44..48].clone_from_slice(&to_le_bytes(vertical_accuracy_mm));
+        payload[60..64].clone_from_slice(&to_le_bytes(ground_speed_mm_s as u32));
+        payload[64..68].clone_from_slice(&to_le_bytes(heading_1e5 as u32));
+
+        let mut frame = vec![0xB5, 0x62, 0x01, 0x07];
+        frame.push((payload.len() & 0xff) as u8);
+        frame.push(((payload.len() >> 8) & 0xff) as u8);
+        frame.extend(payload);
+
+        let mut checksum_a = 0u8;
+        let mut checksum_b = 0u8;
+        for &byte in &frame[2..] {
+            checksum_a = checksum_a.wrapping_add(byte);
+            checksum_b = checksum_b.wrapping_add(checksum_a);
+        }
+        frame.push(checksum_a);
+        frame.push(checksum_b);
+        frame
+    }
+
+    fn to_le_bytes(value: u32) -> [u8; 4] {
+        [
+            (value & 0xff) as u8,
+            ((value >> 8) & 0xff) as u8,
+            ((value >> 16) & 0xff) as u8,
+            ((value >> 24) & 0xff) as u8,
+        ]
+    }
+
+    #[test]
+    fn test_parses_nav_pvt_frame() {
+        let frame = nav_pvt_frame(-1051872092, 400941804, 1500, 2500, 1800, 3200000);
+        let mut parser = UbxParser::new();
+        let mut gps_message = None;
+        for &byte in &frame {
+            if let Some(message) = parser.consume(byte) {
+                gps_message = Some(message);
+            }
+        }
+        let gps_message = gps_message.expect("Expected a GpsMessage from a complete frame");
+        assert!((gps_message.speed - 1.8).abs() < 0.0001);
+        assert!((gps_message.heading - 32.0).abs() < 0.0001);
+        assert!((gps_message.std_dev_x - 1.5).abs() < 0.0001);
+        assert!((gps_message.std_dev_y - 1.5).abs() < 0.0001);
+    }
+
+    #[test]
+    fn test_tolerates_garbage_before_sync() {
+        let frame = nav_pvt_frame(-1051872092, 400941804, 1500, 2500, 1800, 3200000);
+        let mut parser = UbxParser::new();
+        let mut gps_message = None;
+        for &byte in [0xff, 0x00, 0xB5, 0x00].iter().chain(frame.iter()) {
+            if let Some(message) = parser.consume(byte) {
+                gps_message = Some(message);
+            }
+        }
+        assert!(gps_message.is_some());
+    }
+
+    #[test]
+    fn test_rejects_bad_checksum() {
+        let mut frame = nav_pvt_frame(-1051872092, 400941804, 1500, 2500, 1800, 3200000);
+        let last = frame.len() - 1;
+        frame[last] ^= 0xff;
+        let mut parser = UbxParser::new();
+        let mut gps_message = None;
+        for &byte in &frame {
+            if let Some(message) = parser.consume(byte) {
+                gps_message = Some(message);
+            }
+        }
+        assert!(gps_message.is_none());
+    }
+
+    #[test]
+    fn test_resumes_across_partial_feeds() {
+        let frame = nav_pvt_frame(-1051872092, 400941804, 1500, 2500, 1800, 3200000);
+        let mut parser = UbxParser::new();
+        let mut gps_message = None;
+        // Feed the frame split across several chunks, as a real serial read would deliver it.
+        for chunk in frame.chunks(3) {
+            for &byte in chunk {
+                if let Some(message) = parser.consume(byte) {
+                    gps_message = Some(message);
+                }
+            }
+        }
+        assert!(gps_message.is_some());
+    }
+}
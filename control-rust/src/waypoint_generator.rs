@@ -35,4 +35,9 @@ pub trait WaypointGenerator {
      * Returns the distance required to consider a waypoint as reached.
      */
     fn reach_distance(&self) -> Meter;
+
+    /**
+     * Starts the course over from the first waypoint.
+     */
+    fn reset(&mut self);
 }
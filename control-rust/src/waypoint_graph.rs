@@ -0,0 +1,226 @@
+/**
+ * A directed graph over waypoints, borrowed from the waypoint-linking model used by Xonotic's
+ * bot navigation: each waypoint is a node, and nodes carry links to the other nodes reachable
+ * from them. Letting the course branch instead of being one linear order means a route can be
+ * recomputed with A* if a waypoint becomes unreachable.
+ */
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use telemetry::{Meter, Point, distance};
+
+
+pub struct WaypointGraph {
+    nodes: Vec<Point>,
+    links: Vec<Vec<usize>>,
+}
+
+impl WaypointGraph {
+    /**
+     * Builds a graph over `nodes`, auto-linking every pair of nodes within `radius_m` of each
+     * other.
+     */
+    pub fn new(nodes: Vec<Point>, radius_m: Meter) -> WaypointGraph {
+        let mut graph = WaypointGraph::with_no_links(nodes);
+        for i in 0..graph.len() {
+            for j in 0..graph.len() {
+                if i != j && distance(&graph.nodes[i], &graph.nodes[j]) <= radius_m {
+                    graph.add_link(i, j);
+                }
+            }
+        }
+        graph
+    }
+
+    /**
+     * Builds a graph over `nodes` with no links; callers add them individually, e.g. after
+     * loading a saved link file.
+     */
+    pub fn with_no_links(nodes: Vec<Point>) -> WaypointGraph {
+        let link_count = nodes.len();
+        WaypointGraph {
+            nodes: nodes,
+            links: vec![Vec::new(); link_count],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn node(&self, index: usize) -> Point {
+        self.nodes[index]
+    }
+
+    /**
+     * Adds a directed link from node `from` to node `to`.
+     */
+    pub fn add_link(&mut self, from: usize, to: usize) {
+        self.links[from].push(to);
+    }
+
+    /**
+     * Returns the nodes that `from` links directly to.
+     */
+    pub fn links_from(&self, from: usize) -> &[usize] {
+        &self.links[from]
+    }
+
+    /**
+     * Returns the index of the node nearest to `point`.
+     */
+    pub fn nearest_node(&self, point: &Point) -> usize {
+        let mut nearest_index = 0;
+        let mut nearest_distance_m = distance(&self.nodes[0], point);
+        for i in 1..self.nodes.len() {
+            let distance_m = distance(&self.nodes[i], point);
+            if distance_m < nearest_distance_m {
+                nearest_distance_m = distance_m;
+                nearest_index = i;
+            }
+        }
+        nearest_index
+    }
+
+    /**
+     * Routes from the node nearest `start` to `goal` via A*, using Euclidean `distance()` from
+     * `telemetry` as both edge cost and heuristic. Returns `None` if no linked path connects
+     * them.
+     */
+    pub fn route(&self, start: &Point, goal: usize) -> Option<Vec<Point>> {
+        self.route_between(self.nearest_node(start), goal)
+    }
+
+    fn route_between(&self, start: usize, goal: usize) -> Option<Vec<Point>> {
+        let mut open = BinaryHeap::new();
+        let mut came_from: HashMap<usize, usize> = HashMap::new();
+        let mut cost_so_far: HashMap<usize, Meter> = HashMap::new();
+
+        cost_so_far.insert(start, 0.0);
+        open.push(AstarNode { index: start, priority: -self.heuristic(start, goal) });
+
+        while let Some(AstarNode { index, .. }) = open.pop() {
+            if index == goal {
+                return Some(self.reconstruct_path(&came_from, start, goal));
+            }
+            let current_cost_m = cost_so_far[&index];
+            for &next in &self.links[index] {
+                let new_cost_m = current_cost_m + distance(&self.nodes[index], &self.nodes[next]);
+                if !cost_so_far.contains_key(&next) || new_cost_m < cost_so_far[&next] {
+                    cost_so_far.insert(next, new_cost_m);
+                    came_from.insert(next, index);
+                    open.push(AstarNode {
+                        index: next,
+                        priority: -(new_cost_m + self.heuristic(next, goal)),
+                    });
+                }
+            }
+        }
+        None
+    }
+
+    fn heuristic(&self, index: usize, goal: usize) -> Meter {
+        distance(&self.nodes[index], &self.nodes[goal])
+    }
+
+    fn reconstruct_path(
+        &self,
+        came_from: &HashMap<usize, usize>,
+        start: usize,
+        goal: usize,
+    ) -> Vec<Point> {
+        let mut indices = vec![goal];
+        let mut current = goal;
+        while current != start {
+            current = came_from[&current];
+            indices.push(current);
+        }
+        indices.reverse();
+        indices.into_iter().map(|i| self.nodes[i]).collect()
+    }
+}
+
+
+/**
+ * A graph node queued for A* expansion. `priority` is the negated `cost_so_far + heuristic`,
+ * since `BinaryHeap` is a max-heap and the lowest-cost node should pop first.
+ */
+struct AstarNode {
+    index: usize,
+    priority: Meter,
+}
+
+impl PartialEq for AstarNode {
+    fn eq(&self, other: &AstarNode) -> bool {
+        self.priority == other.priority
+    }
+}
+
+impl Eq for AstarNode {}
+
+impl PartialOrd for AstarNode {
+    fn partial_cmp(&self, other: &AstarNode) -> Option<Ordering> {
+        self.priority.partial_cmp(&other.priority)
+    }
+}
+
+impl Ord for AstarNode {
+    fn cmp(&self, other: &AstarNode) -> Ordering {
+        self.partial_cmp(other).unwrap_or(Ordering::Equal)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::WaypointGraph;
+    use telemetry::Point;
+
+    #[test]
+    fn test_route_linear_chain() {
+        let nodes = vec![
+            Point { x: 0.0, y: 0.0 },
+            Point { x: 10.0, y: 0.0 },
+            Point { x: 20.0, y: 0.0 },
+        ];
+        let graph = WaypointGraph::new(nodes, 15.0);
+        let route = graph.route(&Point { x: 0.0, y: 0.0 }, 2).unwrap();
+        assert!(route.len() == 3);
+        assert!(route[0].x == 0.0);
+        assert!(route[1].x == 10.0);
+        assert!(route[2].x == 20.0);
+    }
+
+    #[test]
+    fn test_route_picks_shorter_branch() {
+        let nodes = vec![
+            Point { x: 0.0, y: 0.0 },    // 0: start
+            Point { x: 10.0, y: 10.0 }, // 1: long way round
+            Point { x: 10.0, y: -10.0 }, // 2: long way round
+            Point { x: 20.0, y: 0.0 },  // 3: goal
+            Point { x: 10.0, y: 0.0 },  // 4: short way
+        ];
+        let mut graph = WaypointGraph::with_no_links(nodes);
+        graph.add_link(0, 1);
+        graph.add_link(1, 3);
+        graph.add_link(0, 2);
+        graph.add_link(2, 3);
+        graph.add_link(0, 4);
+        graph.add_link(4, 3);
+
+        let route = graph.route(&Point { x: 0.0, y: 0.0 }, 3).unwrap();
+        assert!(route.len() == 3);
+        assert!(route[1].x == 10.0 && route[1].y == 0.0);
+    }
+
+    #[test]
+    fn test_route_no_path() {
+        let nodes = vec![
+            Point { x: 0.0, y: 0.0 },
+            Point { x: 1000.0, y: 1000.0 },
+        ];
+        let graph = WaypointGraph::new(nodes, 5.0);
+        assert!(graph.route(&Point { x: 0.0, y: 0.0 }, 1).is_none());
+    }
+}